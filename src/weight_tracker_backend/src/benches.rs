@@ -0,0 +1,90 @@
+//! Instruction-count benchmarks, run with `canbench` from this directory.
+//! The `*_scan` benchmarks reproduce the full scans the indexes replaced.
+use canbench_rs::{bench, bench_fn, BenchResult};
+use candid::Principal;
+use crate::memory::*;
+use crate::models::*;
+use crate::repositories::*;
+use crate::traits::*;
+
+const WEIGHTS: u64 = 100_000;
+const BATCHES: u64 = 100;
+const ITEMS: u64 = 10_000;
+
+#[derive(Clone)]
+struct FixedTime;
+
+impl TimeProvider for FixedTime {
+    fn get_time(&self) -> u64 {
+        WEIGHTS
+    }
+}
+
+fn populated_store() -> WeightStore {
+    let store = WeightStore::init(
+        get_memory(WEIGHTS_MEMORY_ID),
+        get_memory(WEIGHTS_BY_BATCH_MEMORY_ID),
+        get_memory(WEIGHTS_BY_CREATED_AT_MEMORY_ID),
+        get_memory(WEIGHTS_BY_ITEM_MEMORY_ID),
+        get_memory(WEIGHT_HISTORY_MEMORY_ID),
+        get_memory(CLIENT_REQUESTS_MEMORY_ID),
+        get_memory(WEIGHT_FLAGS_MEMORY_ID),
+    );
+    let storage = WeightStorageImpl::with_storage(store.clone(), FixedTime);
+    let owner = Principal::from_slice(&[1]);
+
+    for i in 0..WEIGHTS {
+        let key = WeightKey {
+            owner,
+            item_id: format!("item_{}", i % ITEMS),
+            created_at: i,
+        };
+        let weight = Weight {
+            owner,
+            batch_id: format!("batch_{}", i % BATCHES),
+            item_id: key.item_id.clone(),
+            weight: 50.0 + (i % 50) as f64,
+            created_at: i,
+            updated_at: i,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        };
+        storage.create(key, weight).unwrap();
+    }
+    store
+}
+
+#[bench(raw)]
+fn batch_weights_indexed() -> BenchResult {
+    let storage = WeightStorageImpl::with_storage(populated_store(), FixedTime);
+    bench_fn(|| storage.get_all(Some("batch_42".to_string()), false))
+}
+
+#[bench(raw)]
+fn batch_weights_scan() -> BenchResult {
+    let store = populated_store();
+    bench_fn(|| {
+        store.records.borrow()
+            .values()
+            .filter(|w| w.batch_id == "batch_42" && w.deleted_at.is_none())
+            .collect::<Vec<_>>()
+    })
+}
+
+#[bench(raw)]
+fn find_weight_indexed() -> BenchResult {
+    let storage = WeightStorageImpl::with_storage(populated_store(), FixedTime);
+    bench_fn(|| storage.find_at("item_4242", 54_242))
+}
+
+#[bench(raw)]
+fn find_weight_scan() -> BenchResult {
+    let storage = WeightStorageImpl::with_storage(populated_store(), FixedTime);
+    bench_fn(|| {
+        storage.get_all(None, true)
+            .into_iter()
+            .find(|w| w.item_id == "item_4242" && w.created_at == 54_242)
+    })
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, NaiveDate};
+
+/// One record of a CSV file together with the line it starts on.
+#[derive(Debug, PartialEq)]
+pub struct CsvRecord {
+    pub line: u64,
+    pub fields: Vec<String>,
+}
+
+/// Splits CSV text into records following RFC 4180: fields are separated by
+/// commas, may be quoted with `"` to contain commas, quotes (doubled) and
+/// line breaks, and records end with LF or CRLF. Blank lines are skipped.
+pub fn parse(bytes: &[u8]) -> Result<Vec<CsvRecord>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| format!("CSV is not valid UTF-8: {}", e))?;
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => fields.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                push_record(&mut records, record_line, std::mem::take(&mut fields));
+                line += 1;
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Quoted field starting on line {} is never closed", record_line));
+    }
+    fields.push(field);
+    push_record(&mut records, record_line, fields);
+    Ok(records)
+}
+
+fn push_record(records: &mut Vec<CsvRecord>, line: u64, fields: Vec<String>) {
+    let blank = fields.len() == 1 && fields[0].trim().is_empty();
+    if !blank {
+        records.push(CsvRecord { line, fields });
+    }
+}
+
+/// Smallest plain number taken as nanoseconds, 2001-09-09 UTC. Seconds,
+/// milliseconds and microseconds since the epoch, as spreadsheets export
+/// them, all fall below it and would otherwise import as 1970 dates.
+const MIN_PLAIN_NANOS: u64 = 1_000_000_000_000_000_000;
+
+/// Parses a timestamp into nanoseconds since the epoch. Accepts a plain
+/// number of nanoseconds, an RFC 3339 date and time or a `YYYY-MM-DD` date,
+/// which is taken as midnight UTC.
+pub fn parse_timestamp(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if let Ok(nanos) = value.parse::<u64>() {
+        if nanos < MIN_PLAIN_NANOS {
+            return Err(format!(
+                "'{}' is too small for nanoseconds since the epoch; give nanoseconds or a date",
+                value
+            ));
+        }
+        return Ok(nanos);
+    }
+    let nanos = if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        date_time.timestamp_nanos_opt()
+    } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0).and_then(|midnight| midnight.and_utc().timestamp_nanos_opt())
+    } else {
+        return Err(format!("'{}' is not a timestamp", value));
+    };
+    nanos
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .ok_or_else(|| format!("'{}' is out of range", value))
+}
@@ -0,0 +1,53 @@
+use candid::{CandidType, Deserialize};
+use std::fmt;
+
+/// Error returned by every fallible endpoint so that clients can branch on
+/// the kind of failure instead of parsing messages.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum WeightTrackerError {
+    /// The caller is the anonymous principal.
+    Unauthenticated,
+    Unauthorized(String),
+    NotFound(String),
+    Validation { field: String, reason: String },
+    AlreadyDeleted(String),
+    Conflict(String),
+}
+
+impl WeightTrackerError {
+    pub fn validation(field: &str, reason: &str) -> Self {
+        WeightTrackerError::Validation {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        WeightTrackerError::Unauthorized(message.to_string())
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        WeightTrackerError::NotFound(message.to_string())
+    }
+
+    pub fn already_deleted(message: &str) -> Self {
+        WeightTrackerError::AlreadyDeleted(message.to_string())
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        WeightTrackerError::Conflict(message.to_string())
+    }
+}
+
+impl fmt::Display for WeightTrackerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeightTrackerError::Unauthenticated => write!(f, "Please login with Internet Identity"),
+            WeightTrackerError::Unauthorized(message)
+            | WeightTrackerError::NotFound(message)
+            | WeightTrackerError::AlreadyDeleted(message)
+            | WeightTrackerError::Conflict(message) => write!(f, "{}", message),
+            WeightTrackerError::Validation { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+        }
+    }
+}
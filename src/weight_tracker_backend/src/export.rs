@@ -0,0 +1,175 @@
+use crate::models::{Batch, BatchStats, ExportFormat, Weight};
+use std::fmt::Write;
+
+const CSV_HEADER: &str = "batch_id,batch_name,batch_description,batch_owner,batch_created_at,batch_deleted_at,\
+stats_count,stats_min_weight,stats_max_weight,stats_average_weight,\
+item_id,weight,unit,created_at,updated_at,deleted_at,notes";
+
+/// Builds one chunk of an export document. Concatenating the chunks of an
+/// export in order gives the whole document.
+///
+/// CSV has one line per weight, repeating the batch's columns, and a line
+/// with empty weight columns for a batch without weights. JSON is a single
+/// `{"batches": [...]}` object with each batch's weights nested in it.
+pub struct ExportWriter {
+    format: ExportFormat,
+    out: String,
+}
+
+impl ExportWriter {
+    pub fn new(format: ExportFormat) -> Self {
+        Self { format, out: String::new() }
+    }
+
+    /// Opens the document; only the first chunk does this.
+    pub fn begin(&mut self) {
+        match self.format {
+            ExportFormat::Csv => self.out.push_str(CSV_HEADER),
+            ExportFormat::Json => self.out.push_str("{\"batches\":["),
+        }
+    }
+
+    /// `follows_batch` is whether an earlier batch was already written, and
+    /// `empty` whether the batch has no weights to follow.
+    pub fn start_batch(&mut self, batch: &Batch, stats: &BatchStats, follows_batch: bool, empty: bool) {
+        match self.format {
+            ExportFormat::Csv => {
+                if empty {
+                    self.out.push_str("\r\n");
+                    self.csv_batch_columns(batch, stats);
+                    self.out.push_str(",,,,,,,");
+                }
+            }
+            ExportFormat::Json => {
+                if follows_batch {
+                    self.out.push(',');
+                }
+                let _ = write!(
+                    self.out,
+                    "{{\"id\":{},\"name\":{},\"description\":{},\"owner\":{},\"unit\":{},\"created_at\":{},\"deleted_at\":{},\
+                     \"stats\":{{\"count\":{},\"min_weight\":{},\"max_weight\":{},\"average_weight\":{},\"unit\":{}}},\"weights\":[",
+                    json_string(&batch.id),
+                    json_string(&batch.name),
+                    json_optional_string(batch.description.as_deref()),
+                    json_string(&batch.owner.to_text()),
+                    json_string(&format!("{:?}", batch.unit)),
+                    batch.created_at,
+                    json_optional_number(batch.deleted_at),
+                    stats.count,
+                    stats.min_weight,
+                    stats.max_weight,
+                    stats.average_weight,
+                    json_string(&format!("{:?}", stats.unit)),
+                );
+            }
+        }
+    }
+
+    /// Writes a weight already converted to the export's unit.
+    pub fn weight(&mut self, batch: &Batch, stats: &BatchStats, weight: &Weight, follows_weight: bool) {
+        match self.format {
+            ExportFormat::Csv => {
+                self.out.push_str("\r\n");
+                self.csv_batch_columns(batch, stats);
+                let _ = write!(
+                    self.out,
+                    ",{},{},{:?},{},{},{},{}",
+                    csv_field(&weight.item_id),
+                    weight.weight,
+                    stats.unit,
+                    weight.created_at,
+                    weight.updated_at,
+                    weight.deleted_at.map(|at| at.to_string()).unwrap_or_default(),
+                    csv_field(weight.notes.as_deref().unwrap_or_default()),
+                );
+            }
+            ExportFormat::Json => {
+                if follows_weight {
+                    self.out.push(',');
+                }
+                let _ = write!(
+                    self.out,
+                    "{{\"item_id\":{},\"weight\":{},\"created_at\":{},\"updated_at\":{},\"deleted_at\":{},\"notes\":{}}}",
+                    json_string(&weight.item_id),
+                    weight.weight,
+                    weight.created_at,
+                    weight.updated_at,
+                    json_optional_number(weight.deleted_at),
+                    json_optional_string(weight.notes.as_deref()),
+                );
+            }
+        }
+    }
+
+    pub fn end_batch(&mut self) {
+        if let ExportFormat::Json = self.format {
+            self.out.push_str("]}");
+        }
+    }
+
+    /// Closes the document; only the last chunk does this.
+    pub fn end(&mut self) {
+        match self.format {
+            ExportFormat::Csv => self.out.push_str("\r\n"),
+            ExportFormat::Json => self.out.push_str("]}"),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.out.into_bytes()
+    }
+
+    fn csv_batch_columns(&mut self, batch: &Batch, stats: &BatchStats) {
+        let _ = write!(
+            self.out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&batch.id),
+            csv_field(&batch.name),
+            csv_field(batch.description.as_deref().unwrap_or_default()),
+            batch.owner,
+            batch.created_at,
+            batch.deleted_at.map(|at| at.to_string()).unwrap_or_default(),
+            stats.count,
+            stats.min_weight,
+            stats.max_weight,
+            stats.average_weight,
+        );
+    }
+}
+
+/// Quotes a field when it holds a separator, a quote or a line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_optional_string(value: Option<&str>) -> String {
+    value.map(json_string).unwrap_or_else(|| "null".to_string())
+}
+
+fn json_optional_number(value: Option<u64>) -> String {
+    value.map(|n| n.to_string()).unwrap_or_else(|| "null".to_string())
+}
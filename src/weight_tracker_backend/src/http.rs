@@ -0,0 +1,151 @@
+use candid::{CandidType, Deserialize};
+use crate::errors::WeightTrackerError;
+use crate::export::json_string;
+use crate::models::{ExportChunk, ExportFormat, ExportRequest, WeightUnit};
+
+pub type HeaderField = (String, String);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// The secret of an `Authorization: Bearer <token>` header.
+    pub fn bearer_token(&self) -> Option<&str> {
+        self.headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+            .and_then(|(_, value)| value.strip_prefix("Bearer "))
+            .map(str::trim)
+    }
+}
+
+impl HttpResponse {
+    pub fn export(format: ExportFormat, chunk: ExportChunk) -> Self {
+        let content_type = match format {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        };
+        let mut headers = vec![("Content-Type".to_string(), content_type.to_string())];
+        if let Some(cursor) = chunk.next_cursor {
+            headers.push(("X-Next-Cursor".to_string(), cursor));
+        }
+        Self { status_code: 200, headers, body: chunk.data }
+    }
+
+    pub fn error(status_code: u16, message: &str) -> Self {
+        let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+        if status_code == 401 {
+            headers.push(("WWW-Authenticate".to_string(), "Bearer".to_string()));
+        }
+        Self {
+            status_code,
+            headers,
+            body: format!("{{\"error\":{}}}", json_string(message)).into_bytes(),
+        }
+    }
+
+    pub fn from_error(error: &WeightTrackerError) -> Self {
+        let status_code = match error {
+            WeightTrackerError::Unauthenticated => 401,
+            WeightTrackerError::Unauthorized(_) => 403,
+            WeightTrackerError::NotFound(_) => 404,
+            WeightTrackerError::Validation { .. } => 400,
+            WeightTrackerError::AlreadyDeleted(_) => 410,
+            WeightTrackerError::Conflict(_) => 409,
+        };
+        Self::error(status_code, &error.to_string())
+    }
+}
+
+/// Maps a request to the export it asks for. The routes are
+/// `/batches.{csv,json}` for every readable batch and
+/// `/batches/{id}/weights.{csv,json}` for one batch, with the optional query
+/// parameters `unit`, `include_deleted`, `chunk_size` and `cursor`.
+pub fn export_request(request: &HttpRequest) -> Result<ExportRequest, HttpResponse> {
+    if !request.method.eq_ignore_ascii_case("GET") {
+        return Err(HttpResponse::error(405, "Only GET is supported"));
+    }
+
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+    let segments: Vec<String> = path.trim_start_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Option<_>>()
+        .ok_or_else(|| HttpResponse::error(400, "Malformed URL"))?;
+    let (batch_id, document) = match segments.as_slice() {
+        [document] => (None, document.strip_prefix("batches")),
+        [batches, batch_id, document] if batches == "batches" => {
+            (Some(batch_id.clone()), document.strip_prefix("weights"))
+        }
+        _ => (None, None),
+    };
+    let format = match document {
+        Some(".csv") => ExportFormat::Csv,
+        Some(".json") => ExportFormat::Json,
+        _ => return Err(HttpResponse::error(404, "Not found")),
+    };
+
+    let mut export = ExportRequest {
+        batch_id,
+        format,
+        include_deleted: false,
+        unit: None,
+        chunk_size: None,
+        cursor: None,
+    };
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value)
+            .ok_or_else(|| HttpResponse::error(400, "Malformed URL"))?;
+        let invalid = || HttpResponse::error(400, &format!("Invalid value for {}", name));
+        match name {
+            "unit" => export.unit = Some(parse_unit(&value).ok_or_else(invalid)?),
+            "include_deleted" => export.include_deleted = value.parse().map_err(|_| invalid())?,
+            "chunk_size" => export.chunk_size = Some(value.parse().map_err(|_| invalid())?),
+            "cursor" => export.cursor = Some(value),
+            _ => return Err(HttpResponse::error(400, &format!("Unknown query parameter {}", name))),
+        }
+    }
+    Ok(export)
+}
+
+fn parse_unit(value: &str) -> Option<WeightUnit> {
+    match value.to_ascii_lowercase().as_str() {
+        "kilogram" | "kg" => Some(WeightUnit::Kilogram),
+        "pound" | "lb" => Some(WeightUnit::Pound),
+        "gram" | "g" => Some(WeightUnit::Gram),
+        "tonne" | "t" => Some(WeightUnit::Tonne),
+        _ => None,
+    }
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -1,12 +1,23 @@
+mod csv_import;
+mod errors;
+mod export;
+mod http;
+mod memory;
+mod migrations;
 mod models;
+mod pagination;
 mod traits;
 mod repositories;
 mod services;
 
 use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableCell};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::rc::Rc;
+use std::time::Duration;
+use errors::WeightTrackerError;
+use http::{HttpRequest, HttpResponse};
+use memory::*;
 use models::*;
 use services::*;
 use repositories::*;
@@ -14,205 +25,576 @@ use traits::CanisterTimeProvider;
 
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "canbench-rs")]
+mod benches;
 
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+const DEFAULT_RETENTION_SECONDS: u64 = 30 * DAY_SECONDS;
+const MIN_RETENTION_SECONDS: u64 = DAY_SECONDS;
+const PURGE_INTERVAL: Duration = Duration::from_secs(DAY_SECONDS);
+// Records indexed per message while rebuilding the weight indexes.
+const INDEX_REBUILD_CHUNK: usize = 5_000;
+
+// All state lives in stable memory, so upgrades need no pre_upgrade
+// serialization step: the maps are simply re-opened on the same memories.
 thread_local! {
-    static STORAGE: (
-        Arc<RefCell<HashMap<WeightKey, Weight>>>,
-        Arc<RefCell<HashMap<String, Batch>>>,
-        Arc<RefCell<HashMap<String, SharingPermissions>>>
-    ) = (
-        Arc::new(RefCell::new(HashMap::new())),
-        Arc::new(RefCell::new(HashMap::new())),
-        Arc::new(RefCell::new(HashMap::new()))
+    static STORAGE: (WeightStore, BatchStore, PermissionStore, SequenceStore, AuditStore, ApiTokenStore) = (
+        WeightStore::init(
+            get_memory(WEIGHTS_MEMORY_ID),
+            get_memory(WEIGHTS_BY_BATCH_MEMORY_ID),
+            get_memory(WEIGHTS_BY_CREATED_AT_MEMORY_ID),
+            get_memory(WEIGHTS_BY_ITEM_MEMORY_ID),
+            get_memory(WEIGHT_HISTORY_MEMORY_ID),
+            get_memory(CLIENT_REQUESTS_MEMORY_ID),
+            get_memory(WEIGHT_FLAGS_MEMORY_ID)
+        ),
+        Rc::new(RefCell::new(StableBTreeMap::init(get_memory(BATCHES_MEMORY_ID)))),
+        Rc::new(RefCell::new(StableBTreeMap::init(get_memory(PERMISSIONS_MEMORY_ID)))),
+        init_sequence(get_memory(BATCH_SEQUENCE_MEMORY_ID)),
+        AuditStore::init(
+            get_memory(AUDIT_LOG_MEMORY_ID),
+            get_memory(AUDIT_BY_BATCH_MEMORY_ID),
+            get_memory(AUDIT_BY_ITEM_MEMORY_ID)
+        ),
+        Rc::new(RefCell::new(StableBTreeMap::init(get_memory(API_TOKENS_MEMORY_ID))))
+    );
+
+    // Releases before schema versioning never wrote this cell, hence the
+    // legacy default of 1.
+    static STORED_SCHEMA_VERSION: RefCell<StableCell<u16, Memory>> = RefCell::new(
+        StableCell::init(get_memory(SCHEMA_VERSION_MEMORY_ID), 1)
+            .expect("failed to open schema version cell")
     );
 
+    // How long soft-deleted records are kept before the purge removes them.
+    static RETENTION_SECONDS: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(get_memory(RETENTION_MEMORY_ID), DEFAULT_RETENTION_SECONDS)
+            .expect("failed to open retention cell")
+    );
+
+    // Kept on the heap: it is diagnostic only and resets on upgrade.
+    static LAST_PURGE: RefCell<Option<PurgeReport>> = const { RefCell::new(None) };
+
+    #[allow(clippy::type_complexity)]
     static SERVICES: RefCell<(
-        WeightService<WeightStorageImpl<CanisterTimeProvider>, BatchStorageImpl<CanisterTimeProvider>, AccessControlImpl, CanisterTimeProvider>,
-        BatchService<BatchStorageImpl<CanisterTimeProvider>, WeightStorageImpl<CanisterTimeProvider>, AccessControlImpl, CanisterTimeProvider>,
-        AccessControlService<AccessControlImpl, BatchStorageImpl<CanisterTimeProvider>>
+        WeightService<WeightStorageImpl<CanisterTimeProvider>, BatchStorageImpl<CanisterTimeProvider>, AccessControlImpl, CanisterTimeProvider, AuditLogImpl<CanisterTimeProvider>>,
+        BatchService<BatchStorageImpl<CanisterTimeProvider>, WeightStorageImpl<CanisterTimeProvider>, AccessControlImpl, CanisterTimeProvider, AuditLogImpl<CanisterTimeProvider>>,
+        AccessControlService<AccessControlImpl, BatchStorageImpl<CanisterTimeProvider>, AuditLogImpl<CanisterTimeProvider>>
     )> = {
         STORAGE.with(|storage| {
             let weight_store = WeightStorageImpl::with_storage(storage.0.clone(), CanisterTimeProvider);
-            let batch_store = BatchStorageImpl::with_storage(storage.1.clone(), CanisterTimeProvider);
+            let batch_store = BatchStorageImpl::with_storage(storage.1.clone(), storage.3.clone(), CanisterTimeProvider);
             let access_control = AccessControlImpl::with_storage(storage.2.clone());
+            let audit_log = AuditLogImpl::with_storage(storage.4.clone(), CanisterTimeProvider);
 
             RefCell::new((
                 WeightService::new(
                     weight_store.clone(),
                     batch_store.clone(),
                     access_control.clone(),
-                    CanisterTimeProvider
+                    CanisterTimeProvider,
+                    audit_log.clone()
                 ),
                 BatchService::new(
                     batch_store.clone(),
                     weight_store.clone(),
                     access_control.clone(),
-                    CanisterTimeProvider
+                    CanisterTimeProvider,
+                    audit_log.clone()
                 ),
                 AccessControlService::new(
                     access_control,
-                    batch_store
+                    batch_store,
+                    audit_log
                 )
             ))
         })
     };
+
+    static API_TOKENS: ApiTokenService<ApiTokenStorageImpl, CanisterTimeProvider> = STORAGE.with(|storage| {
+        ApiTokenService::new(ApiTokenStorageImpl::with_storage(storage.5.clone()), CanisterTimeProvider)
+    });
+}
+
+fn set_stored_schema_version(version: u16) {
+    STORED_SCHEMA_VERSION.with(|cell| {
+        cell.borrow_mut()
+            .set(version)
+            .expect("failed to write schema version");
+    });
+}
+
+// Timers do not survive upgrades, so both init and post_upgrade call this.
+fn schedule_purge() {
+    ic_cdk_timers::set_timer_interval(PURGE_INTERVAL, || {
+        purge_deleted_records(ic_cdk::id());
+    });
+}
+
+// Indexes a chunk of records per timer call until the rebuild is done. An
+// upgrade cuts it short, and post_upgrade starts it again.
+fn schedule_index_rebuild() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if STORAGE.with(|(weights, ..)| weights.index_next(INDEX_REBUILD_CHUNK)) {
+            schedule_index_rebuild();
+        } else {
+            ic_cdk::println!("Rebuilt weight indexes");
+        }
+    });
 }
 
-fn is_anonymous() -> bool {
-    ic_cdk::caller() == Principal::anonymous()
+fn purge_deleted_records(actor: Principal) -> PurgeReport {
+    let retention = RETENTION_SECONDS.with(|cell| *cell.borrow().get()) * NANOS_PER_SECOND;
+    let report = SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        batch_service.purge_deleted(retention, actor)
+    });
+    ic_cdk::println!(
+        "Purged {} batches and {} weights deleted before {}",
+        report.batch_ids.len(),
+        report.weights_purged,
+        report.cutoff
+    );
+    LAST_PURGE.with(|last| *last.borrow_mut() = Some(report.clone()));
+    report
+}
+
+#[ic_cdk::init]
+fn init() {
+    set_stored_schema_version(migrations::SCHEMA_VERSION);
+    schedule_purge();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    schedule_purge();
+
+    // Like the migrations, nothing here reads every record: the indexes are
+    // rebuilt a chunk at a time on a timer, and lookups read the records not
+    // indexed yet in the meantime.
+    STORAGE.with(|(weights, ..)| {
+        if !weights.indexes_in_sync() {
+            ic_cdk::println!("Weight indexes are out of step with the records; rebuilding them");
+            weights.start_index_rebuild();
+            schedule_index_rebuild();
+        }
+    });
+
+    let stored_version = STORED_SCHEMA_VERSION.with(|cell| *cell.borrow().get());
+    if stored_version > migrations::SCHEMA_VERSION {
+        ic_cdk::trap(&format!(
+            "Refusing to downgrade: stored schema version {} is newer than {}",
+            stored_version,
+            migrations::SCHEMA_VERSION
+        ));
+    }
+    if stored_version == migrations::SCHEMA_VERSION {
+        return;
+    }
+
+    // Nothing is rewritten here: rewriting every record could run out of
+    // instructions as data grows. Older records migrate whenever they are
+    // read and are stamped with the new version when next written.
+    ic_cdk::println!(
+        "Records from schema version {} will migrate to {} as they are read",
+        stored_version,
+        migrations::SCHEMA_VERSION
+    );
+    set_stored_schema_version(migrations::SCHEMA_VERSION);
+}
+
+fn authenticated_caller() -> Result<Principal, WeightTrackerError> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(WeightTrackerError::Unauthenticated);
+    }
+    Ok(caller)
+}
+
+fn controller_caller() -> Result<Principal, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    if !ic_cdk::api::is_controller(&caller) {
+        return Err(WeightTrackerError::unauthorized("Only controllers can manage data retention"));
+    }
+    Ok(caller)
 }
 
 // Weight Management
 #[ic_cdk::update]
-fn create_weight(request: CreateWeightRequest) -> String {
-    if is_anonymous() {
-        return "Please login with Internet Identity to create weights".to_string();
-    }
-    
+fn create_weight(request: CreateWeightRequest) -> Result<CreateWeightResponse, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.create_weight(request, caller)
+    })
+}
+
+#[ic_cdk::update]
+fn create_weights(requests: Vec<CreateWeightRequest>, all_or_nothing: bool) -> Result<CreateWeightsResponse, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.create_weights(requests, all_or_nothing, caller)
+    })
+}
+
+#[ic_cdk::update]
+fn import_weights_csv(request: CsvImportRequest) -> Result<CsvImportReport, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
     SERVICES.with(|services| {
         let (weight_service, _, _) = &mut *services.borrow_mut();
-        weight_service.create_weight(request, ic_cdk::caller())
-            .unwrap_or_else(|e| e)
+        weight_service.import_weights_csv(request, caller)
     })
 }
 
 #[ic_cdk::query]
-fn get_all_weights(batch_id: Option<String>, include_deleted: bool) -> Vec<Weight> {
-    if is_anonymous() {
-        return Vec::new();
-    }
-    
+fn get_all_weights(batch_id: Option<String>, include_deleted: bool, unit: Option<WeightUnit>) -> Result<Vec<Weight>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
     SERVICES.with(|services| {
         let (weight_service, _, _) = &mut *services.borrow_mut();
-        weight_service.get_weights(batch_id, include_deleted, ic_cdk::caller())
+        Ok(weight_service.get_weights(batch_id, include_deleted, unit.unwrap_or_default(), caller))
+    })
+}
+
+#[ic_cdk::query]
+fn get_weights_page(request: WeightPageRequest) -> Result<WeightPage, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.get_weights_page(request, caller)
     })
 }
 
 #[ic_cdk::update]
-fn update_weight(item_id: String, created_at: u64, weight: f64) -> String {
-    if is_anonymous() {
-        return "Please login with Internet Identity to update weights".to_string();
-    }
-    
+fn update_weight(item_id: String, created_at: u64, weight: f64, unit: Option<WeightUnit>, reason: Option<String>) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
     SERVICES.with(|services| {
         let (weight_service, _, _) = &mut *services.borrow_mut();
-        weight_service.update_weight(item_id, created_at, weight, ic_cdk::caller())
-            .unwrap_or_else(|e| e)
+        weight_service.update_weight(item_id, created_at, weight, unit.unwrap_or_default(), reason, caller)
+    })
+}
+
+#[ic_cdk::query]
+fn get_weight_history(item_id: String, created_at: u64, unit: Option<WeightUnit>) -> Result<Vec<WeightRevision>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.get_weight_history(item_id, created_at, unit.unwrap_or_default(), caller)
     })
 }
 
 #[ic_cdk::update]
-fn delete_weight(item_id: String, created_at: u64) -> String {
-    if is_anonymous() {
-        return "Please login with Internet Identity to delete weights".to_string();
-    }
-    
+fn delete_weight(item_id: String, created_at: u64) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.delete_weight(item_id, created_at, caller)
+    })
+}
+
+#[ic_cdk::update]
+fn restore_weight(item_id: String, created_at: u64) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
     SERVICES.with(|services| {
         let (weight_service, _, _) = &mut *services.borrow_mut();
-        weight_service.delete_weight(item_id, created_at, ic_cdk::caller())
-            .unwrap_or_else(|e| e)
+        weight_service.restore_weight(item_id, created_at, caller)
+    })
+}
+
+#[ic_cdk::query]
+fn get_flagged_weights(batch_id: String, include_confirmed: bool, unit: Option<WeightUnit>) -> Result<Vec<FlaggedWeight>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.get_flagged_weights(&batch_id, include_confirmed, unit.unwrap_or_default(), caller)
+    })
+}
+
+#[ic_cdk::update]
+fn confirm_weight(item_id: String, created_at: u64) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.confirm_weight(item_id, created_at, caller)
     })
 }
 
 // Batch Management
 #[ic_cdk::update]
-fn create_batch(request: CreateBatchRequest) -> Result<String, String> {
-    if is_anonymous() {
-        ic_cdk::println!("Anonymous user attempted to create batch");
-        return Err("Please login to create batches".to_string());
-    }
+fn create_batch(request: CreateBatchRequest) -> Result<String, WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     ic_cdk::println!("Creating batch with name: {}", request.name);
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
         ic_cdk::println!("Got batch service, creating batch");
-        batch_service.create_batch(request, ic_cdk::caller())
+        batch_service.create_batch(request, caller)
     })
 }
 
 #[ic_cdk::query]
-fn get_batches(include_deleted: bool) -> Vec<BatchWithStats> {
-    if is_anonymous() {
-        return Vec::new();
-    }
+fn get_batches(include_deleted: bool, unit: Option<WeightUnit>, percentiles: Option<Vec<f64>>) -> Result<Vec<BatchWithStats>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    let percentiles = services::percentiles(percentiles)?;
 
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
-        batch_service.get_batches(include_deleted, ic_cdk::caller())
+        Ok(batch_service.get_batches(include_deleted, unit.unwrap_or_default(), &percentiles, caller))
     })
 }
 
 #[ic_cdk::query]
-fn get_batch(batch_id: String) -> Result<BatchWithStats, String> {
-    if is_anonymous() {
-        return Err("Please login to view batches".to_string());
-    }
+fn get_batch(batch_id: String, unit: Option<WeightUnit>, percentiles: Option<Vec<f64>>) -> Result<BatchWithStats, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    let percentiles = services::percentiles(percentiles)?;
+
+    SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        batch_service.get_batch(&batch_id, unit.unwrap_or_default(), &percentiles, caller)
+    })
+}
+
+#[ic_cdk::query]
+fn get_item_timeline(item_id: String, request: Option<ItemTimelineRequest>) -> Result<ItemTimeline, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.get_item_timeline(&item_id, request.unwrap_or_default(), caller)
+    })
+}
+
+#[ic_cdk::query]
+fn get_weight_series(request: SeriesRequest) -> Result<WeightSeries, WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
-        batch_service.get_batch(&batch_id, ic_cdk::caller())
+        batch_service.get_series(request, caller)
+    })
+}
+
+#[ic_cdk::query]
+fn get_weight_histogram(batch_id: String, request: HistogramRequest) -> Result<Histogram, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        batch_service.get_histogram(&batch_id, request, caller)
     })
 }
 
 #[ic_cdk::update]
-fn update_batch(batch_id: String, request: UpdateBatchRequest) -> Result<(), String> {
-    if is_anonymous() {
-        return Err("Please login to update batches".to_string());
-    }
+fn update_batch(batch_id: String, request: UpdateBatchRequest) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
-        batch_service.update_batch(&batch_id, request, ic_cdk::caller())
+        batch_service.update_batch(&batch_id, request, caller)
     })
 }
 
 #[ic_cdk::update]
-fn delete_batch(batch_id: String) -> Result<(), String> {
-    if is_anonymous() {
-        return Err("Please login to delete batches".to_string());
-    }
+fn delete_batch(batch_id: String) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        batch_service.delete_batch(&batch_id, caller)
+    })
+}
+
+#[ic_cdk::update]
+fn restore_batch(batch_id: String) -> Result<u64, WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
-        batch_service.delete_batch(&batch_id, ic_cdk::caller())
+        batch_service.restore_batch(&batch_id, caller)
     })
 }
 
 // Access Control
 #[ic_cdk::update]
-fn share_with_user(collaborator_principal: Principal, batch_id: String) -> String {
-    if is_anonymous() {
-        return "Please login with Internet Identity to share data".to_string();
-    }
+fn share_with_user(collaborator_principal: Principal, batch_id: String, role: Option<Role>) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     SERVICES.with(|services| {
         let (_, _, access_service) = &mut *services.borrow_mut();
-        access_service.share_with_user(ic_cdk::caller(), collaborator_principal, batch_id)
-            .unwrap_or_else(|e| e)
+        access_service.share_with_user(caller, collaborator_principal, batch_id, role.unwrap_or(Role::Editor))
     })
 }
 
 #[ic_cdk::update]
-fn remove_sharing(request: RemoveSharingRequest) -> String {
-    if is_anonymous() {
-        return "Please login with Internet Identity to remove sharing".to_string();
-    }
+fn remove_sharing(request: RemoveSharingRequest) -> Result<u64, WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     SERVICES.with(|services| {
         let (_, _, access_service) = &mut *services.borrow_mut();
-        access_service.remove_sharing(ic_cdk::caller(), request.collaborator)
-            .unwrap_or_else(|e| e)
+        access_service.remove_sharing(caller, request.collaborator)
+    })
+}
+
+#[ic_cdk::update]
+fn unshare_batch(collaborator: Principal, batch_id: String) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (_, _, access_service) = &mut *services.borrow_mut();
+        access_service.unshare_batch(caller, collaborator, &batch_id)
+    })
+}
+
+#[ic_cdk::query]
+fn get_collaborators() -> Result<Vec<Principal>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (_, _, access_service) = &mut *services.borrow_mut();
+        Ok(access_service.get_collaborators(caller))
     })
 }
 
 #[ic_cdk::query]
-fn get_collaborators() -> Vec<Principal> {
+fn list_batch_collaborators(batch_id: String) -> Result<Vec<BatchCollaborator>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
     SERVICES.with(|services| {
         let (_, _, access_service) = &mut *services.borrow_mut();
-        access_service.get_collaborators(ic_cdk::caller())
+        access_service.list_batch_collaborators(caller, &batch_id)
+    })
+}
+
+// Export
+#[ic_cdk::query]
+fn export(request: ExportRequest) -> Result<ExportChunk, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        batch_service.export(request, caller)
+    })
+}
+
+// HTTP access. Responses carry private data and are not certified, so they
+// are served from the raw domain, <canister id>.raw.icp0.io.
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let owner = request.bearer_token()
+        .and_then(|secret| API_TOKENS.with(|tokens| tokens.authenticate(secret)));
+    let Some(owner) = owner else {
+        return HttpResponse::error(401, "A valid API token is required");
+    };
+    let export_request = match http::export_request(&request) {
+        Ok(export_request) => export_request,
+        Err(response) => return response,
+    };
+
+    let format = export_request.format;
+    SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        match batch_service.export(export_request, owner) {
+            Ok(chunk) => HttpResponse::export(format, chunk),
+            Err(e) => HttpResponse::from_error(&e),
+        }
     })
 }
 
+#[ic_cdk::update]
+async fn create_api_token(label: String) -> Result<String, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    let (entropy,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .unwrap_or_else(|(_, message)| ic_cdk::trap(&format!("failed to get randomness: {}", message)));
+    API_TOKENS.with(|tokens| tokens.create_token(caller, label, &entropy))
+}
+
+#[ic_cdk::query]
+fn list_api_tokens() -> Result<Vec<ApiTokenInfo>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    Ok(API_TOKENS.with(|tokens| tokens.list_tokens(caller)))
+}
+
+#[ic_cdk::update]
+fn revoke_api_token(id: String) -> Result<(), WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    API_TOKENS.with(|tokens| tokens.revoke_token(caller, &id))
+}
+
+// Audit Log
+#[ic_cdk::query]
+fn get_batch_audit_log(batch_id: String, request: AuditPageRequest) -> Result<AuditPage, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (_, batch_service, _) = &mut *services.borrow_mut();
+        batch_service.get_batch_audit_log(&batch_id, request, caller)
+    })
+}
+
+#[ic_cdk::query]
+fn get_item_audit_log(item_id: String, request: AuditPageRequest) -> Result<AuditPage, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+
+    SERVICES.with(|services| {
+        let (weight_service, _, _) = &mut *services.borrow_mut();
+        weight_service.get_item_audit_log(&item_id, request, caller)
+    })
+}
+
+// Data Retention
+#[ic_cdk::update]
+fn set_retention_period(seconds: u64) -> Result<(), WeightTrackerError> {
+    controller_caller()?;
+
+    if seconds < MIN_RETENTION_SECONDS {
+        return Err(WeightTrackerError::validation(
+            "seconds",
+            &format!("Retention period must be at least {} seconds", MIN_RETENTION_SECONDS),
+        ));
+    }
+    if seconds.checked_mul(NANOS_PER_SECOND).is_none() {
+        return Err(WeightTrackerError::validation("seconds", "Retention period is too long"));
+    }
+
+    RETENTION_SECONDS.with(|cell| {
+        cell.borrow_mut()
+            .set(seconds)
+            .expect("failed to write retention period");
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_retention_period() -> Result<u64, WeightTrackerError> {
+    authenticated_caller()?;
+    Ok(RETENTION_SECONDS.with(|cell| *cell.borrow().get()))
+}
+
+#[ic_cdk::update]
+fn purge_deleted_now() -> Result<PurgeReport, WeightTrackerError> {
+    let caller = controller_caller()?;
+    Ok(purge_deleted_records(caller))
+}
+
+#[ic_cdk::query]
+fn get_last_purge_report() -> Result<Option<PurgeReport>, WeightTrackerError> {
+    controller_caller()?;
+    Ok(LAST_PURGE.with(|last| last.borrow().clone()))
+}
+
 #[ic_cdk::query]
 fn whoami() -> Principal {
     ic_cdk::caller()
@@ -220,33 +602,32 @@ fn whoami() -> Principal {
 
 // Additional batch queries
 #[ic_cdk::query]
-fn get_owned_batches() -> Vec<BatchWithStats> {
-    if is_anonymous() {
-        return Vec::new();
-    }
+fn get_owned_batches(unit: Option<WeightUnit>, percentiles: Option<Vec<f64>>) -> Result<Vec<BatchWithStats>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
+    let percentiles = services::percentiles(percentiles)?;
 
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
-        batch_service.get_owned_batches(ic_cdk::caller())
+        Ok(batch_service.get_owned_batches(unit.unwrap_or_default(), &percentiles, caller))
     })
 }
 
 #[ic_cdk::query]
-fn get_shareable_batches() -> Vec<ShareableBatch> {
-    if is_anonymous() {
-        return Vec::new();
-    }
+fn get_shareable_batches() -> Result<Vec<ShareableBatch>, WeightTrackerError> {
+    let caller = authenticated_caller()?;
 
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
-        batch_service.get_shareable_batches(ic_cdk::caller())
+        Ok(batch_service.get_shareable_batches(caller))
     })
 }
 
 #[ic_cdk::query]
-fn get_batch_owner(batch_id: String) -> Result<Principal, String> {
+fn get_batch_owner(batch_id: String) -> Result<Principal, WeightTrackerError> {
     SERVICES.with(|services| {
         let (_, batch_service, _) = &mut *services.borrow_mut();
         batch_service.get_batch_owner(&batch_id)
     })
 }
+
+ic_cdk::export_candid!();
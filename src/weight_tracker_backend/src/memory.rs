@@ -0,0 +1,39 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Each stable structure gets its own virtual memory. Never reuse or renumber
+// an ID once it has shipped: the data behind it lives on across upgrades.
+pub const WEIGHTS_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const BATCHES_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const PERMISSIONS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const WEIGHTS_BY_BATCH_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const WEIGHTS_BY_CREATED_AT_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const BATCH_SEQUENCE_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const RETENTION_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const AUDIT_BY_BATCH_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const AUDIT_BY_ITEM_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const WEIGHT_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const API_TOKENS_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const CLIENT_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const WEIGHTS_BY_ITEM_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const WEIGHT_FLAGS_MEMORY_ID: MemoryId = MemoryId::new(15);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
+}
+
+/// A fresh, heap-backed memory for tests that must not share state.
+#[cfg(test)]
+pub fn test_memory() -> Memory {
+    MemoryManager::init(DefaultMemoryImpl::default()).get(MemoryId::new(0))
+}
@@ -0,0 +1,179 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use crate::models::{Batch, Role, SharingPermissions, ValidationRules, Weight, WeightUnit};
+
+/// Version of the record layout written by this build. Bump it together with
+/// a new entry in `MIGRATIONS` when a stored model changes shape after a
+/// release; changes between two releases share one bump.
+pub const SCHEMA_VERSION: u16 = 2;
+
+/// Records written before version stamps existed are bare Candid messages,
+/// which always start with this magic. They are schema version 1.
+const CANDID_MAGIC: &[u8] = b"DIDL";
+const LEGACY_VERSION: u16 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+    Weight,
+    Batch,
+    SharingPermissions,
+    AuditEntry,
+    WeightRevision,
+    ApiToken,
+    ClientRequest,
+    WeightFlag,
+}
+
+/// A model stored in stable memory under a schema version stamp.
+pub trait Versioned: CandidType + DeserializeOwned {
+    const KIND: RecordKind;
+}
+
+/// Transforms the Candid payload of one record from `from_version` to
+/// `from_version + 1`.
+pub struct Migration {
+    pub from_version: u16,
+    pub description: &'static str,
+    pub migrate: fn(RecordKind, &[u8]) -> Result<Vec<u8>, String>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from_version: 1,
+        description: "upgrade records of the first stable-memory release",
+        migrate: from_first_release,
+    },
+];
+
+/// `Weight` as the first stable-memory release stored it.
+#[derive(CandidType, Deserialize)]
+struct WeightV1 {
+    owner: Principal,
+    batch_id: String,
+    item_id: String,
+    weight: f64,
+    created_at: u64,
+    updated_at: u64,
+    deleted_at: Option<u64>,
+}
+
+/// `Batch` as the first stable-memory release stored it.
+#[derive(CandidType, Deserialize)]
+struct BatchV1 {
+    id: String,
+    name: String,
+    owner: Principal,
+    description: Option<String>,
+    created_at: u64,
+    updated_at: u64,
+    deleted_at: Option<u64>,
+}
+
+/// `SharingPermissions` as the first stable-memory release stored it, when a
+/// grant was just a batch ID.
+#[derive(CandidType, Deserialize)]
+struct SharingPermissionsV1 {
+    owner: Principal,
+    shared_with: HashMap<Principal, HashSet<String>>,
+}
+
+// Weights were always in kilograms. Which deletions came from deleting a
+// batch cannot be told apart, so they are all treated as individual deletions
+// and stay deleted on restore. Batches had no limits, and every collaborator
+// could read, record, update and delete, which is exactly what an editor may do.
+fn from_first_release(kind: RecordKind, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let migrated = match kind {
+        RecordKind::Weight => {
+            let legacy = Decode!(payload, WeightV1).map_err(|e| e.to_string())?;
+            Encode!(&Weight {
+                owner: legacy.owner,
+                batch_id: legacy.batch_id,
+                item_id: legacy.item_id,
+                weight: legacy.weight,
+                created_at: legacy.created_at,
+                updated_at: legacy.updated_at,
+                deleted_at: legacy.deleted_at,
+                deleted_with_batch: false,
+                entered_unit: WeightUnit::Kilogram,
+                notes: None,
+            })
+        }
+        RecordKind::Batch => {
+            let legacy = Decode!(payload, BatchV1).map_err(|e| e.to_string())?;
+            Encode!(&Batch {
+                id: legacy.id,
+                name: legacy.name,
+                owner: legacy.owner,
+                description: legacy.description,
+                created_at: legacy.created_at,
+                updated_at: legacy.updated_at,
+                deleted_at: legacy.deleted_at,
+                unit: WeightUnit::Kilogram,
+                validation: ValidationRules::default(),
+            })
+        }
+        RecordKind::SharingPermissions => {
+            let legacy = Decode!(payload, SharingPermissionsV1).map_err(|e| e.to_string())?;
+            let shared_with = legacy.shared_with.into_iter()
+                .map(|(collaborator, batch_ids)| {
+                    let grants = batch_ids.into_iter().map(|id| (id, Role::Editor)).collect();
+                    (collaborator, grants)
+                })
+                .collect();
+            Encode!(&SharingPermissions { owner: legacy.owner, shared_with })
+        }
+        _ => return Ok(payload.to_vec()),
+    };
+    migrated.map_err(|e| e.to_string())
+}
+
+pub fn encode<T: Versioned>(record: &T) -> Vec<u8> {
+    let mut bytes = SCHEMA_VERSION.to_be_bytes().to_vec();
+    bytes.extend(Encode!(record).expect("failed to encode record"));
+    bytes
+}
+
+pub fn decode<T: Versioned>(bytes: &[u8]) -> Result<T, String> {
+    let (version, payload) = split_version(bytes)?;
+    let payload = upgrade_payload(T::KIND, version, payload.to_vec(), SCHEMA_VERSION, MIGRATIONS)?;
+    Decode!(&payload, T).map_err(|e| format!("failed to decode {:?}: {}", T::KIND, e))
+}
+
+pub fn split_version(bytes: &[u8]) -> Result<(u16, &[u8]), String> {
+    if bytes.starts_with(CANDID_MAGIC) {
+        return Ok((LEGACY_VERSION, bytes));
+    }
+    match bytes {
+        [high, low, payload @ ..] => Ok((u16::from_be_bytes([*high, *low]), payload)),
+        _ => Err("record is too short to carry a schema version".to_string()),
+    }
+}
+
+/// Runs every migration between `version` and `target` over one payload.
+pub fn upgrade_payload(
+    kind: RecordKind,
+    mut version: u16,
+    mut payload: Vec<u8>,
+    target: u16,
+    migrations: &[Migration],
+) -> Result<Vec<u8>, String> {
+    if version > target {
+        return Err(format!(
+            "{:?} record has schema version {} but this build only understands up to {}",
+            kind, version, target
+        ));
+    }
+
+    while version < target {
+        let migration = migrations.iter()
+            .find(|m| m.from_version == version)
+            .ok_or_else(|| format!("no migration registered from schema version {}", version))?;
+        payload = (migration.migrate)(kind, &payload).map_err(|e| {
+            format!("migration '{}' failed for {:?} record: {}", migration.description, kind, e)
+        })?;
+        version += 1;
+    }
+
+    Ok(payload)
+}
@@ -1,22 +1,140 @@
-use candid::{CandidType, Deserialize, Principal};
-use std::collections::{HashMap, HashSet};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use chrono::{Datelike, DateTime, Days, FixedOffset};
+use crate::errors::WeightTrackerError;
+use crate::migrations::{self, RecordKind, Versioned};
+use ic_stable_structures::storable::{Bound, Storable};
+use std::borrow::Cow;
+use std::collections::HashMap;
 
-#[derive(CandidType, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(CandidType, Deserialize, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
 pub struct WeightKey {
     pub owner: Principal,
     pub item_id: String,
     pub created_at: u64,
 }
 
+/// Index entry locating a weight within its batch.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct BatchIndexKey {
+    pub batch_id: String,
+    pub key: WeightKey,
+}
+
+/// Index entry locating a weight by when it was recorded. `item_id` comes
+/// before `owner` so that lookups by item and timestamp are a single range.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct CreatedAtIndexKey {
+    pub created_at: u64,
+    pub item_id: String,
+    pub owner: Principal,
+}
+
+/// Index entry locating a weight by item, in the order it was recorded.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct ItemIndexKey {
+    pub item_id: String,
+    pub created_at: u64,
+    pub owner: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WeightUnit {
+    #[default]
+    Kilogram,
+    Pound,
+    Gram,
+    Tonne,
+}
+
+impl WeightUnit {
+    fn kilograms_per_unit(self) -> f64 {
+        match self {
+            WeightUnit::Kilogram => 1.0,
+            WeightUnit::Pound => 0.453_592_37,
+            WeightUnit::Gram => 0.001,
+            WeightUnit::Tonne => 1000.0,
+        }
+    }
+
+    pub fn to_kilograms(self, value: f64) -> f64 {
+        value * self.kilograms_per_unit()
+    }
+
+    pub fn convert_kilograms(self, kilograms: f64) -> f64 {
+        kilograms / self.kilograms_per_unit()
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            WeightUnit::Kilogram => "kg",
+            WeightUnit::Pound => "lb",
+            WeightUnit::Gram => "g",
+            WeightUnit::Tonne => "t",
+        }
+    }
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Weight {
     pub owner: Principal,
     pub batch_id: String,
     pub item_id: String,
+    /// Stored in kilograms; query responses convert it to the requested unit.
     pub weight: f64,
     pub created_at: u64,
     pub updated_at: u64,
     pub deleted_at: Option<u64>,
+    /// Set when the weight was deleted by deleting its batch, so that
+    /// restoring the batch brings back exactly those weights.
+    pub deleted_with_batch: bool,
+    /// The unit the weight was recorded in, which corrections are given in too.
+    pub entered_unit: WeightUnit,
+    pub notes: Option<String>,
+}
+
+impl Weight {
+    pub fn key(&self) -> WeightKey {
+        WeightKey {
+            owner: self.owner,
+            item_id: self.item_id.clone(),
+            created_at: self.created_at,
+        }
+    }
+
+    pub fn in_unit(self, unit: WeightUnit) -> Self {
+        Self {
+            weight: unit.convert_kilograms(self.weight),
+            ..self
+        }
+    }
+}
+
+/// A value a weight held before it was corrected.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct WeightRevision {
+    /// Stored in kilograms; `get_weight_history` converts it to the requested unit.
+    pub weight: f64,
+    /// When the weight took this value: its creation or an earlier correction.
+    pub valid_from: u64,
+    pub replaced_at: u64,
+    pub replaced_by: Principal,
+    pub reason: Option<String>,
+}
+
+impl WeightRevision {
+    pub fn in_unit(self, unit: WeightUnit) -> Self {
+        Self {
+            weight: unit.convert_kilograms(self.weight),
+            ..self
+        }
+    }
+}
+
+/// Locates the `revision`-th superseded value of a weight, counting from 0.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct WeightRevisionKey {
+    pub key: WeightKey,
+    pub revision: u32,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -28,6 +146,77 @@ pub struct Batch {
     pub created_at: u64,
     pub updated_at: u64,
     pub deleted_at: Option<u64>,
+    /// Unit weights of this batch are recorded in unless they say otherwise.
+    pub unit: WeightUnit,
+    pub validation: ValidationRules,
+}
+
+/// Limits a batch puts on its weights, on top of the ones every weight obeys.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ValidationRules {
+    /// Lightest accepted weight, in the batch's unit.
+    pub min_weight: Option<f64>,
+    /// Heaviest accepted weight, in the batch's unit.
+    pub max_weight: Option<f64>,
+    /// Item IDs must match this pattern in full. `*` stands for any run of
+    /// characters, `?` for any single character and `#` for a digit.
+    pub item_id_pattern: Option<String>,
+    /// Whether an item can have more than one live weight in the batch.
+    pub allow_duplicate_item_ids: bool,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        Self {
+            min_weight: None,
+            max_weight: None,
+            item_id_pattern: None,
+            allow_duplicate_item_ids: true,
+        }
+    }
+}
+
+impl ValidationRules {
+    pub fn item_id_matches(&self, item_id: &str) -> bool {
+        match &self.item_id_pattern {
+            Some(pattern) => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                let item_id: Vec<char> = item_id.chars().collect();
+                pattern_matches(&pattern, &item_id)
+            }
+            None => true,
+        }
+    }
+}
+
+fn pattern_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was seen and how much of the text it has taken so far.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let matches_one = match pattern.get(p) {
+            Some('?') => true,
+            Some('#') => text[t].is_ascii_digit(),
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(c) => *c == text[t],
+            None => false,
+        };
+        if matches_one {
+            p += 1;
+            t += 1;
+        } else if let Some((star, taken)) = backtrack {
+            backtrack = Some((star, taken + 1));
+            p = star + 1;
+            t = taken + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 // DTOs (Data Transfer Objects)
@@ -37,26 +226,403 @@ pub struct CreateWeightRequest {
     pub batch_id: String,
     pub item_id: String,
     pub weight: f64,
+    /// Unit of `weight`; defaults to the batch's unit.
+    pub unit: Option<WeightUnit>,
+    pub notes: Option<String>,
+    /// Chosen by the client to make retries safe: another create by the same
+    /// caller with this ID within 24 hours records nothing and returns the
+    /// key of the weight the first one recorded.
+    pub client_request_id: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CreateWeightResponse {
+    pub key: WeightKey,
+    /// Reasons the weight looks like a data-entry error. It is recorded
+    /// anyway and listed by `get_flagged_weights` until its owner confirms it.
+    pub warnings: Vec<OutlierWarning>,
+}
+
+/// Fewest other live weights a batch needs before a weight is compared with them.
+const MIN_WEIGHTS_FOR_OUTLIERS: usize = 5;
+const OUTLIER_IQR_FENCE: f64 = 3.0;
+const OUTLIER_Z_SCORE: f64 = 3.0;
+const OUTLIER_DAILY_JUMP_PERCENT: f64 = 50.0;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutlierKind {
+    /// More than three interquartile ranges outside the middle half of the
+    /// batch's weights.
+    OutsideBatchRange,
+    /// More than three standard deviations from the batch's average.
+    FarFromBatchAverage,
+    /// Changed by more than 50% a day, compounded, since the item's previous
+    /// reading. Readings less than a day apart count as a day apart.
+    JumpFromPrevious,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct OutlierWarning {
+    pub kind: OutlierKind,
+    /// How far out the weight is: interquartile ranges beyond the middle
+    /// half, standard deviations from the average, or percent change per day
+    /// since the previous reading.
+    pub score: f64,
+}
+
+impl OutlierWarning {
+    /// Checks a weight against the batch's other live weights and the item's
+    /// previous reading, all in kilograms. `previous` also gives how many
+    /// nanoseconds before this weight it was taken.
+    pub fn detect(kilograms: f64, batch_weights: &[f64], previous: Option<(f64, u64)>) -> Vec<Self> {
+        let mut warnings = Vec::new();
+        if batch_weights.len() >= MIN_WEIGHTS_FOR_OUTLIERS {
+            let mut sorted = batch_weights.to_vec();
+            sorted.sort_by(f64::total_cmp);
+            let q1 = percentile_of_sorted(&sorted, 25.0);
+            let q3 = percentile_of_sorted(&sorted, 75.0);
+            let iqr = q3 - q1;
+            let beyond = (q1 - kilograms).max(kilograms - q3);
+            if iqr > 0.0 && beyond > OUTLIER_IQR_FENCE * iqr {
+                warnings.push(Self { kind: OutlierKind::OutsideBatchRange, score: beyond / iqr });
+            }
+
+            let stats = BatchStats::from_weights(&sorted, &[]);
+            if stats.std_deviation > 0.0 {
+                let z_score = (kilograms - stats.average_weight) / stats.std_deviation;
+                if z_score.abs() > OUTLIER_Z_SCORE {
+                    warnings.push(Self { kind: OutlierKind::FarFromBatchAverage, score: z_score });
+                }
+            }
+        }
+        if let Some((previous, elapsed)) = previous.filter(|(p, _)| *p > 0.0) {
+            // Growing stock weighed weekly gains a lot between readings, so
+            // the change is spread over the days it took
+            let days = (elapsed as f64 / NANOS_PER_DAY).max(1.0);
+            let daily_change_percent = ((kilograms / previous).powf(1.0 / days) - 1.0) * 100.0;
+            if daily_change_percent.abs() > OUTLIER_DAILY_JUMP_PERCENT {
+                warnings.push(Self { kind: OutlierKind::JumpFromPrevious, score: daily_change_percent });
+            }
+        }
+        warnings
+    }
+}
+
+/// Outlier warnings raised for a weight, kept until the weight is corrected
+/// or purged.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct WeightFlag {
+    pub warnings: Vec<OutlierWarning>,
+    pub flagged_at: u64,
+    /// Set when the owner confirms the weight is correct after all.
+    pub confirmed_by: Option<Principal>,
+    pub confirmed_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FlaggedWeight {
+    pub weight: Weight,
+    pub flag: WeightFlag,
+}
+
+/// What became of one row of a bulk create, in the order of the request.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum CreateWeightOutcome {
+    Created(WeightKey),
+    /// The row's client request ID was already used; this is the key of the
+    /// weight recorded then.
+    Replayed(WeightKey),
+    Failed(WeightTrackerError),
+    /// The row was valid but not written because another row failed.
+    Skipped,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CreateWeightsResponse {
+    pub results: Vec<CreateWeightOutcome>,
+    pub created: u64,
+}
+
+/// Header names of the CSV columns holding each field.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CsvColumns {
+    pub item_id: String,
+    pub weight: String,
+    /// Either nanoseconds since the epoch (from 2001-09-09 on; smaller numbers,
+    /// such as seconds, fail the row), an RFC 3339 date and time or a
+    /// `YYYY-MM-DD` date (midnight UTC). Rows are recorded at the time of the
+    /// import when this is omitted.
+    pub timestamp: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CsvImportRequest {
+    pub batch_id: String,
+    /// Comma-separated UTF-8 text whose first line names the columns.
+    pub csv: Vec<u8>,
+    pub columns: CsvColumns,
+    /// Unit of the weight column; defaults to the batch's unit.
+    pub unit: Option<WeightUnit>,
+    /// Write nothing unless every row is valid.
+    pub all_or_nothing: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CsvImportRow {
+    /// Line of the file the row starts on, counting the header as line 1.
+    pub line: u64,
+    pub outcome: CreateWeightOutcome,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CsvImportReport {
+    pub rows: Vec<CsvImportRow>,
+    pub created: u64,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct CreateBatchRequest {
     pub name: String,
     pub description: Option<String>,
+    /// Defaults to kilograms.
+    pub unit: Option<WeightUnit>,
+    /// Defaults to no limits beyond the global ones.
+    pub validation: Option<ValidationRules>,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct SharingPermissions {
     pub owner: Principal,
-    pub shared_with: HashMap<Principal, HashSet<String>>, // Principal -> Set of batch_ids
+    pub shared_with: HashMap<Principal, HashMap<String, Role>>, // Principal -> batch_id -> Role
+}
+
+/// What a collaborator may do in a batch shared with them. Any role lets the
+/// collaborator see the batch itself and its stats.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Reads weights.
+    Viewer,
+    /// Records new weights but cannot read or change existing ones.
+    Recorder,
+    /// Reads, records, updates and deletes weights.
+    Editor,
+    /// Everything an editor can do, plus renaming and describing the batch.
+    Manager,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    ReadWeights,
+    RecordWeights,
+    EditWeights,
+    ManageBatch,
 }
 
+impl Role {
+    pub fn allows(self, permission: Permission) -> bool {
+        match self {
+            Role::Viewer => permission == Permission::ReadWeights,
+            Role::Recorder => permission == Permission::RecordWeights,
+            Role::Editor => permission != Permission::ManageBatch,
+            Role::Manager => true,
+        }
+    }
+}
+
+/// Outcome of one run of the purge of soft-deleted records.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PurgeReport {
+    pub ran_at: u64,
+    /// Records deleted before this time were purged.
+    pub cutoff: u64,
+    pub batch_ids: Vec<String>,
+    pub weights_purged: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Share,
+    Unshare,
+    /// Removal of a soft-deleted record by the retention purge.
+    Purge,
+    /// The owner vouching for a weight flagged as a likely data-entry error.
+    Confirm,
+}
+
+/// The record a mutation touched, within the batch of its audit event.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AuditTarget {
+    Batch,
+    Weight(WeightKey),
+    /// A collaborator's grant on the batch.
+    Grant(Principal),
+}
+
+/// The audited state of a target. Snapshots are kept independent of the
+/// stored models so that old entries never need migrating.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AuditValue {
+    Batch {
+        name: String,
+        description: Option<String>,
+        deleted_at: Option<u64>,
+    },
+    Weight {
+        weight: f64,
+        deleted_at: Option<u64>,
+    },
+    Grant(Role),
+}
+
+impl AuditValue {
+    pub fn of_batch(batch: &Batch) -> Self {
+        AuditValue::Batch {
+            name: batch.name.clone(),
+            description: batch.description.clone(),
+            deleted_at: batch.deleted_at,
+        }
+    }
+
+    pub fn of_weight(weight: &Weight) -> Self {
+        AuditValue::Weight {
+            weight: weight.weight,
+            deleted_at: weight.deleted_at,
+        }
+    }
+}
+
+/// One mutation as reported by a service.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEvent {
+    pub actor: Principal,
+    pub action: AuditAction,
+    /// Owner of the batch the target belongs to.
+    pub owner: Principal,
+    pub batch_id: String,
+    pub target: AuditTarget,
+    /// State before the mutation; absent when the target did not exist.
+    pub before: Option<AuditValue>,
+    /// State after the mutation; absent when the target no longer exists.
+    pub after: Option<AuditValue>,
+}
+
+impl AuditEvent {
+    pub fn batch(actor: Principal, action: AuditAction, batch: &Batch, before: Option<&Batch>, after: Option<&Batch>) -> Self {
+        Self {
+            actor,
+            action,
+            owner: batch.owner,
+            batch_id: batch.id.clone(),
+            target: AuditTarget::Batch,
+            before: before.map(AuditValue::of_batch),
+            after: after.map(AuditValue::of_batch),
+        }
+    }
+
+    pub fn weight(actor: Principal, action: AuditAction, weight: &Weight, before: Option<&Weight>, after: Option<&Weight>) -> Self {
+        Self {
+            actor,
+            action,
+            owner: weight.owner,
+            batch_id: weight.batch_id.clone(),
+            target: AuditTarget::Weight(WeightKey {
+                owner: weight.owner,
+                item_id: weight.item_id.clone(),
+                created_at: weight.created_at,
+            }),
+            before: before.map(AuditValue::of_weight),
+            after: after.map(AuditValue::of_weight),
+        }
+    }
+
+    pub fn grant(actor: Principal, action: AuditAction, owner: Principal, batch_id: &str, collaborator: Principal, before: Option<Role>, after: Option<Role>) -> Self {
+        Self {
+            actor,
+            action,
+            owner,
+            batch_id: batch_id.to_string(),
+            target: AuditTarget::Grant(collaborator),
+            before: before.map(AuditValue::Grant),
+            after: after.map(AuditValue::Grant),
+        }
+    }
+}
+
+/// An event as stored in the append-only audit log.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Position in the log; later entries have higher IDs.
+    pub id: u64,
+    pub recorded_at: u64,
+    pub event: AuditEvent,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct AuditPageRequest {
+    pub page_size: Option<u32>,
+    /// `next_cursor` of the previous page; omit to start from the oldest entry.
+    pub cursor: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_cursor: Option<String>,
+}
+
+/// Index entry locating an audit entry by the batch it concerns.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct AuditBatchIndexKey {
+    pub batch_id: String,
+    pub id: u64,
+}
+
+/// Index entry locating an audit entry by the item whose weight it concerns.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct AuditItemIndexKey {
+    pub item_id: String,
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchCollaborator {
+    pub collaborator: Principal,
+    pub role: Role,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PercentileWeight {
+    /// Between 0 and 100.
+    pub percentile: f64,
+    pub weight: f64,
+}
+
+/// Summary of a batch's live weights. Every figure is 0 for an empty batch,
+/// and the spread figures are 0 for a batch of one weight.
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct BatchStats {
     pub count: u64,
     pub min_weight: f64,
     pub max_weight: f64,
     pub average_weight: f64,
+    /// Unit of the weights above and below.
+    pub unit: WeightUnit,
+    pub median_weight: f64,
+    /// Population standard deviation.
+    pub std_deviation: f64,
+    /// Standard deviation as a percentage of the average.
+    pub coefficient_of_variation: f64,
+    /// Percentage of weights within 10% of the average, the usual measure
+    /// of flock uniformity.
+    pub uniformity: f64,
+    /// The percentiles asked for, interpolated between the nearest weights.
+    /// Empty for an empty batch, and for callers who cannot read its weights.
+    pub percentiles: Vec<PercentileWeight>,
 }
 
 impl BatchStats {
@@ -66,35 +632,332 @@ impl BatchStats {
             min_weight: 0.0,
             max_weight: 0.0,
             average_weight: 0.0,
+            unit: WeightUnit::Kilogram,
+            median_weight: 0.0,
+            std_deviation: 0.0,
+            coefficient_of_variation: 0.0,
+            uniformity: 0.0,
+            percentiles: Vec::new(),
         }
     }
 
-    pub fn from_weights(weights: &[f64]) -> Self {
-        let count = weights.len() as u64;
-        let min_weight = weights.iter().fold(f64::INFINITY, |a, &b| a.min(b));
-        let max_weight = weights.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-        let sum: f64 = weights.iter().sum();
-        let average_weight = sum / count as f64;
+    /// Converts stats computed in kilograms. The relative figures stay as
+    /// they are.
+    pub fn in_unit(self, unit: WeightUnit) -> Self {
+        Self {
+            min_weight: unit.convert_kilograms(self.min_weight),
+            max_weight: unit.convert_kilograms(self.max_weight),
+            average_weight: unit.convert_kilograms(self.average_weight),
+            unit,
+            median_weight: unit.convert_kilograms(self.median_weight),
+            std_deviation: unit.convert_kilograms(self.std_deviation),
+            percentiles: self.percentiles.into_iter()
+                .map(|p| PercentileWeight { weight: unit.convert_kilograms(p.weight), ..p })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Keeps only the count, min, max and average, which cannot give away
+    /// the individual readings behind them.
+    pub fn without_distribution(self) -> Self {
+        Self {
+            median_weight: 0.0,
+            std_deviation: 0.0,
+            coefficient_of_variation: 0.0,
+            uniformity: 0.0,
+            percentiles: Vec::new(),
+            ..self
+        }
+    }
+
+    /// `percentiles` are between 0 and 100.
+    pub fn from_weights(weights: &[f64], percentiles: &[f64]) -> Self {
+        if weights.is_empty() {
+            return Self::empty();
+        }
+
+        let mut sorted = weights.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len() as u64;
+        let average_weight = sorted.iter().sum::<f64>() / count as f64;
+        let variance = sorted.iter().map(|w| (w - average_weight).powi(2)).sum::<f64>() / count as f64;
+        let std_deviation = variance.sqrt();
+        let coefficient_of_variation = if average_weight > 0.0 {
+            std_deviation / average_weight * 100.0
+        } else {
+            0.0
+        };
+        let within = sorted.iter().filter(|w| (*w - average_weight).abs() <= average_weight * 0.1).count();
 
         Self {
             count,
-            min_weight,
-            max_weight,
+            min_weight: sorted[0],
+            max_weight: sorted[sorted.len() - 1],
             average_weight,
+            unit: WeightUnit::Kilogram,
+            median_weight: percentile_of_sorted(&sorted, 50.0),
+            std_deviation,
+            coefficient_of_variation,
+            uniformity: within as f64 / count as f64 * 100.0,
+            percentiles: percentiles.iter()
+                .map(|&percentile| PercentileWeight { percentile, weight: percentile_of_sorted(&sorted, percentile) })
+                .collect(),
         }
     }
 }
 
+/// Linear interpolation between the closest ranks of a non-empty sorted slice.
+fn percentile_of_sorted(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = percentile.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct BatchWithStats {
     pub batch: Batch,
     pub stats: BatchStats,
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ItemTimelineRequest {
+    /// Whose item it is; item IDs are only unique per owner. Defaults to the
+    /// caller.
+    pub owner: Option<Principal>,
+    /// Only readings recorded at or after this time.
+    pub from: Option<u64>,
+    /// Only readings recorded at or before this time.
+    pub to: Option<u64>,
+    /// Unit of the weights and gains; defaults to kilograms.
+    pub unit: Option<WeightUnit>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TimelineReading {
+    pub weight: Weight,
+    /// Change since the previous reading; absent for the first.
+    pub delta: Option<f64>,
+    /// Average daily gain since the previous reading; absent for the first
+    /// and for a reading taken at the same time as the previous one.
+    pub daily_gain: Option<f64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ItemTimeline {
+    pub item_id: String,
+    pub unit: WeightUnit,
+    /// Oldest first, across every batch the caller can read.
+    pub readings: Vec<TimelineReading>,
+    /// Average daily gain from the first reading to the last; absent unless
+    /// they were taken at different times.
+    pub average_daily_gain: Option<f64>,
+}
+
+impl ItemTimeline {
+    /// `weights` must be oldest first and already in `unit`.
+    pub fn from_weights(item_id: String, weights: Vec<Weight>, unit: WeightUnit) -> Self {
+        let average_daily_gain = match (weights.first(), weights.last()) {
+            (Some(first), Some(last)) => daily_gain(first, last),
+            _ => None,
+        };
+        let mut previous: Option<Weight> = None;
+        let readings = weights.into_iter()
+            .map(|weight| {
+                let reading = TimelineReading {
+                    delta: previous.as_ref().map(|p| weight.weight - p.weight),
+                    daily_gain: previous.as_ref().and_then(|p| daily_gain(p, &weight)),
+                    weight: weight.clone(),
+                };
+                previous = Some(weight);
+                reading
+            })
+            .collect();
+
+        Self {
+            item_id,
+            unit,
+            readings,
+            average_daily_gain,
+        }
+    }
+}
+
+const NANOS_PER_DAY: f64 = 24.0 * 60.0 * 60.0 * 1e9;
+
+fn daily_gain(from: &Weight, to: &Weight) -> Option<f64> {
+    let days = to.created_at.checked_sub(from.created_at).filter(|nanos| *nanos > 0)? as f64 / NANOS_PER_DAY;
+    Some((to.weight - from.weight) / days)
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeriesInterval {
+    Day,
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+impl SeriesInterval {
+    /// Start of the interval holding `timestamp`, both in nanoseconds since
+    /// the epoch, with days starting at midnight at `offset`.
+    pub fn start_of(self, timestamp: u64, offset: FixedOffset) -> u64 {
+        let local = DateTime::from_timestamp_nanos(timestamp as i64).with_timezone(&offset).date_naive();
+        let first_day = match self {
+            SeriesInterval::Day => local,
+            SeriesInterval::Week => local - Days::new(local.weekday().num_days_from_monday() as u64),
+            SeriesInterval::Month => local.with_day(1).unwrap_or(local),
+        };
+        let start = first_day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp_nanos_opt().unwrap_or_default()
+            - offset.local_minus_utc() as i64 * 1_000_000_000;
+        start.max(0) as u64
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SeriesRequest {
+    /// Covers every batch the caller can see when omitted.
+    pub batch_id: Option<String>,
+    pub interval: SeriesInterval,
+    /// Offset of the caller's time zone from UTC, e.g. 120 for UTC+2, so that
+    /// days start at local midnight. Defaults to 0.
+    pub utc_offset_minutes: Option<i32>,
+    /// Only weights recorded at or after this time.
+    pub from: Option<u64>,
+    /// Only weights recorded at or before this time.
+    pub to: Option<u64>,
+    /// Unit of the stats; defaults to kilograms.
+    pub unit: Option<WeightUnit>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SeriesPoint {
+    /// When the interval starts, in nanoseconds since the epoch.
+    pub start: u64,
+    pub stats: BatchStats,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WeightSeries {
+    pub interval: SeriesInterval,
+    /// Oldest first; intervals without weights are left out.
+    pub points: Vec<SeriesPoint>,
+}
+
+/// A named weight range such as a grade, from `min_weight` up to but not
+/// including `max_weight`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct GradeBand {
+    pub name: String,
+    pub min_weight: f64,
+    pub max_weight: f64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum HistogramBuckets {
+    /// Buckets of this width, each starting at a multiple of it.
+    Width(f64),
+    /// This many equal buckets from the lightest to the heaviest weight.
+    Count(u32),
+    /// One bucket per band, in the order given.
+    Bands(Vec<GradeBand>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HistogramRequest {
+    pub buckets: HistogramBuckets,
+    /// Unit of the bucket bounds, both requested and returned; defaults to
+    /// kilograms.
+    pub unit: Option<WeightUnit>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistogramBucket {
+    /// The band's name; absent for width and count buckets.
+    pub label: Option<String>,
+    pub min_weight: f64,
+    /// Exclusive, except for the last of a bucket count, which holds the
+    /// heaviest weight.
+    pub max_weight: f64,
+    pub count: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Histogram {
+    pub buckets: Vec<HistogramBucket>,
+    pub unit: WeightUnit,
+    /// Number of live weights in the batch.
+    pub total: u64,
+    /// Weights outside every band; always 0 for width and count buckets.
+    pub ungraded: u64,
+}
+
+impl Histogram {
+    /// Sorts `weights`, given in `unit`, into valid `buckets`. An empty batch
+    /// has no width or count buckets.
+    pub fn from_weights(weights: &[f64], buckets: &HistogramBuckets, unit: WeightUnit) -> Self {
+        let bucket = |label, min_weight, max_weight| HistogramBucket { label, min_weight, max_weight, count: 0 };
+        let min = weights.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = weights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        let mut ungraded = 0;
+        let buckets = match buckets {
+            _ if weights.is_empty() && !matches!(buckets, HistogramBuckets::Bands(_)) => Vec::new(),
+            HistogramBuckets::Width(width) => {
+                let first = (min / width).floor();
+                let mut buckets: Vec<_> = (0..=((max / width).floor() - first) as usize)
+                    .map(|i| bucket(None, (first + i as f64) * width, (first + i as f64 + 1.0) * width))
+                    .collect();
+                for weight in weights {
+                    buckets[((weight / width).floor() - first) as usize].count += 1;
+                }
+                buckets
+            }
+            HistogramBuckets::Count(count) => {
+                let count = *count as usize;
+                let width = (max - min) / count as f64;
+                let mut buckets: Vec<_> = (0..count)
+                    .map(|i| bucket(None, min + i as f64 * width, if i + 1 == count { max } else { min + (i + 1) as f64 * width }))
+                    .collect();
+                for weight in weights {
+                    let index = if width > 0.0 { ((weight - min) / width) as usize } else { 0 };
+                    buckets[index.min(count - 1)].count += 1;
+                }
+                buckets
+            }
+            HistogramBuckets::Bands(bands) => {
+                let mut buckets: Vec<_> = bands.iter()
+                    .map(|band| bucket(Some(band.name.clone()), band.min_weight, band.max_weight))
+                    .collect();
+                for weight in weights {
+                    match buckets.iter_mut().find(|b| *weight >= b.min_weight && *weight < b.max_weight) {
+                        Some(bucket) => bucket.count += 1,
+                        None => ungraded += 1,
+                    }
+                }
+                buckets
+            }
+        };
+
+        Self {
+            buckets,
+            unit,
+            total: weights.len() as u64,
+            ungraded,
+        }
+    }
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct UpdateBatchRequest {
     pub name: String,
     pub description: Option<String>,
+    /// Changes the unit new weights default to; omit to keep it.
+    pub unit: Option<WeightUnit>,
+    /// Replaces the batch's validation rules; omit to keep them. Weights
+    /// already recorded are not checked again.
+    pub validation: Option<ValidationRules>,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -106,4 +969,165 @@ pub struct ShareableBatch {
 #[derive(candid::CandidType, candid::Deserialize, Debug)]
 pub struct RemoveSharingRequest {
     pub collaborator: Principal,
-}
\ No newline at end of file
+}
+
+/// Narrows a weight query. Every bound is inclusive and unset fields match
+/// everything.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct WeightFilter {
+    pub batch_id: Option<String>,
+    pub item_id: Option<String>,
+    pub created_from: Option<u64>,
+    pub created_to: Option<u64>,
+    pub min_weight: Option<f64>,
+    pub max_weight: Option<f64>,
+    pub include_deleted: bool,
+}
+
+impl WeightFilter {
+    pub fn matches(&self, weight: &Weight) -> bool {
+        (self.include_deleted || weight.deleted_at.is_none())
+            && self.batch_id.as_ref().is_none_or(|id| weight.batch_id == *id)
+            && self.item_id.as_ref().is_none_or(|id| weight.item_id == *id)
+            && self.created_from.is_none_or(|from| weight.created_at >= from)
+            && self.created_to.is_none_or(|to| weight.created_at <= to)
+            && self.min_weight.is_none_or(|min| weight.weight >= min)
+            && self.max_weight.is_none_or(|max| weight.weight <= max)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WeightSortOrder {
+    #[default]
+    CreatedAtAsc,
+    CreatedAtDesc,
+    WeightAsc,
+    WeightDesc,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct WeightPageRequest {
+    pub filter: WeightFilter,
+    pub sort: Option<WeightSortOrder>,
+    pub page_size: Option<u32>,
+    /// `next_cursor` of the previous page; omit to start from the beginning.
+    pub cursor: Option<String>,
+    /// Unit of the returned weights and of the filter's weight bounds;
+    /// defaults to kilograms.
+    pub unit: Option<WeightUnit>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WeightPage {
+    pub weights: Vec<Weight>,
+    /// Present when more weights match; pass it back to fetch the next page.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportRequest {
+    /// Exports every batch the caller can read weights of when omitted.
+    pub batch_id: Option<String>,
+    pub format: ExportFormat,
+    pub include_deleted: bool,
+    /// Unit of the exported weights and stats; defaults to kilograms.
+    pub unit: Option<WeightUnit>,
+    /// Batches and weights per chunk; defaults to 1000, at most 5000.
+    pub chunk_size: Option<u32>,
+    /// `next_cursor` of the previous chunk; omit to start the export.
+    pub cursor: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportChunk {
+    /// The next part of the UTF-8 document; the chunks of an export
+    /// concatenated in order form the whole document.
+    pub data: Vec<u8>,
+    /// Present until the last chunk; pass it back to fetch the next one.
+    pub next_cursor: Option<String>,
+}
+
+/// A client request ID as used by one caller.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct ClientRequestKey {
+    pub caller: Principal,
+    pub client_request_id: String,
+}
+
+/// The weight a create with a client request ID recorded, kept so that
+/// retries of the create can be answered with it.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClientRequest {
+    pub key: WeightKey,
+    pub recorded_at: u64,
+}
+
+/// A secret that lets HTTP requests read data as its owner. Only a hash of
+/// the secret is stored, as the key of the token.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ApiToken {
+    pub owner: Principal,
+    pub label: String,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ApiTokenInfo {
+    /// Identifies the token for revocation; it is not the secret itself.
+    pub id: String,
+    pub label: String,
+    pub created_at: u64,
+}
+
+// Stable storage encoding. Keys are plain Candid since their ordering must
+// never change; records carry a schema version so they can be migrated.
+// Keys are never migrated: changing a key's shape means a new map.
+macro_rules! impl_key_storable {
+    ($($key:ident),* $(,)?) => {
+        $(
+            impl Storable for $key {
+                fn to_bytes(&self) -> Cow<'_, [u8]> {
+                    Cow::Owned(Encode!(self).expect(concat!("failed to encode ", stringify!($key))))
+                }
+
+                fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                    Decode!(bytes.as_ref(), Self).expect(concat!("failed to decode ", stringify!($key)))
+                }
+
+                const BOUND: Bound = Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+impl_key_storable!(WeightKey, BatchIndexKey, CreatedAtIndexKey, ItemIndexKey, AuditBatchIndexKey, AuditItemIndexKey, WeightRevisionKey, ClientRequestKey);
+
+macro_rules! impl_versioned_storable {
+    ($($model:ident),* $(,)?) => {
+        $(
+            impl Versioned for $model {
+                const KIND: RecordKind = RecordKind::$model;
+            }
+
+            impl Storable for $model {
+                fn to_bytes(&self) -> Cow<'_, [u8]> {
+                    Cow::Owned(migrations::encode(self))
+                }
+
+                fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                    migrations::decode(bytes.as_ref()).unwrap_or_else(|e| panic!("{}", e))
+                }
+
+                const BOUND: Bound = Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+impl_versioned_storable!(Weight, Batch, SharingPermissions, AuditEntry, WeightRevision, ApiToken, ClientRequest, WeightFlag);
@@ -0,0 +1,95 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use std::cmp::Ordering;
+use crate::errors::WeightTrackerError;
+use crate::models::{Weight, WeightKey, WeightSortOrder};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// Resolves the requested page size, rejecting values that cannot be served.
+pub fn page_size(requested: Option<u32>) -> Result<usize, WeightTrackerError> {
+    match requested.unwrap_or(DEFAULT_PAGE_SIZE) {
+        0 => Err(WeightTrackerError::validation("page_size", "Page size must be at least 1")),
+        size if size > MAX_PAGE_SIZE => Err(WeightTrackerError::validation(
+            "page_size",
+            &format!("Page size cannot exceed {}", MAX_PAGE_SIZE),
+        )),
+        size => Ok(size as usize),
+    }
+}
+
+/// Opaque cursors are hex-encoded Candid so clients cannot depend on their
+/// layout.
+pub fn encode_cursor<T: CandidType>(cursor: &T) -> String {
+    Encode!(cursor)
+        .expect("failed to encode cursor")
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn decode_cursor<T: CandidType + for<'de> Deserialize<'de>>(cursor: &str) -> Result<T, WeightTrackerError> {
+    let invalid = || WeightTrackerError::validation("cursor", "Invalid cursor");
+    if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+        return Err(invalid());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    Decode!(&bytes, T).map_err(|_| invalid())
+}
+
+/// Where an export continues. When `opened` is set, the batch itself and its
+/// weights up to and including `after` were already exported, and `wrote_any`
+/// tells whether at least one of those weights was, since they may have been
+/// purged before the next chunk.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportCursor {
+    pub batch_id: String,
+    pub opened: bool,
+    pub after: Option<WeightKey>,
+    pub wrote_any: bool,
+    pub batches_started: u64,
+}
+
+/// Position of the last weight returned on a page.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WeightCursor {
+    pub sort: WeightSortOrder,
+    pub weight: f64,
+    pub created_at: u64,
+    pub owner: Principal,
+    pub item_id: String,
+}
+
+impl WeightCursor {
+    pub fn after(sort: WeightSortOrder, weight: &Weight) -> Self {
+        Self {
+            sort,
+            weight: weight.weight,
+            created_at: weight.created_at,
+            owner: weight.owner,
+            item_id: weight.item_id.clone(),
+        }
+    }
+}
+
+/// Total order of weights for a sort order. Ties are broken by the weight key
+/// so that every weight has a unique position to resume from.
+pub fn compare_weights(sort: WeightSortOrder, a: &WeightCursor, b: &WeightCursor) -> Ordering {
+    let primary = match sort {
+        WeightSortOrder::CreatedAtAsc | WeightSortOrder::CreatedAtDesc => Ordering::Equal,
+        WeightSortOrder::WeightAsc | WeightSortOrder::WeightDesc => a.weight.total_cmp(&b.weight),
+    };
+    let ordering = primary
+        .then_with(|| a.created_at.cmp(&b.created_at))
+        .then_with(|| a.owner.cmp(&b.owner))
+        .then_with(|| a.item_id.cmp(&b.item_id));
+
+    match sort {
+        WeightSortOrder::CreatedAtDesc | WeightSortOrder::WeightDesc => ordering.reverse(),
+        WeightSortOrder::CreatedAtAsc | WeightSortOrder::WeightAsc => ordering,
+    }
+}
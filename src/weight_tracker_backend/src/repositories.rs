@@ -1,163 +1,548 @@
 use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use ic_stable_structures::Storable;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::rc::Rc;
+use crate::errors::WeightTrackerError;
+use crate::memory::Memory;
 use crate::models::*;
 use crate::traits::*;
 
+pub type BatchStore = Rc<RefCell<StableBTreeMap<String, Batch, Memory>>>;
+pub type SequenceStore = Rc<RefCell<StableCell<u64, Memory>>>;
+pub type PermissionStore = Rc<RefCell<StableBTreeMap<String, SharingPermissions, Memory>>>;
+pub type ApiTokenStore = Rc<RefCell<StableBTreeMap<String, ApiToken, Memory>>>;
+
+type Index<K> = Rc<RefCell<StableBTreeMap<K, (), Memory>>>;
+
+/// How far a rebuild of the weight indexes has got: every record up to
+/// `indexed_to` is indexed, the ones after it may not be yet.
+struct IndexRebuild {
+    indexed_to: Option<WeightKey>,
+}
+
+/// Weight records together with the indexes kept in step with them, the
+/// values corrections replaced, the outlier flags raised on them and the
+/// client request IDs that recorded them. Records are keyed by owner, item and
+/// timestamp, so the record map doubles as the per-item index.
+#[derive(Clone)]
+pub struct WeightStore {
+    pub records: Rc<RefCell<StableBTreeMap<WeightKey, Weight, Memory>>>,
+    by_batch: Index<BatchIndexKey>,
+    by_created_at: Index<CreatedAtIndexKey>,
+    by_item: Index<ItemIndexKey>,
+    history: Rc<RefCell<StableBTreeMap<WeightRevisionKey, WeightRevision, Memory>>>,
+    client_requests: Rc<RefCell<StableBTreeMap<ClientRequestKey, ClientRequest, Memory>>>,
+    flags: Rc<RefCell<StableBTreeMap<WeightKey, WeightFlag, Memory>>>,
+    // On the heap: an upgrade during a rebuild finds the indexes out of step
+    // and starts it again.
+    rebuild: Rc<RefCell<Option<IndexRebuild>>>,
+}
+
+impl WeightStore {
+    pub fn init(records: Memory, by_batch: Memory, by_created_at: Memory, by_item: Memory, history: Memory, client_requests: Memory, flags: Memory) -> Self {
+        Self {
+            records: Rc::new(RefCell::new(StableBTreeMap::init(records))),
+            by_batch: Rc::new(RefCell::new(StableBTreeMap::init(by_batch))),
+            by_created_at: Rc::new(RefCell::new(StableBTreeMap::init(by_created_at))),
+            by_item: Rc::new(RefCell::new(StableBTreeMap::init(by_item))),
+            history: Rc::new(RefCell::new(StableBTreeMap::init(history))),
+            client_requests: Rc::new(RefCell::new(StableBTreeMap::init(client_requests))),
+            flags: Rc::new(RefCell::new(StableBTreeMap::init(flags))),
+            rebuild: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn indexes_in_sync(&self) -> bool {
+        let records = self.records.borrow().len();
+        self.by_batch.borrow().len() == records
+            && self.by_created_at.borrow().len() == records
+            && self.by_item.borrow().len() == records
+    }
+
+    /// Empties the indexes to build them again from the records, e.g. after
+    /// upgrading from a release that did not maintain them. Indexing every
+    /// record at once could run out of instructions, so `index_next` does it a
+    /// few records at a time; lookups read the records not indexed yet meanwhile.
+    pub fn start_index_rebuild(&self) {
+        self.by_batch.borrow_mut().clear_new();
+        self.by_created_at.borrow_mut().clear_new();
+        self.by_item.borrow_mut().clear_new();
+        *self.rebuild.borrow_mut() = Some(IndexRebuild { indexed_to: None });
+    }
+
+    /// Indexes up to `limit` more records of a rebuild. Returns whether any
+    /// are left.
+    pub fn index_next(&self, limit: usize) -> bool {
+        let mut rebuild = self.rebuild.borrow_mut();
+        let Some(progress) = rebuild.as_mut() else {
+            return false;
+        };
+        let chunk: Vec<_> = self.records.borrow()
+            .range(after(&progress.indexed_to))
+            .take(limit)
+            .collect();
+        for (key, weight) in &chunk {
+            self.by_batch.borrow_mut().insert(batch_index_key(&weight.batch_id, key), ());
+            self.by_created_at.borrow_mut().insert(created_at_index_key(key), ());
+            self.by_item.borrow_mut().insert(item_index_key(key), ());
+        }
+        if chunk.len() < limit {
+            *rebuild = None;
+            return false;
+        }
+        progress.indexed_to = chunk.into_iter().last().map(|(key, _)| key);
+        true
+    }
+
+    /// The entries of `index` from `start` on that satisfy `in_range`, in index
+    /// order. During a rebuild the records not indexed yet are looked at too,
+    /// through `index_key`.
+    fn lookup<K: Storable + Ord + Clone>(
+        &self,
+        index: &Index<K>,
+        start: K,
+        in_range: impl Fn(&K) -> bool,
+        index_key: impl Fn(&WeightKey, &Weight) -> K,
+    ) -> Vec<K> {
+        let mut entries: BTreeSet<K> = index.borrow()
+            .keys_range(start.clone()..)
+            .take_while(&in_range)
+            .collect();
+        if let Some(progress) = &*self.rebuild.borrow() {
+            entries.extend(self.records.borrow()
+                .range(after(&progress.indexed_to))
+                .map(|(key, weight)| index_key(&key, &weight))
+                .filter(|entry| *entry >= start && in_range(entry)));
+        }
+        entries.into_iter().collect()
+    }
+
+    fn insert(&self, key: WeightKey, weight: Weight) {
+        let previous = self.records.borrow_mut().insert(key.clone(), weight.clone());
+        if let Some(previous) = previous {
+            self.by_batch.borrow_mut().remove(&batch_index_key(&previous.batch_id, &key));
+        }
+        self.by_batch.borrow_mut().insert(batch_index_key(&weight.batch_id, &key), ());
+        self.by_created_at.borrow_mut().insert(created_at_index_key(&key), ());
+        self.by_item.borrow_mut().insert(item_index_key(&key), ());
+    }
+
+    fn remove(&self, key: &WeightKey) {
+        if let Some(weight) = self.records.borrow_mut().remove(key) {
+            self.by_batch.borrow_mut().remove(&batch_index_key(&weight.batch_id, key));
+            self.by_created_at.borrow_mut().remove(&created_at_index_key(key));
+            self.by_item.borrow_mut().remove(&item_index_key(key));
+            self.flags.borrow_mut().remove(key);
+            for revision in self.revision_keys(key) {
+                self.history.borrow_mut().remove(&revision);
+            }
+        }
+    }
+
+    fn revision_keys(&self, key: &WeightKey) -> Vec<WeightRevisionKey> {
+        self.history.borrow()
+            .keys_range(WeightRevisionKey { key: key.clone(), revision: 0 }..)
+            .take_while(|entry| entry.key == *key)
+            .collect()
+    }
+
+    fn resolve(&self, keys: impl Iterator<Item = WeightKey>, include_deleted: bool) -> Vec<Weight> {
+        let records = self.records.borrow();
+        keys.filter_map(|key| records.get(&key))
+            .filter(|w| include_deleted || w.deleted_at.is_none())
+            .collect()
+    }
+}
+
+fn after(key: &Option<WeightKey>) -> (Bound<WeightKey>, Bound<WeightKey>) {
+    match key {
+        Some(key) => (Bound::Excluded(key.clone()), Bound::Unbounded),
+        None => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+fn batch_index_key(batch_id: &str, key: &WeightKey) -> BatchIndexKey {
+    BatchIndexKey {
+        batch_id: batch_id.to_string(),
+        key: key.clone(),
+    }
+}
+
+fn created_at_index_key(key: &WeightKey) -> CreatedAtIndexKey {
+    CreatedAtIndexKey {
+        created_at: key.created_at,
+        item_id: key.item_id.clone(),
+        owner: key.owner,
+    }
+}
+
+fn item_index_key(key: &WeightKey) -> ItemIndexKey {
+    ItemIndexKey {
+        item_id: key.item_id.clone(),
+        created_at: key.created_at,
+        owner: key.owner,
+    }
+}
+
+// The management canister has the shortest principal, which sorts first.
+fn lowest_key(item_id: &str, created_at: u64) -> WeightKey {
+    WeightKey {
+        owner: Principal::management_canister(),
+        item_id: item_id.to_string(),
+        created_at,
+    }
+}
+
 #[derive(Clone)]
 pub struct WeightStorageImpl<T: TimeProvider> {
-    storage: Arc<RefCell<HashMap<WeightKey, Weight>>>,
+    storage: WeightStore,
     time_provider: T,
 }
 
 impl<T: TimeProvider> WeightStorageImpl<T> {
+    #[cfg(test)]
     pub fn new(time_provider: T) -> Self {
+        use crate::memory::test_memory;
         Self {
-            storage: Arc::new(RefCell::new(HashMap::new())),
+            storage: WeightStore::init(test_memory(), test_memory(), test_memory(), test_memory(), test_memory(), test_memory(), test_memory()),
             time_provider,
         }
     }
 
-    pub fn with_storage(storage: Arc<RefCell<HashMap<WeightKey, Weight>>>, time_provider: T) -> Self {
+    pub fn with_storage(storage: WeightStore, time_provider: T) -> Self {
         Self {
             storage,
             time_provider,
         }
     }
+
+    fn set_deleted(&self, key: WeightKey, deleted_at: Option<u64>, with_batch: bool) -> Result<(), WeightTrackerError> {
+        let mut storage = self.storage.records.borrow_mut();
+        if let Some(mut entry) = storage.get(&key) {
+            entry.deleted_at = deleted_at;
+            entry.deleted_with_batch = with_batch;
+            storage.insert(key, entry);
+            Ok(())
+        } else {
+            Err(WeightTrackerError::not_found("Weight not found"))
+        }
+    }
 }
 
 impl<T: TimeProvider> WeightRepository for WeightStorageImpl<T> {
-    fn create(&self, key: WeightKey, weight: Weight) -> Result<(), String> {
-        ic_cdk::println!("WeightRepository: Creating weight");
-        ic_cdk::println!("Key: {:?}", key);
-        ic_cdk::println!("Weight: {:?}", weight);
-        
-        self.storage.borrow_mut().insert(key, weight);
+    fn create(&self, key: WeightKey, weight: Weight) -> Result<(), WeightTrackerError> {
+        self.storage.insert(key, weight);
         Ok(())
     }
 
     fn get_all(&self, batch_id: Option<String>, include_deleted: bool) -> Vec<Weight> {
-        self.storage.borrow()
-            .values()
-            .filter(|w| {
-                match &batch_id {
-                    Some(bid) => w.batch_id == *bid && (include_deleted || w.deleted_at.is_none()),
-                    None => include_deleted || w.deleted_at.is_none()
-                }
-            })
-            .cloned()
-            .collect()
+        match batch_id {
+            Some(batch_id) => {
+                let start = batch_index_key(&batch_id, &lowest_key("", 0));
+                let keys = self.storage.lookup(
+                    &self.storage.by_batch,
+                    start,
+                    |entry| entry.batch_id == batch_id,
+                    |key, weight| batch_index_key(&weight.batch_id, key),
+                );
+                self.storage.resolve(keys.into_iter().map(|entry| entry.key), include_deleted)
+            }
+            None => self.storage.records.borrow()
+                .values()
+                .filter(|w| include_deleted || w.deleted_at.is_none())
+                .collect(),
+        }
     }
 
-    fn update(&self, key: WeightKey, new_weight: f64) -> Result<(), String> {
-        if let Some(entry) = self.storage.borrow_mut().get_mut(&key) {
+    fn update(&self, key: WeightKey, new_weight: f64, changed_by: Principal, reason: Option<String>) -> Result<(), WeightTrackerError> {
+        let mut storage = self.storage.records.borrow_mut();
+        if let Some(mut entry) = storage.get(&key) {
+            let now = self.time_provider.get_time();
+            let revision = WeightRevisionKey {
+                key: key.clone(),
+                revision: self.storage.revision_keys(&key).len() as u32,
+            };
+            self.storage.history.borrow_mut().insert(revision, WeightRevision {
+                weight: entry.weight,
+                valid_from: entry.updated_at,
+                replaced_at: now,
+                replaced_by: changed_by,
+                reason,
+            });
+
             entry.weight = new_weight;
-            entry.updated_at = self.time_provider.get_time();
+            entry.updated_at = now;
+            storage.insert(key, entry);
             Ok(())
         } else {
-            Err("Weight not found".to_string())
+            Err(WeightTrackerError::not_found("Weight not found"))
         }
     }
 
-    fn delete(&self, key: WeightKey) -> Result<(), String> {
-        if let Some(entry) = self.storage.borrow_mut().get_mut(&key) {
-            entry.deleted_at = Some(self.time_provider.get_time());
-            Ok(())
+    fn get_history(&self, key: &WeightKey) -> Vec<WeightRevision> {
+        let history = self.storage.history.borrow();
+        self.storage.revision_keys(key)
+            .into_iter()
+            .filter_map(|revision| history.get(&revision))
+            .collect()
+    }
+
+    fn delete(&self, key: WeightKey) -> Result<(), WeightTrackerError> {
+        self.set_deleted(key, Some(self.time_provider.get_time()), false)
+    }
+
+    fn delete_with_batch(&self, key: WeightKey) -> Result<(), WeightTrackerError> {
+        self.set_deleted(key, Some(self.time_provider.get_time()), true)
+    }
+
+    fn restore(&self, key: WeightKey) -> Result<(), WeightTrackerError> {
+        self.set_deleted(key, None, false)
+    }
+
+    fn purge_deleted_before(&self, cutoff: u64) -> Vec<Weight> {
+        let expired = self.storage.records.borrow()
+            .iter()
+            .filter(|(_, w)| w.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff))
+            .collect::<Vec<_>>();
+        for (key, _) in &expired {
+            self.storage.remove(key);
+        }
+        expired.into_iter().map(|(_, w)| w).collect()
+    }
+
+    fn find_at(&self, item_id: &str, created_at: u64) -> Vec<Weight> {
+        let start = created_at_index_key(&lowest_key(item_id, created_at));
+        let keys = self.storage.lookup(
+            &self.storage.by_created_at,
+            start,
+            |entry| entry.created_at == created_at && entry.item_id == item_id,
+            |key, _| created_at_index_key(key),
+        );
+        let keys = keys.into_iter().map(|entry| WeightKey { owner: entry.owner, item_id: entry.item_id, created_at });
+        self.storage.resolve(keys, true)
+    }
+
+    fn get_created_between(&self, from: u64, to: u64, include_deleted: bool) -> Vec<Weight> {
+        let start = created_at_index_key(&lowest_key("", from));
+        let keys = self.storage.lookup(
+            &self.storage.by_created_at,
+            start,
+            |entry| entry.created_at <= to,
+            |key, _| created_at_index_key(key),
+        );
+        let keys = keys.into_iter().map(|entry| WeightKey { owner: entry.owner, item_id: entry.item_id, created_at: entry.created_at });
+        self.storage.resolve(keys, include_deleted)
+    }
+
+    fn get_for_item(&self, item_id: &str, include_deleted: bool) -> Vec<Weight> {
+        let start = item_index_key(&lowest_key(item_id, 0));
+        let keys = self.storage.lookup(
+            &self.storage.by_item,
+            start,
+            |entry| entry.item_id == item_id,
+            |key, _| item_index_key(key),
+        );
+        let keys = keys.into_iter().map(|entry| WeightKey { owner: entry.owner, item_id: entry.item_id, created_at: entry.created_at });
+        self.storage.resolve(keys, include_deleted)
+    }
+
+    fn set_flag(&self, key: WeightKey, warnings: Vec<OutlierWarning>) {
+        let mut flags = self.storage.flags.borrow_mut();
+        if warnings.is_empty() {
+            flags.remove(&key);
         } else {
-            Err("Weight not found".to_string())
+            flags.insert(key, WeightFlag {
+                warnings,
+                flagged_at: self.time_provider.get_time(),
+                confirmed_by: None,
+                confirmed_at: None,
+            });
+        }
+    }
+
+    fn get_flag(&self, key: &WeightKey) -> Option<WeightFlag> {
+        self.storage.flags.borrow().get(key)
+    }
+
+    fn confirm_flag(&self, key: &WeightKey, confirmed_by: Principal) -> Result<(), WeightTrackerError> {
+        let mut flags = self.storage.flags.borrow_mut();
+        let mut flag = flags.get(key)
+            .ok_or_else(|| WeightTrackerError::not_found("This weight is not flagged"))?;
+        flag.confirmed_by = Some(confirmed_by);
+        flag.confirmed_at = Some(self.time_provider.get_time());
+        flags.insert(key.clone(), flag);
+        Ok(())
+    }
+
+    fn find_client_request(&self, caller: Principal, client_request_id: &str) -> Option<ClientRequest> {
+        self.storage.client_requests.borrow().get(&ClientRequestKey {
+            caller,
+            client_request_id: client_request_id.to_string(),
+        })
+    }
+
+    fn record_client_request(&self, caller: Principal, client_request_id: String, key: WeightKey) {
+        self.storage.client_requests.borrow_mut().insert(
+            ClientRequestKey { caller, client_request_id },
+            ClientRequest { key, recorded_at: self.time_provider.get_time() },
+        );
+    }
+
+    fn forget_client_requests_before(&self, cutoff: u64) -> u64 {
+        let mut client_requests = self.storage.client_requests.borrow_mut();
+        let expired = client_requests.iter()
+            .filter(|(_, request)| request.recorded_at < cutoff)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in &expired {
+            client_requests.remove(key);
         }
+        expired.len() as u64
     }
 }
 
+pub fn init_sequence(memory: Memory) -> SequenceStore {
+    Rc::new(RefCell::new(
+        StableCell::init(memory, 0).expect("failed to open batch sequence")
+    ))
+}
+
 #[derive(Clone)]
 pub struct BatchStorageImpl<T: TimeProvider> {
-    storage: Arc<RefCell<HashMap<String, Batch>>>,
+    storage: BatchStore,
+    sequence: SequenceStore,
     time_provider: T,
 }
 
 impl<T: TimeProvider> BatchStorageImpl<T> {
+    #[cfg(test)]
     pub fn new(time_provider: T) -> Self {
+        use crate::memory::test_memory;
         Self {
-            storage: Arc::new(RefCell::new(HashMap::new())),
+            storage: Rc::new(RefCell::new(StableBTreeMap::init(test_memory()))),
+            sequence: init_sequence(test_memory()),
             time_provider,
         }
     }
 
-    pub fn with_storage(storage: Arc<RefCell<HashMap<String, Batch>>>, time_provider: T) -> Self {
+    pub fn with_storage(storage: BatchStore, sequence: SequenceStore, time_provider: T) -> Self {
         Self {
             storage,
+            sequence,
             time_provider,
         }
     }
 }
 
 impl<T: TimeProvider> BatchRepository for BatchStorageImpl<T> {
-    fn create(&mut self, batch: Batch) -> Result<String, String> {
+    fn next_sequence(&mut self) -> u64 {
+        let mut sequence = self.sequence.borrow_mut();
+        let next = *sequence.get() + 1;
+        sequence.set(next).expect("failed to advance batch sequence");
+        next
+    }
+
+    fn create(&mut self, batch: Batch) -> Result<String, WeightTrackerError> {
         ic_cdk::println!("BatchRepository: Creating new batch");
         ic_cdk::println!("New batch details: {:?}", batch);
         let batch_id = batch.id.clone();
-        self.storage.borrow_mut().insert(batch_id.clone(), batch);
+        let mut storage = self.storage.borrow_mut();
+        if storage.contains_key(&batch_id) {
+            return Err(WeightTrackerError::conflict(&format!("Batch {} already exists", batch_id)));
+        }
+        storage.insert(batch_id.clone(), batch);
         ic_cdk::println!("Batch created with ID: {}", batch_id);
         Ok(batch_id)
     }
 
     fn get(&self, id: &str) -> Option<Batch> {
         ic_cdk::println!("BatchRepository: Looking up batch with ID: {}", id);
-        ic_cdk::println!("Available batches: {:?}", 
-            self.storage.borrow().keys().collect::<Vec<_>>());
-        let result = self.storage.borrow().get(id).cloned();
+        let result = self.storage.borrow().get(&id.to_string());
         ic_cdk::println!("Batch lookup result: {:?}", result);
         result
     }
 
     fn get_all(&self, include_deleted: bool) -> Vec<Batch> {
         self.storage.borrow()
-            .values()
+            .iter()
+            .map(|(_, b)| b)
             .filter(|b| include_deleted || b.deleted_at.is_none())
-            .cloned()
             .collect()
     }
 
-    fn update(&mut self, id: &str, name: String, description: Option<String>) -> Result<(), String> {
+    fn update(&mut self, id: &str, name: String, description: Option<String>, unit: WeightUnit, validation: ValidationRules) -> Result<(), WeightTrackerError> {
         let mut storage = self.storage.borrow_mut();
-        if let Some(batch) = storage.get_mut(id) {
+        if let Some(mut batch) = storage.get(&id.to_string()) {
             batch.name = name;
             batch.description = description;
+            batch.unit = unit;
+            batch.validation = validation;
             batch.updated_at = self.time_provider.get_time();
+            storage.insert(id.to_string(), batch);
             Ok(())
         } else {
-            Err("Batch not found".to_string())
+            Err(WeightTrackerError::not_found("Batch not found"))
         }
     }
 
-    fn delete(&mut self, id: &str) -> Result<(), String> {
+    fn delete(&mut self, id: &str) -> Result<(), WeightTrackerError> {
         let mut storage = self.storage.borrow_mut();
-        if let Some(batch) = storage.get_mut(id) {
+        if let Some(mut batch) = storage.get(&id.to_string()) {
             if batch.deleted_at.is_some() {
-                return Err("Batch is already deleted".to_string());
+                return Err(WeightTrackerError::already_deleted("Batch is already deleted"));
             }
             batch.deleted_at = Some(self.time_provider.get_time());
+            storage.insert(id.to_string(), batch);
+            Ok(())
+        } else {
+            Err(WeightTrackerError::not_found("Batch not found"))
+        }
+    }
+
+    fn restore(&mut self, id: &str) -> Result<(), WeightTrackerError> {
+        let mut storage = self.storage.borrow_mut();
+        if let Some(mut batch) = storage.get(&id.to_string()) {
+            if batch.deleted_at.is_none() {
+                return Err(WeightTrackerError::conflict("Batch is not deleted"));
+            }
+            batch.deleted_at = None;
+            storage.insert(id.to_string(), batch);
             Ok(())
         } else {
-            Err("Batch not found".to_string())
+            Err(WeightTrackerError::not_found("Batch not found"))
+        }
+    }
+
+    fn purge_deleted_before(&mut self, cutoff: u64) -> Vec<Batch> {
+        let mut storage = self.storage.borrow_mut();
+        let expired = storage.iter()
+            .map(|(_, b)| b)
+            .filter(|b| b.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff))
+            .collect::<Vec<_>>();
+        for batch in &expired {
+            storage.remove(&batch.id);
         }
+        expired
     }
 }
 
 #[derive(Clone)]
 pub struct AccessControlImpl {
-    permissions: Arc<RefCell<HashMap<String, SharingPermissions>>>
+    permissions: PermissionStore
 }
 
 impl AccessControlImpl {
+    #[cfg(test)]
     pub fn new() -> Self {
         Self {
-            permissions: Arc::new(RefCell::new(HashMap::new()))
+            permissions: Rc::new(RefCell::new(StableBTreeMap::init(crate::memory::test_memory())))
         }
     }
 
-    pub fn with_storage(storage: Arc<RefCell<HashMap<String, SharingPermissions>>>) -> Self {
+    pub fn with_storage(storage: PermissionStore) -> Self {
         Self {
             permissions: storage
         }
@@ -172,53 +557,228 @@ impl AccessControl for AccessControlImpl {
         
         self.permissions.borrow()
             .get(&data_owner.to_string())
-            .and_then(|p| p.shared_with.get(&accessor))
-            .map(|batch_ids| {
+            .and_then(|p| p.shared_with.get(&accessor).cloned())
+            .map(|grants| {
                 match batch_id {
-                    Some(bid) => batch_ids.contains(bid),
-                    None => !batch_ids.is_empty()
+                    Some(bid) => grants.contains_key(bid),
+                    None => !grants.is_empty()
                 }
             })
             .unwrap_or(false)
     }
 
-    fn share_access(&mut self, owner: Principal, collaborator: Principal, batch_id: String) -> Result<(), String> {
+    fn get_role(&self, data_owner: Principal, accessor: Principal, batch_id: &str) -> Option<Role> {
+        self.permissions.borrow()
+            .get(&data_owner.to_string())
+            .and_then(|p| p.shared_with.get(&accessor)?.get(batch_id).copied())
+    }
+
+    fn share_access(&mut self, owner: Principal, collaborator: Principal, batch_id: String, role: Role) -> Result<(), WeightTrackerError> {
         let mut perms = self.permissions.borrow_mut();
         let owner_key = owner.to_string();
         
-        let sharing = perms.entry(owner_key.clone())
-            .or_insert(SharingPermissions {
+        let mut sharing = perms.get(&owner_key)
+            .unwrap_or(SharingPermissions {
                 owner,
                 shared_with: HashMap::new(),
             });
             
         sharing.shared_with
             .entry(collaborator)
-            .or_insert_with(HashSet::new)
-            .insert(batch_id);
+            .or_default()
+            .insert(batch_id, role);
+        perms.insert(owner_key, sharing);
         
         Ok(())
     }
 
-    fn remove_access(&mut self, owner: Principal, collaborator: Principal) -> Result<(), String> {
+    fn remove_access(&mut self, owner: Principal, collaborator: Principal) -> Result<(), WeightTrackerError> {
         let mut perms = self.permissions.borrow_mut();
         let owner_key = owner.to_string();
         
-        if let Some(sharing) = perms.get_mut(&owner_key) {
+        if let Some(mut sharing) = perms.get(&owner_key) {
             sharing.shared_with.remove(&collaborator);
             if sharing.shared_with.is_empty() {
                 perms.remove(&owner_key);
+            } else {
+                perms.insert(owner_key, sharing);
             }
             Ok(())
         } else {
-            Err("No sharing permissions found".to_string())
+            Err(WeightTrackerError::not_found("No sharing permissions found"))
+        }
+    }
+
+    fn remove_batch_access(&mut self, owner: Principal, collaborator: Principal, batch_id: &str) -> Result<(), WeightTrackerError> {
+        let mut perms = self.permissions.borrow_mut();
+        let owner_key = owner.to_string();
+
+        let mut sharing = perms.get(&owner_key)
+            .ok_or_else(|| WeightTrackerError::not_found("No sharing permissions found"))?;
+        let grants = sharing.shared_with.get_mut(&collaborator)
+            .ok_or_else(|| WeightTrackerError::not_found("Batch is not shared with this user"))?;
+        if grants.remove(batch_id).is_none() {
+            return Err(WeightTrackerError::not_found("Batch is not shared with this user"));
+        }
+
+        if grants.is_empty() {
+            sharing.shared_with.remove(&collaborator);
+        }
+        if sharing.shared_with.is_empty() {
+            perms.remove(&owner_key);
+        } else {
+            perms.insert(owner_key, sharing);
         }
+        Ok(())
     }
 
     fn get_collaborators(&self, owner: Principal) -> Vec<Principal> {
         self.permissions.borrow()
             .get(&owner.to_string())
             .map(|p| p.shared_with.keys().cloned().collect())
-            .unwrap_or_else(Vec::new)
+            .unwrap_or_default()
+    }
+
+    fn get_batch_collaborators(&self, owner: Principal, batch_id: &str) -> Vec<BatchCollaborator> {
+        let mut collaborators: Vec<BatchCollaborator> = self.permissions.borrow()
+            .get(&owner.to_string())
+            .map(|p| p.shared_with.into_iter()
+                .filter_map(|(collaborator, grants)| {
+                    grants.get(batch_id).map(|role| BatchCollaborator { collaborator, role: *role })
+                })
+                .collect())
+            .unwrap_or_default();
+        collaborators.sort_by_key(|c| c.collaborator);
+        collaborators
+    }
+}
+
+/// Audit entries keyed by ID, with indexes by batch and by item.
+#[derive(Clone)]
+pub struct AuditStore {
+    entries: Rc<RefCell<StableBTreeMap<u64, AuditEntry, Memory>>>,
+    by_batch: Index<AuditBatchIndexKey>,
+    by_item: Index<AuditItemIndexKey>,
+}
+
+impl AuditStore {
+    pub fn init(entries: Memory, by_batch: Memory, by_item: Memory) -> Self {
+        Self {
+            entries: Rc::new(RefCell::new(StableBTreeMap::init(entries))),
+            by_batch: Rc::new(RefCell::new(StableBTreeMap::init(by_batch))),
+            by_item: Rc::new(RefCell::new(StableBTreeMap::init(by_item))),
+        }
+    }
+
+    fn resolve(&self, ids: impl Iterator<Item = u64>) -> Vec<AuditEntry> {
+        let entries = self.entries.borrow();
+        ids.filter_map(|id| entries.get(&id)).collect()
+    }
+}
+
+fn first_id_after(after: Option<u64>) -> u64 {
+    after.map_or(0, |id| id.saturating_add(1))
+}
+
+#[derive(Clone)]
+pub struct AuditLogImpl<T: TimeProvider> {
+    storage: AuditStore,
+    time_provider: T,
+}
+
+impl<T: TimeProvider> AuditLogImpl<T> {
+    #[cfg(test)]
+    pub fn new(time_provider: T) -> Self {
+        use crate::memory::test_memory;
+        Self {
+            storage: AuditStore::init(test_memory(), test_memory(), test_memory()),
+            time_provider,
+        }
+    }
+
+    pub fn with_storage(storage: AuditStore, time_provider: T) -> Self {
+        Self {
+            storage,
+            time_provider,
+        }
+    }
+}
+
+impl<T: TimeProvider> AuditLog for AuditLogImpl<T> {
+    fn record(&self, event: AuditEvent) -> u64 {
+        let mut entries = self.storage.entries.borrow_mut();
+        let id = entries.last_key_value().map_or(0, |(id, _)| id + 1);
+
+        self.storage.by_batch.borrow_mut().insert(AuditBatchIndexKey { batch_id: event.batch_id.clone(), id }, ());
+        if let AuditTarget::Weight(key) = &event.target {
+            self.storage.by_item.borrow_mut().insert(AuditItemIndexKey { item_id: key.item_id.clone(), id }, ());
+        }
+        entries.insert(id, AuditEntry {
+            id,
+            recorded_at: self.time_provider.get_time(),
+            event,
+        });
+        id
+    }
+
+    fn get_for_batch(&self, batch_id: &str, after: Option<u64>, limit: usize) -> Vec<AuditEntry> {
+        let start = AuditBatchIndexKey { batch_id: batch_id.to_string(), id: first_id_after(after) };
+        let ids = self.storage.by_batch.borrow()
+            .keys_range(start..)
+            .take_while(|entry| entry.batch_id == batch_id)
+            .take(limit)
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        self.storage.resolve(ids.into_iter())
+    }
+
+    fn get_for_item(&self, item_id: &str, after: Option<u64>, limit: usize) -> Vec<AuditEntry> {
+        let start = AuditItemIndexKey { item_id: item_id.to_string(), id: first_id_after(after) };
+        let ids = self.storage.by_item.borrow()
+            .keys_range(start..)
+            .take_while(|entry| entry.item_id == item_id)
+            .take(limit)
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        self.storage.resolve(ids.into_iter())
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiTokenStorageImpl {
+    storage: ApiTokenStore,
+}
+
+impl ApiTokenStorageImpl {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self {
+            storage: Rc::new(RefCell::new(StableBTreeMap::init(crate::memory::test_memory()))),
+        }
+    }
+
+    pub fn with_storage(storage: ApiTokenStore) -> Self {
+        Self { storage }
+    }
+}
+
+impl ApiTokenRepository for ApiTokenStorageImpl {
+    fn create(&self, hash: String, token: ApiToken) {
+        self.storage.borrow_mut().insert(hash, token);
+    }
+
+    fn get(&self, hash: &str) -> Option<ApiToken> {
+        self.storage.borrow().get(&hash.to_string())
+    }
+
+    fn get_for_owner(&self, owner: Principal) -> Vec<(String, ApiToken)> {
+        self.storage.borrow()
+            .iter()
+            .filter(|(_, token)| token.owner == owner)
+            .collect()
+    }
+
+    fn delete(&self, hash: &str) -> Option<ApiToken> {
+        self.storage.borrow_mut().remove(&hash.to_string())
     }
 }
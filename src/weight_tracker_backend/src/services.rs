@@ -1,48 +1,435 @@
 use candid::Principal;
+use chrono::FixedOffset;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::csv_import;
+use crate::errors::WeightTrackerError;
+use crate::export::ExportWriter;
 use crate::models::*;
+use crate::pagination::{self, ExportCursor, WeightCursor};
 use crate::traits::*;
 
-pub struct WeightService<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider> {
+const MAX_REASON_LENGTH: usize = 500;
+const MAX_NOTES_LENGTH: usize = 500;
+/// Heaviest weight accepted, whatever unit it is recorded in.
+const MAX_WEIGHT_KG: f64 = 10_000.0;
+const MAX_ITEM_ID_PATTERN_LENGTH: usize = 100;
+const MAX_BULK_WEIGHTS: usize = 1000;
+const DEFAULT_EXPORT_CHUNK_SIZE: u32 = 1000;
+const MAX_EXPORT_CHUNK_SIZE: u32 = 5000;
+const MAX_CLIENT_REQUEST_ID_LENGTH: usize = 100;
+/// How long a client request ID keeps answering retries, in nanoseconds.
+const CLIENT_REQUEST_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+const DEFAULT_PERCENTILES: [f64; 4] = [10.0, 25.0, 75.0, 90.0];
+const MAX_PERCENTILES: usize = 20;
+const MAX_HISTOGRAM_BUCKETS: usize = 1000;
+/// UTC offsets in use range from UTC-12 to UTC+14.
+const MIN_UTC_OFFSET_MINUTES: i32 = -12 * 60;
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// Resolves the percentiles batch stats should report, rejecting values
+/// outside 0 to 100.
+pub fn percentiles(requested: Option<Vec<f64>>) -> Result<Vec<f64>, WeightTrackerError> {
+    let percentiles = requested.unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec());
+    if percentiles.len() > MAX_PERCENTILES {
+        return Err(WeightTrackerError::validation(
+            "percentiles",
+            &format!("At most {} percentiles can be requested", MAX_PERCENTILES),
+        ));
+    }
+    if percentiles.iter().any(|p| !(0.0..=100.0).contains(p)) {
+        return Err(WeightTrackerError::validation("percentiles", "Percentiles must be between 0 and 100"));
+    }
+    Ok(percentiles)
+}
+
+/// Checks a weight against the global limits and the batch's rules, whose
+/// bounds are in the batch's `unit`.
+fn validate_weight(kilograms: f64, rules: &ValidationRules, unit: WeightUnit) -> Result<(), WeightTrackerError> {
+    if !kilograms.is_finite() || kilograms <= 0.0 {
+        ic_cdk::println!("Invalid weight value: {}", kilograms);
+        return Err(WeightTrackerError::validation("weight", "Invalid weight value: weight must be positive"));
+    }
+    if kilograms > MAX_WEIGHT_KG {
+        ic_cdk::println!("Weight value out of range: {} kg", kilograms);
+        return Err(WeightTrackerError::validation(
+            "weight",
+            &format!("Weight value out of reasonable range: at most {} kg", MAX_WEIGHT_KG),
+        ));
+    }
+    if let Some(min) = rules.min_weight.filter(|min| kilograms < unit.to_kilograms(*min)) {
+        return Err(WeightTrackerError::validation(
+            "weight",
+            &format!("Weight is below this batch's minimum of {} {}", min, unit.symbol()),
+        ));
+    }
+    if let Some(max) = rules.max_weight.filter(|max| kilograms > unit.to_kilograms(*max)) {
+        return Err(WeightTrackerError::validation(
+            "weight",
+            &format!("Weight is above this batch's maximum of {} {}", max, unit.symbol()),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_rules(rules: &ValidationRules, unit: WeightUnit) -> Result<(), WeightTrackerError> {
+    for bound in [rules.min_weight, rules.max_weight].into_iter().flatten() {
+        if !bound.is_finite() || bound <= 0.0 || unit.to_kilograms(bound) > MAX_WEIGHT_KG {
+            return Err(WeightTrackerError::validation(
+                "validation",
+                &format!("Weight limits must be positive and at most {} kg", MAX_WEIGHT_KG),
+            ));
+        }
+    }
+    if let (Some(min), Some(max)) = (rules.min_weight, rules.max_weight) {
+        if min > max {
+            return Err(WeightTrackerError::validation("validation", "Minimum weight cannot exceed the maximum"));
+        }
+    }
+    if let Some(pattern) = &rules.item_id_pattern {
+        if pattern.is_empty() || pattern.chars().count() > MAX_ITEM_ID_PATTERN_LENGTH {
+            return Err(WeightTrackerError::validation(
+                "validation",
+                &format!("Item ID pattern must be between 1 and {} characters", MAX_ITEM_ID_PATTERN_LENGTH),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Pages through audit entries in log order, keeping those `visible` allows.
+/// `fetch` returns up to `limit` entries with an ID greater than `after`.
+fn audit_page(
+    request: AuditPageRequest,
+    fetch: impl Fn(Option<u64>, usize) -> Vec<AuditEntry>,
+    visible: impl Fn(&AuditEntry) -> bool,
+) -> Result<AuditPage, WeightTrackerError> {
+    let page_size = pagination::page_size(request.page_size)?;
+    let mut after = match request.cursor {
+        Some(cursor) => Some(pagination::decode_cursor::<u64>(&cursor)?),
+        None => None,
+    };
+
+    let mut entries = Vec::new();
+    loop {
+        let chunk = fetch(after, page_size + 1);
+        let exhausted = chunk.len() <= page_size;
+        for entry in chunk {
+            after = Some(entry.id);
+            if visible(&entry) {
+                entries.push(entry);
+                if entries.len() > page_size {
+                    break;
+                }
+            }
+        }
+        if exhausted || entries.len() > page_size {
+            break;
+        }
+    }
+
+    let has_more = entries.len() > page_size;
+    entries.truncate(page_size);
+    let next_cursor = if has_more {
+        entries.last().map(|entry| pagination::encode_cursor(&entry.id))
+    } else {
+        None
+    };
+    Ok(AuditPage { entries, next_cursor })
+}
+
+/// Checks buckets that do not depend on the weights. Bands must be named
+/// and must not overlap.
+fn validate_buckets(buckets: &HistogramBuckets) -> Result<(), WeightTrackerError> {
+    let invalid = |reason: &str| Err(WeightTrackerError::validation("buckets", reason));
+    match buckets {
+        HistogramBuckets::Width(width) if !width.is_finite() || *width <= 0.0 => invalid("Bucket width must be positive"),
+        HistogramBuckets::Count(count) if *count == 0 || *count as usize > MAX_HISTOGRAM_BUCKETS => {
+            invalid(&format!("Bucket count must be between 1 and {}", MAX_HISTOGRAM_BUCKETS))
+        }
+        HistogramBuckets::Bands(bands) => {
+            if bands.is_empty() || bands.len() > MAX_HISTOGRAM_BUCKETS {
+                return invalid(&format!("Give between 1 and {} bands", MAX_HISTOGRAM_BUCKETS));
+            }
+            if bands.iter().any(|band| band.name.trim().is_empty()) {
+                return invalid("Every band needs a name");
+            }
+            if bands.iter().any(|band| !band.min_weight.is_finite() || !band.max_weight.is_finite() || band.min_weight >= band.max_weight) {
+                return invalid("Every band's minimum must be below its maximum");
+            }
+            let mut sorted: Vec<_> = bands.iter().collect();
+            sorted.sort_by(|a, b| a.min_weight.total_cmp(&b.min_weight));
+            if sorted.windows(2).any(|pair| pair[1].min_weight < pair[0].max_weight) {
+                return invalid("Bands cannot overlap");
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A create request that passed validation.
+enum PreparedWeight {
+    New { key: WeightKey, weight: Weight, client_request_id: Option<String>, warnings: Vec<OutlierWarning> },
+    /// A retry of a create that already recorded the weight with this key.
+    Replayed(WeightKey),
+}
+
+/// Live weights of the batches a call has checked rows against, together
+/// with the rows prepared so far. A bulk create reads each batch once rather
+/// than once per row, and checks each row against the rows before it too.
+#[derive(Default)]
+struct BatchWeights(HashMap<String, Vec<Weight>>);
+
+impl BatchWeights {
+    fn get<T: WeightRepository>(&mut self, weight_repo: &T, batch_id: &str) -> &[Weight] {
+        self.0.entry(batch_id.to_string())
+            .or_insert_with(|| weight_repo.get_all(Some(batch_id.to_string()), false))
+    }
+
+    fn add(&mut self, weight: Weight) {
+        self.0.entry(weight.batch_id.clone()).or_default().push(weight);
+    }
+}
+
+pub struct WeightService<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider, L: AuditLog> {
     weight_repo: T,
     batch_repo: B,
     access_control: A,
     time_provider: TP,
+    audit_log: L,
 }
 
-impl<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider> WeightService<T, B, A, TP> {
-    pub fn new(weight_repo: T, batch_repo: B, access_control: A, time_provider: TP) -> Self {
+impl<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider, L: AuditLog> WeightService<T, B, A, TP, L> {
+    pub fn new(weight_repo: T, batch_repo: B, access_control: A, time_provider: TP, audit_log: L) -> Self {
         Self {
             weight_repo,
             batch_repo,
             access_control,
             time_provider,
+            audit_log,
+        }
+    }
+
+    /// Records a weight, or returns the key recorded earlier when the request
+    /// is a retry of one with the same client request ID. Weights that look
+    /// like data-entry errors are recorded with warnings.
+    pub fn create_weight(&self, request: CreateWeightRequest, caller: Principal) -> Result<CreateWeightResponse, WeightTrackerError> {
+        let now = self.time_provider.get_time();
+        match self.prepare_weight(request, caller, now, &mut BatchWeights::default())? {
+            PreparedWeight::New { key, weight, client_request_id, warnings } => {
+                let key = self.store_weight(key, weight, client_request_id, warnings.clone(), caller)?;
+                Ok(CreateWeightResponse { key, warnings })
+            }
+            PreparedWeight::Replayed(key) => {
+                let warnings = self.weight_repo.get_flag(&key).map(|flag| flag.warnings).unwrap_or_default();
+                Ok(CreateWeightResponse { key, warnings })
+            }
+        }
+    }
+
+    /// Records many weights at once, checking each one like `create_weight`.
+    /// With `all_or_nothing`, nothing is written unless every row is valid.
+    pub fn create_weights(&self, requests: Vec<CreateWeightRequest>, all_or_nothing: bool, caller: Principal) -> Result<CreateWeightsResponse, WeightTrackerError> {
+        if requests.len() > MAX_BULK_WEIGHTS {
+            return Err(WeightTrackerError::validation(
+                "requests",
+                &format!("At most {} weights can be created at once", MAX_BULK_WEIGHTS),
+            ));
+        }
+
+        let now = self.time_provider.get_time();
+        let mut batch_weights = BatchWeights::default();
+        let rows = requests.into_iter()
+            .map(|request| self.prepare_weight(request, caller, now, &mut batch_weights))
+            .collect();
+        let (results, created) = self.store_rows(rows, all_or_nothing, caller);
+        Ok(CreateWeightsResponse { results, created })
+    }
+
+    /// Records the weights of a CSV file in one batch, keeping the timestamps
+    /// the file gives them. Each row is checked like `create_weight`.
+    pub fn import_weights_csv(&self, request: CsvImportRequest, caller: Principal) -> Result<CsvImportReport, WeightTrackerError> {
+        let batch = self.batch_repo.get(&request.batch_id)
+            .ok_or_else(|| WeightTrackerError::not_found("Invalid batch ID: batch not found"))?;
+        if !self.access_control.has_permission(batch.owner, caller, &batch.id, Permission::RecordWeights) {
+            return Err(WeightTrackerError::unauthorized("You don't have access to add weights to this batch"));
+        }
+        if batch.deleted_at.is_some() {
+            return Err(WeightTrackerError::already_deleted("Cannot add weights to a deleted batch"));
+        }
+
+        let mut records = csv_import::parse(&request.csv)
+            .map_err(|e| WeightTrackerError::validation("csv", &e))?
+            .into_iter();
+        let header = records.next()
+            .ok_or_else(|| WeightTrackerError::validation("csv", "CSV has no header line"))?;
+        let records: Vec<_> = records.collect();
+        if records.len() > MAX_BULK_WEIGHTS {
+            return Err(WeightTrackerError::validation(
+                "csv",
+                &format!("At most {} rows can be imported at once", MAX_BULK_WEIGHTS),
+            ));
         }
+
+        let column = |name: &str| {
+            header.fields.iter()
+                .position(|field| field.trim() == name.trim())
+                .ok_or_else(|| WeightTrackerError::validation("columns", &format!("CSV has no column named '{}'", name)))
+        };
+        let columns = &request.columns;
+        let item_id_column = column(&columns.item_id)?;
+        let weight_column = column(&columns.weight)?;
+        let timestamp_column = columns.timestamp.as_deref().map(column).transpose()?;
+        let notes_column = columns.notes.as_deref().map(column).transpose()?;
+
+        let now = self.time_provider.get_time();
+        let mut batch_weights = BatchWeights::default();
+        let lines: Vec<u64> = records.iter().map(|record| record.line).collect();
+        let rows = records.into_iter()
+            .map(|record| {
+                let field = |index: usize| record.fields.get(index).map(|value| value.trim()).unwrap_or_default();
+                let weight = field(weight_column).parse::<f64>()
+                    .map_err(|_| WeightTrackerError::validation("weight", &format!("'{}' is not a number", field(weight_column))))?;
+                let created_at = match timestamp_column {
+                    Some(index) => csv_import::parse_timestamp(field(index))
+                        .map_err(|e| WeightTrackerError::validation("timestamp", &e))?,
+                    None => now,
+                };
+                if created_at > now {
+                    return Err(WeightTrackerError::validation("timestamp", "Timestamp cannot be in the future"));
+                }
+                let weight_request = CreateWeightRequest {
+                    owner_override: None,
+                    batch_id: batch.id.clone(),
+                    item_id: field(item_id_column).to_string(),
+                    weight,
+                    unit: request.unit,
+                    notes: notes_column.map(|index| field(index).to_string()),
+                    client_request_id: None,
+                };
+                self.prepare_weight(weight_request, caller, created_at, &mut batch_weights)
+            })
+            .collect();
+
+        let (outcomes, created) = self.store_rows(rows, request.all_or_nothing, caller);
+        let rows = lines.into_iter()
+            .zip(outcomes)
+            .map(|(line, outcome)| CsvImportRow { line, outcome })
+            .collect();
+        Ok(CsvImportReport { rows, created })
     }
 
-    pub fn create_weight(&self, request: CreateWeightRequest, caller: Principal) -> Result<String, String> {
+    /// Writes the rows that were prepared successfully, or none of them when
+    /// `all_or_nothing` is set and any row failed. A row that would overwrite
+    /// an earlier row of the same call, reuses its client request ID, or repeats
+    /// its item in a batch that allows one weight per item, fails. Replayed rows
+    /// were recorded before, so they are reported either way.
+    fn store_rows(
+        &self,
+        rows: Vec<Result<PreparedWeight, WeightTrackerError>>,
+        all_or_nothing: bool,
+        caller: Principal,
+    ) -> (Vec<CreateWeightOutcome>, u64) {
+        let mut seen = HashSet::new();
+        let mut seen_request_ids = HashSet::new();
+        let mut seen_items = HashSet::new();
+        let mut allows_duplicates = HashMap::new();
+        let rows: Vec<_> = rows.into_iter()
+            .map(|row| row.and_then(|prepared| {
+                if let PreparedWeight::New { key, weight, client_request_id, .. } = &prepared {
+                    if !seen.insert(key.clone()) {
+                        return Err(WeightTrackerError::conflict("This item already has a weight at this time earlier in the request"));
+                    }
+                    if client_request_id.as_ref().is_some_and(|id| !seen_request_ids.insert(id.clone())) {
+                        return Err(WeightTrackerError::conflict("This client request ID is used earlier in the request"));
+                    }
+                    let allow_duplicates = *allows_duplicates.entry(weight.batch_id.clone()).or_insert_with(|| {
+                        self.batch_repo.get(&weight.batch_id).is_none_or(|batch| batch.validation.allow_duplicate_item_ids)
+                    });
+                    if !allow_duplicates && !seen_items.insert((weight.batch_id.clone(), weight.item_id.clone())) {
+                        return Err(WeightTrackerError::validation("item_id", "This batch already has a weight for this item"));
+                    }
+                }
+                Ok(prepared)
+            }))
+            .collect();
+
+        if all_or_nothing && rows.iter().any(Result::is_err) {
+            let outcomes = rows.into_iter()
+                .map(|row| match row {
+                    Ok(PreparedWeight::Replayed(key)) => CreateWeightOutcome::Replayed(key),
+                    Ok(PreparedWeight::New { .. }) => CreateWeightOutcome::Skipped,
+                    Err(e) => CreateWeightOutcome::Failed(e),
+                })
+                .collect();
+            return (outcomes, 0);
+        }
+
+        let mut created = 0;
+        let outcomes = rows.into_iter()
+            .map(|row| match row {
+                Ok(PreparedWeight::New { key, weight, client_request_id, warnings }) => {
+                    match self.store_weight(key, weight, client_request_id, warnings, caller) {
+                        Ok(key) => {
+                            created += 1;
+                            CreateWeightOutcome::Created(key)
+                        }
+                        Err(e) => CreateWeightOutcome::Failed(e),
+                    }
+                }
+                Ok(PreparedWeight::Replayed(key)) => CreateWeightOutcome::Replayed(key),
+                Err(e) => CreateWeightOutcome::Failed(e),
+            })
+            .collect();
+        (outcomes, created)
+    }
+
+    /// Validates a request and builds the weight it would record at
+    /// `created_at`, unless its client request ID already recorded one.
+    /// `batch_weights` holds the batches earlier rows of the call looked at,
+    /// and the weights those rows would record.
+    fn prepare_weight(
+        &self,
+        request: CreateWeightRequest,
+        caller: Principal,
+        created_at: u64,
+        batch_weights: &mut BatchWeights,
+    ) -> Result<PreparedWeight, WeightTrackerError> {
         ic_cdk::println!("Starting create_weight process");
         ic_cdk::println!("Request details: {:?}", request);
         ic_cdk::println!("Caller: {:?}", caller);
 
-        if request.weight <= 0.0 {
-            ic_cdk::println!("Invalid weight value: {}", request.weight);
-            return Err("Invalid weight value: weight must be positive".to_string());
-        }
-
-        if request.weight > 10000.0 {
-            ic_cdk::println!("Weight value out of range: {}", request.weight);
-            return Err("Weight value out of reasonable range".to_string());
+        if let Some(id) = &request.client_request_id {
+            if id.is_empty() || id.chars().count() > MAX_CLIENT_REQUEST_ID_LENGTH {
+                return Err(WeightTrackerError::validation(
+                    "client_request_id",
+                    &format!("Client request ID must be 1 to {} characters long", MAX_CLIENT_REQUEST_ID_LENGTH),
+                ));
+            }
+            let now = self.time_provider.get_time();
+            if let Some(earlier) = self.weight_repo.find_client_request(caller, id) {
+                if now.saturating_sub(earlier.recorded_at) < CLIENT_REQUEST_WINDOW {
+                    ic_cdk::println!("Client request {} already recorded {:?}", id, earlier.key);
+                    return Ok(PreparedWeight::Replayed(earlier.key));
+                }
+            }
         }
 
         if request.item_id.trim().is_empty() {
             ic_cdk::println!("Empty item ID provided");
-            return Err("Item ID cannot be empty".to_string());
+            return Err(WeightTrackerError::validation("item_id", "Item ID cannot be empty"));
         }
 
-        // Debug: List all available batches
-        let all_batches = self.batch_repo.get_all(true);
-        ic_cdk::println!("All available batches: {:?}", 
-            all_batches.iter().map(|b| &b.id).collect::<Vec<_>>());
+        let notes = request.notes
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty());
+        if notes.as_ref().is_some_and(|n| n.chars().count() > MAX_NOTES_LENGTH) {
+            return Err(WeightTrackerError::validation(
+                "notes",
+                &format!("Notes cannot be longer than {} characters", MAX_NOTES_LENGTH),
+            ));
+        }
 
         // Get the batch to determine the true owner
         ic_cdk::println!("Fetching batch with ID: {}", request.batch_id);
@@ -53,24 +440,35 @@ impl<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider
             },
             None => {
                 ic_cdk::println!("Batch not found: {}", request.batch_id);
-                return Err("Invalid batch ID".to_string());
+                return Err(WeightTrackerError::not_found("Invalid batch ID: batch not found"));
             }
         };
 
-        // Check if caller has access
+        // Check if caller has access before anything tells them about the batch
         ic_cdk::println!("Checking access for caller {} to batch {}", caller, request.batch_id);
-        if !self.access_control.has_access(batch.owner, caller, Some(&request.batch_id)) {
+        if !self.access_control.has_permission(batch.owner, caller, &request.batch_id, Permission::RecordWeights) {
             ic_cdk::println!("Access denied for caller {} to batch {}", caller, request.batch_id);
-            return Err("Unauthorized: You don't have access to add weights to this batch".to_string());
+            return Err(WeightTrackerError::unauthorized("You don't have access to add weights to this batch"));
         }
 
-        let now = self.time_provider.get_time();
-        ic_cdk::println!("Current timestamp: {}", now);
+        // The purge would remove the batch and leave its new weights behind
+        if batch.deleted_at.is_some() {
+            return Err(WeightTrackerError::already_deleted("Cannot add weights to a deleted batch"));
+        }
+
+        let unit = request.unit.unwrap_or(batch.unit);
+        let kilograms = unit.to_kilograms(request.weight);
+        let live_weights = batch_weights.get(&self.weight_repo, &batch.id);
+        self.validate_new_weight(&batch, &request.item_id, kilograms, live_weights)?;
+
+        if self.weight_repo.find_at(&request.item_id, created_at).iter().any(|w| w.owner == batch.owner) {
+            return Err(WeightTrackerError::conflict("This item already has a weight recorded at this time"));
+        }
 
         let weight_key = WeightKey {
             owner: batch.owner,
             item_id: request.item_id.clone(),
-            created_at: now,
+            created_at,
         };
         ic_cdk::println!("Created weight key: {:?}", weight_key);
         
@@ -78,44 +476,320 @@ impl<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider
             owner: batch.owner,
             batch_id: request.batch_id,
             item_id: request.item_id,
-            weight: request.weight,
-            created_at: now,
-            updated_at: now,
+            weight: kilograms,
+            created_at,
+            updated_at: created_at,
             deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: unit,
+            notes,
         };
         ic_cdk::println!("Created weight entry: {:?}", weight_entry);
+        let warnings = self.outlier_warnings(&weight_entry, live_weights, None);
+        batch_weights.add(weight_entry.clone());
+        Ok(PreparedWeight::New {
+            key: weight_key,
+            weight: weight_entry,
+            client_request_id: request.client_request_id,
+            warnings,
+        })
+    }
+
+    /// Compares a weight with the other live weights of its batch and with
+    /// the owner's previous reading of the item. `existing` is the weight's
+    /// own key when it is already stored.
+    fn outlier_warnings(&self, weight: &Weight, live_weights: &[Weight], existing: Option<&WeightKey>) -> Vec<OutlierWarning> {
+        let is_other = |w: &Weight| existing.is_none_or(|key| w.key() != *key);
+        let batch_weights: Vec<f64> = live_weights
+            .iter()
+            .filter(|w| is_other(w))
+            .map(|w| w.weight)
+            .collect();
+        let previous = self.weight_repo.get_for_item(&weight.item_id, false)
+            .into_iter()
+            .rfind(|w| w.owner == weight.owner && w.created_at < weight.created_at && is_other(w))
+            .map(|w| (w.weight, weight.created_at - w.created_at));
+        OutlierWarning::detect(weight.weight, &batch_weights, previous)
+    }
 
-        match self.weight_repo.create(weight_key, weight_entry) {
+    fn store_weight(
+        &self,
+        weight_key: WeightKey,
+        weight_entry: Weight,
+        client_request_id: Option<String>,
+        warnings: Vec<OutlierWarning>,
+        caller: Principal,
+    ) -> Result<WeightKey, WeightTrackerError> {
+        let event = AuditEvent::weight(caller, AuditAction::Create, &weight_entry, None, Some(&weight_entry));
+        match self.weight_repo.create(weight_key.clone(), weight_entry) {
             Ok(_) => {
                 ic_cdk::println!("Weight successfully created");
-                Ok("Weight created successfully".to_string())
+                self.audit_log.record(event);
+                if !warnings.is_empty() {
+                    self.weight_repo.set_flag(weight_key.clone(), warnings);
+                }
+                if let Some(id) = client_request_id {
+                    self.weight_repo.record_client_request(caller, id, weight_key.clone());
+                }
+                Ok(weight_key)
             },
             Err(e) => {
-                ic_cdk::println!("Error creating weight: {}", e);
+                ic_cdk::println!("Error creating weight: {:?}", e);
                 Err(e)
             }
         }
     }
 
-    pub fn get_weights(&self, batch_id: Option<String>, include_deleted: bool, caller: Principal) -> Vec<Weight> {
-        self.weight_repo.get_all(batch_id, include_deleted)
+    /// Checks a weight about to be recorded in `batch` against the batch's rules,
+    /// given the batch's live weights.
+    fn validate_new_weight(&self, batch: &Batch, item_id: &str, kilograms: f64, live_weights: &[Weight]) -> Result<(), WeightTrackerError> {
+        let rules = &batch.validation;
+        if !rules.item_id_matches(item_id) {
+            return Err(WeightTrackerError::validation(
+                "item_id",
+                &format!("Item ID must match this batch's pattern '{}'", rules.item_id_pattern.as_deref().unwrap_or_default()),
+            ));
+        }
+        validate_weight(kilograms, rules, batch.unit)?;
+        if !rules.allow_duplicate_item_ids && live_weights.iter().any(|w| w.item_id == item_id) {
+            return Err(WeightTrackerError::validation("item_id", "This batch already has a weight for this item"));
+        }
+        Ok(())
+    }
+
+    pub fn get_weights(&self, batch_id: Option<String>, include_deleted: bool, unit: WeightUnit, caller: Principal) -> Vec<Weight> {
+        self.visible_to(caller, self.weight_repo.get_all(batch_id, include_deleted))
             .into_iter()
-            .filter(|w| self.access_control.has_access(w.owner, caller, Some(&w.batch_id)))
+            .map(|w| w.in_unit(unit))
+            .collect()
+    }
+
+    /// The readings of one owner's item that the caller can read, across
+    /// batches, with the change between each and the one before.
+    pub fn get_item_timeline(&self, item_id: &str, request: ItemTimelineRequest, caller: Principal) -> Result<ItemTimeline, WeightTrackerError> {
+        if item_id.trim().is_empty() {
+            return Err(WeightTrackerError::validation("item_id", "Item ID cannot be empty"));
+        }
+        if let (Some(from), Some(to)) = (request.from, request.to) {
+            if from > to {
+                return Err(WeightTrackerError::validation("from", "Start of the range cannot be after its end"));
+            }
+        }
+
+        let owner = request.owner.unwrap_or(caller);
+        let unit = request.unit.unwrap_or_default();
+        let weights: Vec<Weight> = self.visible_to(caller, self.weight_repo.get_for_item(item_id, false))
+            .into_iter()
+            .filter(|w| w.owner == owner)
+            .filter(|w| request.from.is_none_or(|from| w.created_at >= from) && request.to.is_none_or(|to| w.created_at <= to))
+            .map(|w| w.in_unit(unit))
+            .collect();
+        if weights.is_empty() {
+            return Err(WeightTrackerError::not_found("No readings found for this item"));
+        }
+        Ok(ItemTimeline::from_weights(item_id.to_string(), weights, unit))
+    }
+
+    fn visible_to(&self, caller: Principal, weights: Vec<Weight>) -> Vec<Weight> {
+        weights.into_iter()
+            .filter(|w| self.access_control.has_permission(w.owner, caller, &w.batch_id, Permission::ReadWeights))
             .collect()
     }
 
-    pub fn update_weight(&self, item_id: String, created_at: u64, weight: f64, caller: Principal) -> Result<String, String> {
-        let weights = self.weight_repo.get_all(None, true);
-        let weight_entry = weights.iter()
-            .find(|w| w.item_id == item_id && w.created_at == created_at)
-            .ok_or_else(|| "Weight not found".to_string())?;
+    /// Finds the weight identified by item and timestamp, preferring the one
+    /// the caller can access if several owners recorded the same pair.
+    fn find_weight(&self, item_id: &str, created_at: u64, caller: Principal) -> Result<Weight, WeightTrackerError> {
+        let mut candidates = self.weight_repo.find_at(item_id, created_at);
+        if candidates.is_empty() {
+            return Err(WeightTrackerError::not_found("Weight not found"));
+        }
+        let accessible = candidates.iter()
+            .position(|w| self.access_control.has_access(w.owner, caller, Some(&w.batch_id)))
+            .unwrap_or(0);
+        Ok(candidates.swap_remove(accessible))
+    }
+
+    pub fn get_weights_page(&self, request: WeightPageRequest, caller: Principal) -> Result<WeightPage, WeightTrackerError> {
+        let unit = request.unit.unwrap_or_default();
+        let mut filter = request.filter;
+        if let (Some(from), Some(to)) = (filter.created_from, filter.created_to) {
+            if from > to {
+                return Err(WeightTrackerError::validation("created_from", "Start of the created_at range is after its end"));
+            }
+        }
+        if let (Some(min), Some(max)) = (filter.min_weight, filter.max_weight) {
+            if min > max {
+                return Err(WeightTrackerError::validation("min_weight", "Minimum weight is greater than maximum weight"));
+            }
+        }
+
+        filter.min_weight = filter.min_weight.map(|min| unit.to_kilograms(min));
+        filter.max_weight = filter.max_weight.map(|max| unit.to_kilograms(max));
+
+        let sort = request.sort.unwrap_or_default();
+        let page_size = pagination::page_size(request.page_size)?;
+        let resume_after = match request.cursor {
+            Some(cursor) => {
+                let cursor: WeightCursor = pagination::decode_cursor(&cursor)?;
+                if cursor.sort != sort {
+                    return Err(WeightTrackerError::validation("cursor", "Cursor was issued for a different sort order"));
+                }
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        // Narrow the scan with whichever index the filter allows.
+        let candidates = match (&filter.batch_id, filter.created_from, filter.created_to) {
+            (None, from, to) if from.is_some() || to.is_some() => self.weight_repo.get_created_between(
+                from.unwrap_or(0),
+                to.unwrap_or(u64::MAX),
+                filter.include_deleted,
+            ),
+            (batch_id, _, _) => self.weight_repo.get_all(batch_id.clone(), filter.include_deleted),
+        };
+
+        let mut matching: Vec<(WeightCursor, Weight)> = self.visible_to(caller, candidates)
+            .into_iter()
+            .filter(|w| filter.matches(w))
+            .map(|w| (WeightCursor::after(sort, &w), w))
+            .filter(|(position, _)| resume_after.as_ref()
+                .is_none_or(|after| pagination::compare_weights(sort, position, after).is_gt()))
+            .collect();
+        matching.sort_by(|(a, _), (b, _)| pagination::compare_weights(sort, a, b));
+
+        let has_more = matching.len() > page_size;
+        matching.truncate(page_size);
+        let next_cursor = if has_more {
+            matching.last().map(|(position, _)| pagination::encode_cursor(position))
+        } else {
+            None
+        };
+
+        Ok(WeightPage {
+            weights: matching.into_iter().map(|(_, w)| w.in_unit(unit)).collect(),
+            next_cursor,
+        })
+    }
+
+    /// Corrects a weight, given in `unit`. The value it replaces stays in the
+    /// weight's history together with `reason`.
+    pub fn update_weight(&self, item_id: String, created_at: u64, weight: f64, unit: WeightUnit, reason: Option<String>, caller: Principal) -> Result<(), WeightTrackerError> {
+        let reason = reason
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty());
+        if reason.as_ref().is_some_and(|r| r.chars().count() > MAX_REASON_LENGTH) {
+            return Err(WeightTrackerError::validation(
+                "reason",
+                &format!("Reason cannot be longer than {} characters", MAX_REASON_LENGTH),
+            ));
+        }
+
+        let weight_entry = self.find_weight(&item_id, created_at, caller)?;
 
         if weight_entry.deleted_at.is_some() {
-            return Err("Cannot update deleted weight".to_string());
+            return Err(WeightTrackerError::already_deleted("Cannot update deleted weight"));
+        }
+
+        if !self.access_control.has_permission(weight_entry.owner, caller, &weight_entry.batch_id, Permission::EditWeights) {
+            return Err(WeightTrackerError::unauthorized("You don't have permission to update this record"));
+        }
+
+        let (rules, batch_unit) = self.batch_repo.get(&weight_entry.batch_id)
+            .map(|batch| (batch.validation, batch.unit))
+            .unwrap_or_default();
+        let kilograms = unit.to_kilograms(weight);
+        validate_weight(kilograms, &rules, batch_unit)?;
+
+        let key = WeightKey {
+            owner: weight_entry.owner,
+            item_id,
+            created_at,
+        };
+
+        self.weight_repo.update(key.clone(), kilograms, caller, reason)?;
+        let updated = Weight { weight: kilograms, ..weight_entry.clone() };
+        self.audit_log.record(AuditEvent::weight(caller, AuditAction::Update, &weight_entry, Some(&weight_entry), Some(&updated)));
+        // A correction settles the old flag; the new value is checked afresh
+        let live_weights = self.weight_repo.get_all(Some(updated.batch_id.clone()), false);
+        self.weight_repo.set_flag(key.clone(), self.outlier_warnings(&updated, &live_weights, Some(&key)));
+        Ok(())
+    }
+
+    /// Weights of a batch flagged as possible data-entry errors, oldest first.
+    /// Confirmed flags are left out unless `include_confirmed` is set.
+    pub fn get_flagged_weights(&self, batch_id: &str, include_confirmed: bool, unit: WeightUnit, caller: Principal) -> Result<Vec<FlaggedWeight>, WeightTrackerError> {
+        let batch = self.batch_repo.get(batch_id)
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
+        if !self.access_control.has_permission(batch.owner, caller, batch_id, Permission::ReadWeights) {
+            return Err(WeightTrackerError::unauthorized("You don't have permission to view weights of this batch"));
+        }
+
+        let mut flagged: Vec<FlaggedWeight> = self.weight_repo.get_all(Some(batch_id.to_string()), false)
+            .into_iter()
+            .filter_map(|weight| {
+                let flag = self.weight_repo.get_flag(&weight.key())?;
+                (include_confirmed || flag.confirmed_at.is_none()).then(|| FlaggedWeight { weight: weight.in_unit(unit), flag })
+            })
+            .collect();
+        flagged.sort_by_key(|f| f.weight.created_at);
+        Ok(flagged)
+    }
+
+    /// Marks a flagged weight as correct after all. Only its owner can.
+    pub fn confirm_weight(&self, item_id: String, created_at: u64, caller: Principal) -> Result<(), WeightTrackerError> {
+        let weight_entry = self.find_weight(&item_id, created_at, caller)?;
+        if weight_entry.owner != caller {
+            return Err(WeightTrackerError::unauthorized("Only the owner can confirm this record"));
+        }
+
+        let key = weight_entry.key();
+        match self.weight_repo.get_flag(&key) {
+            None => return Err(WeightTrackerError::not_found("This weight is not flagged")),
+            Some(flag) if flag.confirmed_at.is_some() => return Err(WeightTrackerError::conflict("This weight is already confirmed")),
+            Some(_) => self.weight_repo.confirm_flag(&key, caller)?,
+        }
+        self.audit_log.record(AuditEvent::weight(caller, AuditAction::Confirm, &weight_entry, Some(&weight_entry), Some(&weight_entry)));
+        Ok(())
+    }
+
+    pub fn get_weight_history(&self, item_id: String, created_at: u64, unit: WeightUnit, caller: Principal) -> Result<Vec<WeightRevision>, WeightTrackerError> {
+        let weight_entry = self.find_weight(&item_id, created_at, caller)?;
+
+        if !self.access_control.has_permission(weight_entry.owner, caller, &weight_entry.batch_id, Permission::ReadWeights) {
+            return Err(WeightTrackerError::unauthorized("You don't have permission to view this record"));
         }
 
-        if !self.access_control.has_access(weight_entry.owner, caller, Some(&weight_entry.batch_id)) {
-            return Err("Unauthorized: You don't have permission to update this record".to_string());
+        Ok(self.weight_repo.get_history(&WeightKey {
+            owner: weight_entry.owner,
+            item_id,
+            created_at,
+        })
+            .into_iter()
+            .map(|revision| revision.in_unit(unit))
+            .collect())
+    }
+
+    pub fn restore_weight(&self, item_id: String, created_at: u64, caller: Principal) -> Result<(), WeightTrackerError> {
+        let weight_entry = self.find_weight(&item_id, created_at, caller)?;
+
+        if weight_entry.owner != caller {
+            return Err(WeightTrackerError::unauthorized("Only the owner can restore this record"));
+        }
+
+        if weight_entry.deleted_at.is_none() {
+            return Err(WeightTrackerError::conflict("Weight is not deleted"));
+        }
+
+        if let Some(batch) = self.batch_repo.get(&weight_entry.batch_id) {
+            if batch.deleted_at.is_some() {
+                return Err(WeightTrackerError::conflict("Restore the batch before restoring its weights"));
+            }
+            let duplicate = !batch.validation.allow_duplicate_item_ids
+                && self.weight_repo.get_all(Some(batch.id), false).iter().any(|w| w.item_id == weight_entry.item_id);
+            if duplicate {
+                return Err(WeightTrackerError::validation("item_id", "This batch already has a weight for this item"));
+            }
         }
 
         let key = WeightKey {
@@ -124,18 +798,21 @@ impl<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider
             created_at,
         };
 
-        self.weight_repo.update(key, weight)?;
-        Ok("Weight updated successfully".to_string())
+        self.weight_repo.restore(key)?;
+        let restored = Weight { deleted_at: None, ..weight_entry.clone() };
+        self.audit_log.record(AuditEvent::weight(caller, AuditAction::Restore, &weight_entry, Some(&weight_entry), Some(&restored)));
+        Ok(())
     }
 
-    pub fn delete_weight(&self, item_id: String, created_at: u64, caller: Principal) -> Result<String, String> {
-        let weights = self.weight_repo.get_all(None, true);
-        let weight_entry = weights.iter()
-            .find(|w| w.item_id == item_id && w.created_at == created_at)
-            .ok_or_else(|| "Weight not found".to_string())?;
+    pub fn delete_weight(&self, item_id: String, created_at: u64, caller: Principal) -> Result<(), WeightTrackerError> {
+        let weight_entry = self.find_weight(&item_id, created_at, caller)?;
 
-        if !self.access_control.has_access(weight_entry.owner, caller, Some(&weight_entry.batch_id)) {
-            return Err("Unauthorized: You don't have permission to delete this record".to_string());
+        if weight_entry.deleted_at.is_some() {
+            return Err(WeightTrackerError::already_deleted("Weight is already deleted"));
+        }
+
+        if !self.access_control.has_permission(weight_entry.owner, caller, &weight_entry.batch_id, Permission::EditWeights) {
+            return Err(WeightTrackerError::unauthorized("You don't have permission to delete this record"));
         }
 
         let key = WeightKey {
@@ -145,55 +822,155 @@ impl<T: WeightRepository, B: BatchRepository, A: AccessControl, TP: TimeProvider
         };
 
         self.weight_repo.delete(key)?;
-        Ok("Weight deleted successfully".to_string())
+        let deleted = Weight { deleted_at: Some(self.time_provider.get_time()), ..weight_entry.clone() };
+        self.audit_log.record(AuditEvent::weight(caller, AuditAction::Delete, &weight_entry, Some(&weight_entry), Some(&deleted)));
+        Ok(())
+    }
+
+    /// History of every weight recorded for an item, limited to the batches
+    /// the caller can read weights of.
+    pub fn get_item_audit_log(&self, item_id: &str, request: AuditPageRequest, caller: Principal) -> Result<AuditPage, WeightTrackerError> {
+        audit_page(
+            request,
+            |after, limit| self.audit_log.get_for_item(item_id, after, limit),
+            |entry| self.access_control.has_permission(entry.event.owner, caller, &entry.event.batch_id, Permission::ReadWeights),
+        )
     }
 }
 
-pub struct BatchService<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider> {
+pub struct BatchService<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider, L: AuditLog> {
     batch_repo: B,
     weight_repo: W,
     access_control: A,
     time_provider: TP,
+    audit_log: L,
 }
 
-impl<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider> BatchService<B, W, A, TP> {
-    pub fn new(batch_repo: B, weight_repo: W, access_control: A, time_provider: TP) -> Self {
+impl<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider, L: AuditLog> BatchService<B, W, A, TP, L> {
+    pub fn new(batch_repo: B, weight_repo: W, access_control: A, time_provider: TP, audit_log: L) -> Self {
         Self {
             batch_repo,
             weight_repo,
             access_control,
             time_provider,
+            audit_log,
         }
     }
 
-    pub fn calculate_batch_stats(&self, batch_id: &str) -> BatchStats {
-        // If batch is deleted, return empty stats
+    pub fn calculate_batch_stats(&self, batch_id: &str, percentiles: &[f64]) -> BatchStats {
+        BatchStats::from_weights(&self.live_weights(batch_id), percentiles)
+    }
+
+    /// Stats of a batch as `caller` may see them. A median, spread and many
+    /// percentiles are enough to rebuild the readings of a small batch, so
+    /// they take permission to read its weights.
+    fn stats_for(&self, batch: &Batch, unit: WeightUnit, percentiles: &[f64], caller: Principal) -> BatchStats {
+        if self.access_control.has_permission(batch.owner, caller, &batch.id, Permission::ReadWeights) {
+            self.calculate_batch_stats(&batch.id, percentiles).in_unit(unit)
+        } else {
+            self.calculate_batch_stats(&batch.id, &[]).without_distribution().in_unit(unit)
+        }
+    }
+
+    /// The live weights of a batch in kilograms; none if the batch is deleted.
+    fn live_weights(&self, batch_id: &str) -> Vec<f64> {
         if let Some(batch) = self.batch_repo.get(batch_id) {
             if batch.deleted_at.is_some() {
-                return BatchStats::empty();
+                return Vec::new();
             }
         }
 
-        let weights: Vec<f64> = self.weight_repo
+        self.weight_repo
             .get_all(Some(batch_id.to_string()), false)
             .iter()
             .map(|w| w.weight)
+            .collect()
+    }
+
+    /// Stats of the live weights recorded in each day, week or month, for one
+    /// batch or every batch whose weights the caller can read. Narrow intervals
+    /// give away single readings, so recorders get none.
+    pub fn get_series(&self, request: SeriesRequest, caller: Principal) -> Result<WeightSeries, WeightTrackerError> {
+        let offset_minutes = request.utc_offset_minutes.unwrap_or(0);
+        let offset = Some(offset_minutes)
+            .filter(|minutes| (MIN_UTC_OFFSET_MINUTES..=MAX_UTC_OFFSET_MINUTES).contains(minutes))
+            .and_then(|minutes| FixedOffset::east_opt(minutes * 60))
+            .ok_or_else(|| WeightTrackerError::validation("utc_offset_minutes", "UTC offset must be between -720 and 840 minutes"))?;
+        let from = request.from.unwrap_or(0);
+        let to = request.to.unwrap_or(u64::MAX);
+        if from > to {
+            return Err(WeightTrackerError::validation("from", "Start of the range cannot be after its end"));
+        }
+
+        let weights = match &request.batch_id {
+            Some(batch_id) => {
+                let batch = self.batch_repo.get(batch_id)
+                    .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
+                if !self.access_control.has_permission(batch.owner, caller, batch_id, Permission::ReadWeights) {
+                    return Err(WeightTrackerError::unauthorized("You don't have permission to view weights of this batch"));
+                }
+                self.weight_repo.get_all(Some(batch_id.clone()), false)
+                    .into_iter()
+                    .filter(|w| w.created_at >= from && w.created_at <= to)
+                    .collect()
+            }
+            None => self.weight_repo.get_created_between(from, to, false)
+                .into_iter()
+                .filter(|w| self.access_control.has_permission(w.owner, caller, &w.batch_id, Permission::ReadWeights))
+                .collect::<Vec<_>>(),
+        };
+
+        let mut intervals: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+        for weight in weights {
+            intervals.entry(request.interval.start_of(weight.created_at, offset))
+                .or_default()
+                .push(weight.weight);
+        }
+        let unit = request.unit.unwrap_or_default();
+        let points = intervals.into_iter()
+            .map(|(start, weights)| SeriesPoint {
+                start,
+                stats: BatchStats::from_weights(&weights, &[]).in_unit(unit),
+            })
             .collect();
+        Ok(WeightSeries { interval: request.interval, points })
+    }
 
-        if weights.is_empty() {
-            return BatchStats::empty();
+    /// Counts the live weights of a batch per bucket. Narrow buckets give away
+    /// single readings, so it takes permission to read the batch's weights.
+    pub fn get_histogram(&self, batch_id: &str, request: HistogramRequest, caller: Principal) -> Result<Histogram, WeightTrackerError> {
+        let batch = self.batch_repo.get(batch_id)
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
+        if !self.access_control.has_permission(batch.owner, caller, batch_id, Permission::ReadWeights) {
+            return Err(WeightTrackerError::unauthorized("You don't have permission to view weights of this batch"));
         }
+        validate_buckets(&request.buckets)?;
 
-        BatchStats::from_weights(&weights)
+        let unit = request.unit.unwrap_or_default();
+        let weights: Vec<f64> = self.live_weights(batch_id)
+            .into_iter()
+            .map(|kilograms| unit.convert_kilograms(kilograms))
+            .collect();
+        if let (HistogramBuckets::Width(width), false) = (&request.buckets, weights.is_empty()) {
+            let min = weights.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = weights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            if (max / width).floor() - (min / width).floor() >= MAX_HISTOGRAM_BUCKETS as f64 {
+                return Err(WeightTrackerError::validation(
+                    "buckets",
+                    &format!("Bucket width is too small: at most {} buckets", MAX_HISTOGRAM_BUCKETS),
+                ));
+            }
+        }
+        Ok(Histogram::from_weights(&weights, &request.buckets, unit))
     }
 
-    pub fn get_owned_batches(&self, owner: Principal) -> Vec<BatchWithStats> {
+    pub fn get_owned_batches(&self, unit: WeightUnit, percentiles: &[f64], owner: Principal) -> Vec<BatchWithStats> {
         self.batch_repo
             .get_all(false)
             .into_iter()
             .filter(|b| b.owner == owner)
             .map(|batch| {
-                let stats = self.calculate_batch_stats(&batch.id);
+                let stats = self.stats_for(&batch, unit, percentiles, owner);
                 BatchWithStats { batch, stats }
             })
             .collect()
@@ -205,7 +982,7 @@ impl<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider
             .into_iter()
             .filter(|b| b.owner == owner)
             .filter_map(|batch| {
-                let stats = self.calculate_batch_stats(&batch.id);
+                let stats = self.calculate_batch_stats(&batch.id, &[]);
                 if stats.count > 0 {
                     Some(ShareableBatch {
                         id: batch.id.clone(),
@@ -218,21 +995,25 @@ impl<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider
             .collect()
     }
 
-    pub fn get_batch_owner(&self, batch_id: &str) -> Result<Principal, String> {
+    pub fn get_batch_owner(&self, batch_id: &str) -> Result<Principal, WeightTrackerError> {
         self.batch_repo
             .get(batch_id)
             .map(|batch| batch.owner)
-            .ok_or_else(|| "Batch not found".to_string())
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))
     }
 
-    pub fn create_batch(&mut self, request: CreateBatchRequest, owner: Principal) -> Result<String, String> {
+    pub fn create_batch(&mut self, request: CreateBatchRequest, owner: Principal) -> Result<String, WeightTrackerError> {
         if request.name.trim().is_empty() {
-            return Err("Batch name cannot be empty".to_string());
+            return Err(WeightTrackerError::validation("name", "Batch name cannot be empty"));
         }
+        let validation = request.validation.unwrap_or_default();
+        validate_rules(&validation, request.unit.unwrap_or_default())?;
 
         let now = self.time_provider.get_time();
         ic_cdk::println!("Creating batch at timestamp: {}", now);
-        let batch_id = format!("batch_{}", now);
+        // Several batches can share a timestamp within one round; the
+        // sequence keeps their IDs apart.
+        let batch_id = format!("batch_{}_{}", now, self.batch_repo.next_sequence());
         ic_cdk::println!("Generated batch ID: {}", batch_id);
 
         let batch = Batch {
@@ -243,125 +1024,363 @@ impl<B: BatchRepository, W: WeightRepository, A: AccessControl, TP: TimeProvider
             created_at: now,
             updated_at: now,
             deleted_at: None,
+            unit: request.unit.unwrap_or_default(),
+            validation,
         };
         ic_cdk::println!("Created batch object: {:?}", batch);
 
-        self.batch_repo.create(batch)
+        let event = AuditEvent::batch(owner, AuditAction::Create, &batch, None, Some(&batch));
+        let batch_id = self.batch_repo.create(batch)?;
+        self.audit_log.record(event);
+        Ok(batch_id)
     }
 
-    pub fn get_batches(&self, include_deleted: bool, caller: Principal) -> Vec<BatchWithStats> {
+    pub fn get_batches(&self, include_deleted: bool, unit: WeightUnit, percentiles: &[f64], caller: Principal) -> Vec<BatchWithStats> {
         self.batch_repo
             .get_all(include_deleted)
             .into_iter()
             .filter(|b| self.access_control.has_access(b.owner, caller, Some(&b.id)))
             .map(|batch| {
-                let stats = self.calculate_batch_stats(&batch.id);
+                let stats = self.stats_for(&batch, unit, percentiles, caller);
                 BatchWithStats { batch, stats }
             })
             .collect()
     }
 
-    pub fn get_batch(&self, batch_id: &str, caller: Principal) -> Result<BatchWithStats, String> {
+    pub fn get_batch(&self, batch_id: &str, unit: WeightUnit, percentiles: &[f64], caller: Principal) -> Result<BatchWithStats, WeightTrackerError> {
         let batch = self.batch_repo.get(batch_id)
-            .ok_or_else(|| "Batch not found".to_string())?;
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
 
         if !self.access_control.has_access(batch.owner, caller, Some(batch_id)) {
-            return Err("Unauthorized access to batch".to_string());
+            return Err(WeightTrackerError::unauthorized("Unauthorized access to batch"));
         }
 
-        let stats = self.calculate_batch_stats(batch_id);
+        let stats = self.stats_for(&batch, unit, percentiles, caller);
         Ok(BatchWithStats { batch, stats })
     }
 
-    pub fn update_batch(&mut self, batch_id: &str, request: UpdateBatchRequest, caller: Principal) -> Result<(), String> {
+    pub fn update_batch(&mut self, batch_id: &str, request: UpdateBatchRequest, caller: Principal) -> Result<(), WeightTrackerError> {
         let batch = self.batch_repo.get(batch_id)
-            .ok_or_else(|| "Batch not found".to_string())?;
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
 
-        if batch.owner != caller {
-            return Err("Unauthorized: Only the owner can update batch details".to_string());
+        if !self.access_control.has_permission(batch.owner, caller, batch_id, Permission::ManageBatch) {
+            return Err(WeightTrackerError::unauthorized("Only the owner or a batch manager can update batch details"));
         }
 
-        self.batch_repo.update(batch_id, request.name, request.description)
+        let unit = request.unit.unwrap_or(batch.unit);
+        // Limits are in the batch's unit, so kept limits follow a change of unit.
+        let validation = match request.validation {
+            Some(validation) => validation,
+            None => ValidationRules {
+                min_weight: batch.validation.min_weight.map(|min| unit.convert_kilograms(batch.unit.to_kilograms(min))),
+                max_weight: batch.validation.max_weight.map(|max| unit.convert_kilograms(batch.unit.to_kilograms(max))),
+                ..batch.validation.clone()
+            },
+        };
+        validate_rules(&validation, unit)?;
+
+        let updated = Batch {
+            name: request.name.clone(),
+            description: request.description.clone(),
+            unit,
+            validation,
+            ..batch.clone()
+        };
+        self.batch_repo.update(batch_id, request.name, request.description, updated.unit, updated.validation.clone())?;
+        self.audit_log.record(AuditEvent::batch(caller, AuditAction::Update, &batch, Some(&batch), Some(&updated)));
+        Ok(())
     }
 
-    pub fn delete_batch(&mut self, batch_id: &str, caller: Principal) -> Result<(), String> {
+    pub fn delete_batch(&mut self, batch_id: &str, caller: Principal) -> Result<(), WeightTrackerError> {
         let batch = self.batch_repo.get(batch_id)
-            .ok_or_else(|| "Batch not found".to_string())?;
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
 
         if batch.owner != caller {
-            return Err("Unauthorized: Only the owner can delete this batch".to_string());
+            return Err(WeightTrackerError::unauthorized("Only the owner can delete this batch"));
         }
 
         if batch.deleted_at.is_some() {
-            return Err("Batch is already deleted".to_string());
+            return Err(WeightTrackerError::already_deleted("Batch is already deleted"));
         }
 
         self.batch_repo.delete(batch_id)?;
+        let now = self.time_provider.get_time();
+        let deleted = Batch { deleted_at: Some(now), ..batch.clone() };
+        self.audit_log.record(AuditEvent::batch(caller, AuditAction::Delete, &batch, Some(&batch), Some(&deleted)));
 
         // Mark all associated weights as deleted
         let weights = self.weight_repo.get_all(Some(batch_id.to_string()), false);
         for weight in weights {
             let key = WeightKey {
                 owner: weight.owner,
-                item_id: weight.item_id,
+                item_id: weight.item_id.clone(),
                 created_at: weight.created_at,
             };
-            self.weight_repo.delete(key)?;
+            self.weight_repo.delete_with_batch(key)?;
+            let deleted = Weight { deleted_at: Some(now), deleted_with_batch: true, ..weight.clone() };
+            self.audit_log.record(AuditEvent::weight(caller, AuditAction::Delete, &weight, Some(&weight), Some(&deleted)));
         }
 
         Ok(())
     }
+
+    /// Hard-deletes batches and weights that were soft-deleted more than
+    /// `retention` nanoseconds ago, and revokes sharing of the purged batches.
+    /// `actor` is recorded in the audit log as having run the purge.
+    pub fn purge_deleted(&mut self, retention: u64, actor: Principal) -> PurgeReport {
+        let now = self.time_provider.get_time();
+        let cutoff = now.saturating_sub(retention);
+
+        let batches = self.batch_repo.purge_deleted_before(cutoff);
+        let weights = self.weight_repo.purge_deleted_before(cutoff);
+        self.weight_repo.forget_client_requests_before(now.saturating_sub(CLIENT_REQUEST_WINDOW));
+        for batch in &batches {
+            self.audit_log.record(AuditEvent::batch(actor, AuditAction::Purge, batch, Some(batch), None));
+            for grant in self.access_control.get_batch_collaborators(batch.owner, &batch.id) {
+                // The grant was just listed, so it cannot be missing
+                let _ = self.access_control.remove_batch_access(batch.owner, grant.collaborator, &batch.id);
+                self.audit_log.record(AuditEvent::grant(
+                    actor,
+                    AuditAction::Unshare,
+                    batch.owner,
+                    &batch.id,
+                    grant.collaborator,
+                    Some(grant.role),
+                    None,
+                ));
+            }
+        }
+        for weight in &weights {
+            self.audit_log.record(AuditEvent::weight(actor, AuditAction::Purge, weight, Some(weight), None));
+        }
+
+        PurgeReport {
+            ran_at: now,
+            cutoff,
+            batch_ids: batches.into_iter().map(|b| b.id).collect(),
+            weights_purged: weights.len() as u64,
+        }
+    }
+
+    /// Restores a deleted batch together with the weights its deletion took
+    /// with it. Weights deleted individually beforehand stay deleted.
+    /// Returns the number of weights restored.
+    pub fn restore_batch(&mut self, batch_id: &str, caller: Principal) -> Result<u64, WeightTrackerError> {
+        let batch = self.batch_repo.get(batch_id)
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
+
+        if batch.owner != caller {
+            return Err(WeightTrackerError::unauthorized("Only the owner can restore this batch"));
+        }
+
+        if batch.deleted_at.is_none() {
+            return Err(WeightTrackerError::conflict("Batch is not deleted"));
+        }
+
+        let (cascaded, others): (Vec<_>, Vec<_>) = self.weight_repo.get_all(Some(batch_id.to_string()), true)
+            .into_iter()
+            .partition(|w| w.deleted_with_batch);
+        if !batch.validation.allow_duplicate_item_ids {
+            let mut items = HashSet::new();
+            let mut live = cascaded.iter().chain(others.iter().filter(|w| w.deleted_at.is_none()));
+            if !live.all(|w| items.insert(&w.item_id)) {
+                return Err(WeightTrackerError::validation("item_id", "Restoring this batch would give an item more than one weight"));
+            }
+        }
+
+        self.batch_repo.restore(batch_id)?;
+        let restored = Batch { deleted_at: None, ..batch.clone() };
+        self.audit_log.record(AuditEvent::batch(caller, AuditAction::Restore, &batch, Some(&batch), Some(&restored)));
+        for weight in &cascaded {
+            let key = WeightKey {
+                owner: weight.owner,
+                item_id: weight.item_id.clone(),
+                created_at: weight.created_at,
+            };
+            self.weight_repo.restore(key)?;
+            let restored = Weight { deleted_at: None, deleted_with_batch: false, ..weight.clone() };
+            self.audit_log.record(AuditEvent::weight(caller, AuditAction::Restore, weight, Some(weight), Some(&restored)));
+        }
+
+        Ok(cascaded.len() as u64)
+    }
+
+    /// Produces the next chunk of a CSV or JSON export of one batch, or of
+    /// every batch the caller can read weights of, with stats and weights.
+    pub fn export(&self, request: ExportRequest, caller: Principal) -> Result<ExportChunk, WeightTrackerError> {
+        let chunk_size = match request.chunk_size.unwrap_or(DEFAULT_EXPORT_CHUNK_SIZE) {
+            size if size == 0 || size > MAX_EXPORT_CHUNK_SIZE => {
+                return Err(WeightTrackerError::validation(
+                    "chunk_size",
+                    &format!("Chunk size must be between 1 and {}", MAX_EXPORT_CHUNK_SIZE),
+                ));
+            }
+            size => size as usize,
+        };
+        let unit = request.unit.unwrap_or_default();
+        let cursor = request.cursor.as_deref()
+            .map(pagination::decode_cursor::<ExportCursor>)
+            .transpose()?;
+
+        let mut batches = match &request.batch_id {
+            Some(batch_id) => {
+                let batch = self.batch_repo.get(batch_id)
+                    .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
+                if !self.access_control.has_permission(batch.owner, caller, batch_id, Permission::ReadWeights) {
+                    return Err(WeightTrackerError::unauthorized("You don't have permission to read weights in this batch"));
+                }
+                vec![batch]
+            }
+            None => self.batch_repo.get_all(request.include_deleted)
+                .into_iter()
+                .filter(|b| self.access_control.has_permission(b.owner, caller, &b.id, Permission::ReadWeights))
+                .collect(),
+        };
+        batches.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut writer = ExportWriter::new(request.format);
+        // `resume` is set while continuing inside an opened batch
+        let (mut index, mut resume, mut batches_started) = match cursor {
+            None => {
+                writer.begin();
+                (0, None, 0)
+            }
+            Some(cursor) => {
+                let index = batches.partition_point(|b| b.id < cursor.batch_id);
+                let still_there = batches.get(index).is_some_and(|b| b.id == cursor.batch_id);
+                if cursor.opened && !still_there {
+                    // The opened batch went away between chunks
+                    writer.end_batch();
+                }
+                let resume = Some((cursor.after, cursor.wrote_any)).filter(|_| cursor.opened && still_there);
+                (index, resume, cursor.batches_started)
+            }
+        };
+
+        let mut written = 0;
+        let mut next_cursor = None;
+        while let Some(batch) = batches.get(index) {
+            if written >= chunk_size {
+                next_cursor = Some(ExportCursor { batch_id: batch.id.clone(), opened: false, after: None, wrote_any: false, batches_started });
+                break;
+            }
+
+            let stats = self.calculate_batch_stats(&batch.id, &[]).in_unit(unit);
+            let mut weights = self.weight_repo.get_all(Some(batch.id.clone()), request.include_deleted);
+            weights.sort_by_key(Weight::key);
+            let (skip, wrote_any) = match resume.take() {
+                Some((Some(after), wrote_any)) => (weights.partition_point(|w| w.key() <= after), wrote_any),
+                Some((None, wrote_any)) => (0, wrote_any),
+                None => {
+                    writer.start_batch(batch, &stats, batches_started > 0, weights.is_empty());
+                    batches_started += 1;
+                    written += 1;
+                    (0, false)
+                }
+            };
+
+            let remaining = &weights[skip..];
+            let room = chunk_size.saturating_sub(written);
+            for (n, weight) in remaining.iter().take(room).enumerate() {
+                writer.weight(batch, &stats, &weight.clone().in_unit(unit), wrote_any || n > 0);
+            }
+            written += remaining.len().min(room);
+            if remaining.len() > room {
+                let written_weights = skip + room;
+                next_cursor = Some(ExportCursor {
+                    batch_id: batch.id.clone(),
+                    opened: true,
+                    after: written_weights.checked_sub(1).map(|last| weights[last].key()),
+                    wrote_any: wrote_any || room > 0,
+                    batches_started,
+                });
+                break;
+            }
+
+            writer.end_batch();
+            index += 1;
+        }
+
+        if next_cursor.is_none() {
+            writer.end();
+        }
+        Ok(ExportChunk {
+            data: writer.into_bytes(),
+            next_cursor: next_cursor.as_ref().map(pagination::encode_cursor),
+        })
+    }
+
+    /// Every recorded change to a batch, its weights and its sharing. Entries
+    /// of a purged batch remain readable by the users who could read it.
+    pub fn get_batch_audit_log(&self, batch_id: &str, request: AuditPageRequest, caller: Principal) -> Result<AuditPage, WeightTrackerError> {
+        if let Some(batch) = self.batch_repo.get(batch_id) {
+            if !self.access_control.has_permission(batch.owner, caller, batch_id, Permission::ReadWeights) {
+                return Err(WeightTrackerError::unauthorized("You don't have access to the history of this batch"));
+            }
+        }
+
+        audit_page(
+            request,
+            |after, limit| self.audit_log.get_for_batch(batch_id, after, limit),
+            |entry| self.access_control.has_permission(entry.event.owner, caller, batch_id, Permission::ReadWeights),
+        )
+    }
 }
 
-pub struct AccessControlService<A: AccessControl, B: BatchRepository> {
+pub struct AccessControlService<A: AccessControl, B: BatchRepository, L: AuditLog> {
     access_control: A,
     batch_repo: B,
+    audit_log: L,
 }
 
-impl<A: AccessControl, B: BatchRepository> AccessControlService<A, B> {
-    pub fn new(access_control: A, batch_repo: B) -> Self {
+impl<A: AccessControl, B: BatchRepository, L: AuditLog> AccessControlService<A, B, L> {
+    pub fn new(access_control: A, batch_repo: B, audit_log: L) -> Self {
         Self { 
             access_control,
             batch_repo,
+            audit_log,
         }
     }
 
-    pub fn share_with_user(&mut self, owner: Principal, collaborator: Principal, batch_id: String) -> Result<String, String> {
+    /// Grants `role` on the batch, or changes the role of an existing grant.
+    pub fn share_with_user(&mut self, owner: Principal, collaborator: Principal, batch_id: String, role: Role) -> Result<(), WeightTrackerError> {
         if owner == collaborator {
-            return Err("Cannot share data with yourself".to_string());
+            return Err(WeightTrackerError::validation("collaborator", "Cannot share data with yourself"));
         }
 
         if collaborator == Principal::anonymous() {
-            return Err("Cannot share with anonymous principal".to_string());
+            return Err(WeightTrackerError::validation("collaborator", "Cannot share with anonymous principal"));
         }
 
         if batch_id.trim().is_empty() {
-            return Err("Batch ID cannot be empty".to_string());
+            return Err(WeightTrackerError::validation("batch_id", "Batch ID cannot be empty"));
         }
 
         // Check if batch exists (we'll need to add BatchRepository as a dependency)
         match self.batch_repo.get(&batch_id) {
-            None => return Err("Batch not found".to_string()),
+            None => return Err(WeightTrackerError::not_found("Batch not found")),
             Some(batch) => {
                 if batch.owner != owner {
-                    return Err("Only the owner can share this batch".to_string());
+                    return Err(WeightTrackerError::unauthorized("Only the owner can share this batch"));
                 }
                 if batch.deleted_at.is_some() {
-                    return Err("Cannot share deleted batch".to_string());
+                    return Err(WeightTrackerError::already_deleted("Cannot share deleted batch"));
                 }
             }
         }
 
-        // Check if already shared
-        if self.access_control.has_access(owner, collaborator, Some(&batch_id)) {
-            return Err("Batch is already shared with this user".to_string());
+        // Check if already shared with this role
+        let previous = self.access_control.get_role(owner, collaborator, &batch_id);
+        if previous == Some(role) {
+            return Err(WeightTrackerError::conflict("Batch is already shared with this user"));
         }
 
-        self.access_control.share_access(owner, collaborator, batch_id)?;
-        Ok("Successfully shared batch with collaborator".to_string())
+        self.access_control.share_access(owner, collaborator, batch_id.clone(), role)?;
+        self.audit_log.record(AuditEvent::grant(owner, AuditAction::Share, owner, &batch_id, collaborator, previous, Some(role)));
+        Ok(())
     }
 
-    pub fn remove_sharing(&mut self, caller: Principal, collaborator: Principal) -> Result<String, String> {
+    pub fn remove_sharing(&mut self, caller: Principal, collaborator: Principal) -> Result<u64, WeightTrackerError> {
         // Get all batches owned by the caller
         let all_batches = self.batch_repo.get_all(false)
             .into_iter()
@@ -369,32 +1388,133 @@ impl<A: AccessControl, B: BatchRepository> AccessControlService<A, B> {
             .collect::<Vec<_>>();
 
         if all_batches.is_empty() {
-            return Err("Only the owner can remove sharing permissions".to_string());
+            return Err(WeightTrackerError::unauthorized("Only the owner can remove sharing permissions"));
         }
 
         // Get all shared batches with this collaborator
         let shared_batches = self.access_control.get_collaborators(caller);
         if !shared_batches.contains(&collaborator) {
-            return Err("No sharing permissions found with this collaborator".to_string());
+            return Err(WeightTrackerError::not_found("No sharing permissions found with this collaborator"));
         }
 
-        // Remove access for all batches
-        let mut removed_count = 0;
-        for batch in all_batches {
-            if self.access_control.has_access(caller, collaborator, Some(&batch.id)) {
-                self.access_control.remove_access(caller, collaborator)?;
-                removed_count += 1;
-            }
+        // Count the shared batches before access to all of them is removed at once
+        let removed_count = all_batches.iter()
+            .filter(|batch| self.access_control.has_access(caller, collaborator, Some(&batch.id)))
+            .count() as u64;
+        // Grants on deleted batches go too, so audit those as well
+        let revoked = self.batch_repo.get_all(true)
+            .into_iter()
+            .filter(|b| b.owner == caller)
+            .filter_map(|b| self.access_control.get_role(caller, collaborator, &b.id).map(|role| (b.id, role)))
+            .collect::<Vec<_>>();
+        self.access_control.remove_access(caller, collaborator)?;
+        for (batch_id, role) in revoked {
+            self.audit_log.record(AuditEvent::grant(caller, AuditAction::Unshare, caller, &batch_id, collaborator, Some(role), None));
         }
 
-        if removed_count == 0 {
-            Ok("No shared batches found to remove".to_string())
-        } else {
-            Ok(format!("Successfully removed sharing permissions for {} batches", removed_count))
-        }
+        Ok(removed_count)
+    }
+
+    pub fn unshare_batch(&mut self, caller: Principal, collaborator: Principal, batch_id: &str) -> Result<(), WeightTrackerError> {
+        self.owned_batch(caller, batch_id, "Only the owner can unshare this batch")?;
+        let role = self.access_control.get_role(caller, collaborator, batch_id);
+        self.access_control.remove_batch_access(caller, collaborator, batch_id)?;
+        self.audit_log.record(AuditEvent::grant(caller, AuditAction::Unshare, caller, batch_id, collaborator, role, None));
+        Ok(())
     }
 
     pub fn get_collaborators(&self, owner: Principal) -> Vec<Principal> {
         self.access_control.get_collaborators(owner)
     }
+
+    pub fn list_batch_collaborators(&self, caller: Principal, batch_id: &str) -> Result<Vec<BatchCollaborator>, WeightTrackerError> {
+        self.owned_batch(caller, batch_id, "Only the owner can list collaborators of this batch")?;
+        Ok(self.access_control.get_batch_collaborators(caller, batch_id))
+    }
+
+    fn owned_batch(&self, caller: Principal, batch_id: &str, denied: &str) -> Result<Batch, WeightTrackerError> {
+        let batch = self.batch_repo.get(batch_id)
+            .ok_or_else(|| WeightTrackerError::not_found("Batch not found"))?;
+        if batch.owner != caller {
+            return Err(WeightTrackerError::unauthorized(denied));
+        }
+        Ok(batch)
+    }
+}
+
+const API_TOKEN_PREFIX: &str = "wt_";
+const MAX_API_TOKENS_PER_USER: usize = 10;
+const MAX_API_TOKEN_LABEL_LENGTH: usize = 100;
+
+fn hash_api_token(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub struct ApiTokenService<R: ApiTokenRepository, TP: TimeProvider> {
+    tokens: R,
+    time_provider: TP,
+}
+
+impl<R: ApiTokenRepository, TP: TimeProvider> ApiTokenService<R, TP> {
+    pub fn new(tokens: R, time_provider: TP) -> Self {
+        Self { tokens, time_provider }
+    }
+
+    /// Issues a token whose secret is built from `entropy`, which must be
+    /// unpredictable. The secret is only ever returned here.
+    pub fn create_token(&self, owner: Principal, label: String, entropy: &[u8]) -> Result<String, WeightTrackerError> {
+        let label = label.trim().to_string();
+        if label.is_empty() || label.chars().count() > MAX_API_TOKEN_LABEL_LENGTH {
+            return Err(WeightTrackerError::validation(
+                "label",
+                &format!("Label must be between 1 and {} characters", MAX_API_TOKEN_LABEL_LENGTH),
+            ));
+        }
+        if self.tokens.get_for_owner(owner).len() >= MAX_API_TOKENS_PER_USER {
+            return Err(WeightTrackerError::conflict(&format!(
+                "You already have {} API tokens; revoke one first",
+                MAX_API_TOKENS_PER_USER
+            )));
+        }
+
+        let secret: String = std::iter::once(API_TOKEN_PREFIX.to_string())
+            .chain(entropy.iter().map(|byte| format!("{:02x}", byte)))
+            .collect();
+        self.tokens.create(hash_api_token(&secret), ApiToken {
+            owner,
+            label,
+            created_at: self.time_provider.get_time(),
+        });
+        Ok(secret)
+    }
+
+    pub fn list_tokens(&self, owner: Principal) -> Vec<ApiTokenInfo> {
+        let mut tokens: Vec<_> = self.tokens.get_for_owner(owner)
+            .into_iter()
+            .map(|(id, token)| ApiTokenInfo { id, label: token.label, created_at: token.created_at })
+            .collect();
+        tokens.sort_by_key(|token| token.created_at);
+        tokens
+    }
+
+    pub fn revoke_token(&self, owner: Principal, id: &str) -> Result<(), WeightTrackerError> {
+        match self.tokens.get(id) {
+            Some(token) if token.owner == owner => {
+                self.tokens.delete(id);
+                Ok(())
+            }
+            _ => Err(WeightTrackerError::not_found("API token not found")),
+        }
+    }
+
+    /// The user a token's secret acts for, if it is a live token.
+    pub fn authenticate(&self, secret: &str) -> Option<Principal> {
+        if !secret.starts_with(API_TOKEN_PREFIX) {
+            return None;
+        }
+        self.tokens.get(&hash_api_token(secret)).map(|token| token.owner)
+    }
 }
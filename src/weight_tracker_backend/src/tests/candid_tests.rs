@@ -0,0 +1,55 @@
+use candid::types::subtype::subtype;
+use candid_parser::utils::{instantiate_candid, service_equal, CandidSource};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn manifest_path(relative: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
+
+/// Fails when `weight_tracker_backend.did` no longer matches the endpoints in
+/// `lib.rs`. Run `UPDATE_CANDID=1 cargo test` to regenerate it.
+#[test]
+fn test_candid_interface_is_up_to_date() {
+    let generated = crate::__export_service();
+    let did_path = manifest_path("weight_tracker_backend.did");
+
+    if std::env::var("UPDATE_CANDID").is_ok() {
+        std::fs::write(&did_path, &generated).expect("failed to write the candid interface");
+    }
+
+    if let Err(e) = service_equal(CandidSource::Text(&generated), CandidSource::File(&did_path)) {
+        panic!(
+            "weight_tracker_backend.did is out of date, run `UPDATE_CANDID=1 cargo test` to regenerate it: {}",
+            e
+        );
+    }
+}
+
+/// Every method of the last released interface must still exist with a
+/// Candid subtype of its released type, so that existing clients keep working
+/// after an upgrade.
+#[test]
+fn test_candid_interface_is_compatible_with_previous_release() {
+    let generated = crate::__export_service();
+    let released_path = manifest_path("candid/released.did");
+    let (_, (mut env, service)) = instantiate_candid(CandidSource::Text(&generated)).unwrap();
+    let (_, (released_env, released)) = instantiate_candid(CandidSource::File(&released_path)).unwrap();
+    let released = env.merge_type(released_env, released);
+
+    let methods = env.as_service(&service).unwrap();
+    let breaks: Vec<String> = env.as_service(&released).unwrap()
+        .iter()
+        .filter_map(|(name, released_method)| {
+            let result = match methods.iter().find(|(method, _)| method == name) {
+                Some((_, method)) => subtype(&mut HashSet::new(), &env, method, released_method).map_err(|e| e.to_string()),
+                None => Err("method was removed".to_string()),
+            };
+            result.err().map(|e| format!("{}: {}", name, e))
+        })
+        .collect();
+
+    if !breaks.is_empty() {
+        panic!("the candid interface breaks clients of the previous release:\n{}", breaks.join("\n"));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::csv_import::*;
+
+fn fields(records: &[CsvRecord]) -> Vec<(u64, Vec<&str>)> {
+    records.iter()
+        .map(|record| (record.line, record.fields.iter().map(String::as_str).collect()))
+        .collect()
+}
+
+#[test]
+fn test_parse_quoted_fields_and_line_endings() {
+    let csv = "\u{feff}item,weight,notes\r\ncow_1,512.5,\"limping, checked\"\r\n\r\ncow_2,498,\"said \"\"calm\"\"\nall day\"\ncow_3,501,";
+    let records = parse(csv.as_bytes()).unwrap();
+    assert_eq!(fields(&records), vec![
+        (1, vec!["item", "weight", "notes"]),
+        (2, vec!["cow_1", "512.5", "limping, checked"]),
+        (4, vec!["cow_2", "498", "said \"calm\"\nall day"]),
+        (6, vec!["cow_3", "501", ""]),
+    ]);
+}
+
+#[test]
+fn test_parse_rejects_malformed_input() {
+    assert!(parse(b"item,weight\ncow_1,\"512").unwrap_err().contains("line 2"));
+    assert!(parse(&[0x69, 0xff, 0x0a]).is_err());
+    assert!(parse(b"").unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_timestamp_formats() {
+    assert_eq!(parse_timestamp("1700000000000000000"), Ok(1_700_000_000_000_000_000));
+    assert_eq!(parse_timestamp("2023-11-14T22:13:20Z"), Ok(1_700_000_000_000_000_000));
+    assert_eq!(parse_timestamp(" 2023-11-14T23:13:20+01:00 "), Ok(1_700_000_000_000_000_000));
+    assert_eq!(parse_timestamp("2023-11-14"), Ok(1_699_920_000_000_000_000));
+    assert!(parse_timestamp("14/11/2023").is_err());
+    assert!(parse_timestamp("1969-12-31").is_err());
+
+    // Seconds and milliseconds would otherwise land in 1970
+    assert!(parse_timestamp("1700000000").is_err());
+    assert!(parse_timestamp("1700000000000").is_err());
+}
@@ -0,0 +1,46 @@
+use crate::errors::WeightTrackerError;
+use crate::http::*;
+use crate::models::*;
+
+fn get(url: &str) -> HttpRequest {
+    HttpRequest {
+        method: "GET".to_string(),
+        url: url.to_string(),
+        headers: vec![("authorization".to_string(), "Bearer wt_secret ".to_string())],
+        body: Vec::new(),
+    }
+}
+
+#[test]
+fn test_routes_map_to_exports() {
+    let request = export_request(&get("/batches.csv")).unwrap();
+    assert_eq!(request.batch_id, None);
+    assert_eq!(request.format, ExportFormat::Csv);
+    assert!(!request.include_deleted);
+
+    let request = export_request(&get("/batches/batch%201/weights.json?unit=lb&include_deleted=true&chunk_size=50&cursor=4449")).unwrap();
+    assert_eq!(request.batch_id, Some("batch 1".to_string()));
+    assert_eq!(request.format, ExportFormat::Json);
+    assert_eq!(request.unit, Some(WeightUnit::Pound));
+    assert!(request.include_deleted);
+    assert_eq!(request.chunk_size, Some(50));
+    assert_eq!(request.cursor, Some("4449".to_string()));
+
+    assert_eq!(get("/batches.csv").bearer_token(), Some("wt_secret"));
+}
+
+#[test]
+fn test_bad_requests_are_rejected() {
+    let status = |request: HttpRequest| export_request(&request).unwrap_err().status_code;
+    assert_eq!(status(get("/batches.xml")), 404);
+    assert_eq!(status(get("/batches/batch_1.csv")), 404);
+    assert_eq!(status(get("/other/batch_1/weights.csv")), 404);
+    assert_eq!(status(get("/batches.csv?unit=stone")), 400);
+    assert_eq!(status(get("/batches.csv?limit=10")), 400);
+    assert_eq!(status(get("/batches/%zz/weights.csv")), 400);
+    assert_eq!(status(HttpRequest { method: "POST".to_string(), ..get("/batches.csv") }), 405);
+
+    let response = HttpResponse::from_error(&WeightTrackerError::unauthorized("No access"));
+    assert_eq!(response.status_code, 403);
+    assert_eq!(response.body, b"{\"error\":\"No access\"}".to_vec());
+}
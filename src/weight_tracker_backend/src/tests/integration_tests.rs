@@ -33,6 +33,7 @@ mod integration_tests {
             let batch_request = CreateBatchRequest {
                 name: "Test Batch".to_string(),
                 description: Some("Test Description".to_string()),
+                validation: None,
             };
             let batch_id = create_batch(batch_request).unwrap();
 
@@ -55,6 +56,7 @@ mod integration_tests {
             let batch_request = CreateBatchRequest {
                 name: "Original Name".to_string(),
                 description: None,
+                validation: None,
             };
             let batch_id = create_batch(batch_request).unwrap();
 
@@ -62,6 +64,7 @@ mod integration_tests {
             let update_request = UpdateBatchRequest {
                 name: "Updated Name".to_string(),
                 description: Some("New Description".to_string()),
+                validation: None,
             };
             let result = update_batch(batch_id.clone(), update_request);
             assert!(result.is_ok());
@@ -85,6 +88,7 @@ mod integration_tests {
             let batch_request = CreateBatchRequest {
                 name: "To Delete".to_string(),
                 description: None,
+                validation: None,
             };
             let batch_id = create_batch(batch_request).unwrap();
 
@@ -110,6 +114,7 @@ mod integration_tests {
             let batch_request = CreateBatchRequest {
                 name: "Weight Test".to_string(),
                 description: None,
+                validation: None,
             };
             let batch_id = create_batch(batch_request).unwrap();
 
@@ -119,9 +124,11 @@ mod integration_tests {
                 item_id: "test_item".to_string(),
                 weight: 100.0,
                 owner_override: None,
+                notes: None,
+                client_request_id: None,
             };
             let result = create_weight(weight_request);
-            assert!(result.contains("successfully"));
+            assert!(result.is_ok());
 
             // Verify weight in batch
             let batch = get_batch(batch_id).unwrap();
@@ -143,12 +150,13 @@ mod integration_tests {
             let batch_request = CreateBatchRequest {
                 name: "Shared Batch".to_string(),
                 description: None,
+                validation: None,
             };
             let batch_id = create_batch(batch_request).unwrap();
 
             // Share batch
             let share_result = share_with_user(collaborator, batch_id.clone());
-            assert!(share_result.contains("successfully"));
+            assert!(share_result.is_ok());
         });
 
         // Switch to collaborator
@@ -0,0 +1,192 @@
+use crate::migrations::*;
+use crate::models::*;
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+// Fixtures: records exactly as the first stable-memory release wrote them,
+// i.e. bare Candid without a schema version stamp.
+#[derive(CandidType, Deserialize)]
+struct WeightV1 {
+    owner: Principal,
+    batch_id: String,
+    item_id: String,
+    weight: f64,
+    created_at: u64,
+    updated_at: u64,
+    deleted_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct BatchV1 {
+    id: String,
+    name: String,
+    owner: Principal,
+    description: Option<String>,
+    created_at: u64,
+    updated_at: u64,
+    deleted_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SharingPermissionsV1 {
+    owner: Principal,
+    shared_with: HashMap<Principal, HashSet<String>>,
+}
+
+fn owner() -> Principal {
+    Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+}
+
+fn legacy_weight_bytes() -> Vec<u8> {
+    Encode!(&WeightV1 {
+        owner: owner(),
+        batch_id: "batch_1".to_string(),
+        item_id: "cow_42".to_string(),
+        weight: 512.5,
+        created_at: 1000,
+        updated_at: 2000,
+        deleted_at: None,
+    }).unwrap()
+}
+
+fn legacy_batch_bytes() -> Vec<u8> {
+    Encode!(&BatchV1 {
+        id: "batch_1".to_string(),
+        name: "Spring herd".to_string(),
+        owner: owner(),
+        description: Some("Legacy batch".to_string()),
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at: Some(3000),
+    }).unwrap()
+}
+
+fn assert_stamped_with_current_version(bytes: &[u8]) {
+    let (version, _) = split_version(bytes).unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+}
+
+#[test]
+fn test_legacy_weight_is_migrated_on_decode() {
+    let weight = Weight::from_bytes(Cow::Owned(legacy_weight_bytes()));
+    assert_eq!(weight.owner, owner());
+    assert_eq!(weight.batch_id, "batch_1");
+    assert_eq!(weight.item_id, "cow_42");
+    assert_eq!(weight.weight, 512.5);
+    assert_eq!(weight.updated_at, 2000);
+    assert!(!weight.deleted_with_batch);
+    assert_eq!(weight.entered_unit, WeightUnit::Kilogram);
+    assert_eq!(weight.notes, None);
+
+    assert_stamped_with_current_version(&weight.to_bytes());
+}
+
+#[test]
+fn test_legacy_batch_is_migrated_on_decode() {
+    let batch = Batch::from_bytes(Cow::Owned(legacy_batch_bytes()));
+    assert_eq!(batch.name, "Spring herd");
+    assert_eq!(batch.description, Some("Legacy batch".to_string()));
+    assert_eq!(batch.deleted_at, Some(3000));
+    assert_eq!(batch.unit, WeightUnit::Kilogram);
+    assert_eq!(batch.validation, ValidationRules::default());
+
+    assert_stamped_with_current_version(&batch.to_bytes());
+}
+
+#[test]
+fn test_legacy_sharing_permissions_are_migrated_on_decode() {
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let legacy = Encode!(&SharingPermissionsV1 {
+        owner: owner(),
+        shared_with: HashMap::from([(collaborator, HashSet::from(["batch_1".to_string()]))]),
+    }).unwrap();
+
+    let permissions = SharingPermissions::from_bytes(Cow::Owned(legacy));
+    assert_eq!(permissions.owner, owner());
+    assert_eq!(permissions.shared_with[&collaborator]["batch_1"], Role::Editor);
+}
+
+#[test]
+fn test_stored_records_migrate_when_read_and_restamp_when_written() {
+    let stable_memory = DefaultMemoryImpl::default();
+    let memory = || MemoryManager::init(stable_memory.clone()).get(MemoryId::new(0));
+
+    let mut raw: StableBTreeMap<String, Vec<u8>, _> = StableBTreeMap::init(memory());
+    raw.insert("batch_1".to_string(), legacy_batch_bytes());
+
+    // Reading leaves the stored bytes alone
+    let mut batches: StableBTreeMap<String, Batch, _> = StableBTreeMap::init(memory());
+    let batch = batches.get(&"batch_1".to_string()).unwrap();
+    assert_eq!(batch.name, "Spring herd");
+    let raw: StableBTreeMap<String, Vec<u8>, _> = StableBTreeMap::init(memory());
+    assert_eq!(raw.get(&"batch_1".to_string()).unwrap(), legacy_batch_bytes());
+
+    batches.insert("batch_1".to_string(), batch);
+    let stored = raw.get(&"batch_1".to_string()).unwrap();
+    assert_stamped_with_current_version(&stored);
+    assert_eq!(Batch::from_bytes(Cow::Owned(stored)).name, "Spring herd");
+}
+
+#[derive(CandidType, Deserialize)]
+struct ReadingV2 {
+    weight: f64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ReadingV3 {
+    grams: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct ReadingV4 {
+    grams: u64,
+    verified: bool,
+}
+
+#[test]
+fn test_migrations_are_applied_in_order() {
+    let migrations = [
+        Migration {
+            from_version: 3,
+            description: "add verified flag",
+            migrate: |_, payload| {
+                let old = Decode!(payload, ReadingV3).map_err(|e| e.to_string())?;
+                Encode!(&ReadingV4 { grams: old.grams, verified: false }).map_err(|e| e.to_string())
+            },
+        },
+        Migration {
+            from_version: 2,
+            description: "store grams",
+            migrate: |_, payload| {
+                let old = Decode!(payload, ReadingV2).map_err(|e| e.to_string())?;
+                Encode!(&ReadingV3 { grams: (old.weight * 1000.0) as u64 }).map_err(|e| e.to_string())
+            },
+        },
+    ];
+
+    let payload = Encode!(&ReadingV2 { weight: 1.5 }).unwrap();
+    let migrated = upgrade_payload(RecordKind::Weight, 2, payload, 4, &migrations).unwrap();
+    assert_eq!(
+        Decode!(&migrated, ReadingV4).unwrap(),
+        ReadingV4 { grams: 1500, verified: false }
+    );
+}
+
+#[test]
+fn test_missing_migration_is_reported() {
+    let payload = Encode!(&ReadingV2 { weight: 1.5 }).unwrap();
+    let result = upgrade_payload(RecordKind::Weight, 2, payload, 3, &[]);
+    assert!(result.unwrap_err().contains("no migration registered from schema version 2"));
+}
+
+#[test]
+fn test_records_from_newer_schema_are_rejected() {
+    let mut bytes = (SCHEMA_VERSION + 1).to_be_bytes().to_vec();
+    bytes.extend(legacy_weight_bytes());
+
+    let result = decode::<Weight>(&bytes);
+    assert!(result.unwrap_err().contains("only understands up to"));
+}
@@ -4,5 +4,13 @@ mod services_tests;
 mod models_tests;
 #[cfg(test)]
 mod repositories_tests;
+#[cfg(test)]
+mod migrations_tests;
+#[cfg(test)]
+mod candid_tests;
+#[cfg(test)]
+mod csv_import_tests;
+#[cfg(test)]
+mod http_tests;
 // #[cfg(test)]
 // mod integration_tests; 
\ No newline at end of file
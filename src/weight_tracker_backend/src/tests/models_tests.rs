@@ -12,9 +12,156 @@ fn test_batch_stats_empty() {
 #[test]
 fn test_batch_stats_from_weights() {
     let weights = vec![10.0, 20.0, 30.0, 40.0, 50.0];
-    let stats = BatchStats::from_weights(&weights);
+    let stats = BatchStats::from_weights(&weights, &[]);
     assert_eq!(stats.count, 5);
     assert_eq!(stats.min_weight, 10.0);
     assert_eq!(stats.max_weight, 50.0);
     assert_eq!(stats.average_weight, 30.0);
-} 
\ No newline at end of file
+}
+
+#[test]
+fn test_batch_stats_spread_and_percentiles() {
+    let weights = vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+    let stats = BatchStats::from_weights(&weights, &[0.0, 25.0, 90.0, 100.0]);
+    assert_eq!(stats.average_weight, 5.0);
+    assert_eq!(stats.median_weight, 4.5);
+    assert_eq!(stats.std_deviation, 2.0);
+    assert_eq!(stats.coefficient_of_variation, 40.0);
+    // Only the two weights of 5.0 are within 10% of the average
+    assert_eq!(stats.uniformity, 25.0);
+    let percentiles: Vec<_> = stats.percentiles.iter().map(|p| (p.percentile, p.weight)).collect();
+    assert_eq!(percentiles, vec![(0.0, 2.0), (25.0, 4.0), (90.0, 7.6), (100.0, 9.0)]);
+
+    let stats = stats.in_unit(WeightUnit::Gram);
+    assert_eq!(stats.median_weight, 4500.0);
+    assert_eq!(stats.std_deviation, 2000.0);
+    assert_eq!(stats.coefficient_of_variation, 40.0);
+    assert_eq!(stats.percentiles[3].weight, 9000.0);
+}
+
+#[test]
+fn test_batch_stats_have_no_nan_for_tiny_batches() {
+    let empty = BatchStats::from_weights(&[], &[50.0]);
+    assert_eq!(empty.count, 0);
+    assert_eq!(empty.average_weight, 0.0);
+    assert_eq!(empty.coefficient_of_variation, 0.0);
+    assert!(empty.percentiles.is_empty());
+
+    let single = BatchStats::from_weights(&[12.5], &[10.0, 90.0]);
+    assert_eq!(single.median_weight, 12.5);
+    assert_eq!(single.std_deviation, 0.0);
+    assert_eq!(single.coefficient_of_variation, 0.0);
+    assert_eq!(single.uniformity, 100.0);
+    assert!(single.percentiles.iter().all(|p| p.weight == 12.5));
+}
+
+#[test]
+fn test_item_id_pattern() {
+    let rules = |pattern: &str| ValidationRules { item_id_pattern: Some(pattern.to_string()), ..ValidationRules::default() };
+    assert!(ValidationRules::default().item_id_matches("anything"));
+    assert!(rules("COW-####").item_id_matches("COW-0042"));
+    assert!(!rules("COW-####").item_id_matches("COW-42"));
+    assert!(!rules("COW-####").item_id_matches("COW-00042"));
+    assert!(rules("chick_*").item_id_matches("chick_"));
+    assert!(rules("*_?b*").item_id_matches("pen_3_ab_12"));
+    assert!(!rules("*_?b*").item_id_matches("pen_3_a"));
+    assert!(rules("**").item_id_matches(""));
+}
+
+#[test]
+fn test_histogram_buckets() {
+    let weights = [452.0, 470.0, 499.9, 500.0, 515.0, 560.0];
+    let counts = |histogram: &Histogram| histogram.buckets.iter().map(|b| (b.min_weight, b.max_weight, b.count)).collect::<Vec<_>>();
+
+    let by_width = Histogram::from_weights(&weights, &HistogramBuckets::Width(50.0), WeightUnit::Kilogram);
+    assert_eq!(counts(&by_width), vec![(450.0, 500.0, 3), (500.0, 550.0, 2), (550.0, 600.0, 1)]);
+    assert_eq!(by_width.total, 6);
+
+    let by_count = Histogram::from_weights(&weights, &HistogramBuckets::Count(4), WeightUnit::Kilogram);
+    assert_eq!(counts(&by_count), vec![(452.0, 479.0, 2), (479.0, 506.0, 2), (506.0, 533.0, 1), (533.0, 560.0, 1)]);
+
+    let band = |name: &str, min_weight, max_weight| GradeBand { name: name.to_string(), min_weight, max_weight };
+    let graded = Histogram::from_weights(
+        &weights,
+        &HistogramBuckets::Bands(vec![band("A", 500.0, 550.0), band("B", 450.0, 500.0)]),
+        WeightUnit::Kilogram,
+    );
+    assert_eq!(graded.buckets[0].label.as_deref(), Some("A"));
+    assert_eq!(counts(&graded), vec![(500.0, 550.0, 2), (450.0, 500.0, 3)]);
+    assert_eq!(graded.ungraded, 1);
+
+    // Identical weights land in a single count bucket, and nothing is not an error
+    let same = Histogram::from_weights(&[7.0, 7.0], &HistogramBuckets::Count(3), WeightUnit::Kilogram);
+    assert_eq!(same.buckets.iter().map(|b| b.count).collect::<Vec<_>>(), vec![2, 0, 0]);
+    assert!(Histogram::from_weights(&[], &HistogramBuckets::Width(10.0), WeightUnit::Kilogram).buckets.is_empty());
+}
+
+#[test]
+fn test_item_timeline_deltas_and_daily_gain() {
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+    let reading = |day: u64, weight: f64| Weight {
+        owner: candid::Principal::anonymous(),
+        batch_id: "batch_1".to_string(),
+        item_id: "calf_7".to_string(),
+        weight,
+        created_at: day * DAY,
+        updated_at: day * DAY,
+        deleted_at: None,
+        deleted_with_batch: false,
+        entered_unit: WeightUnit::Kilogram,
+        notes: None,
+    };
+
+    let timeline = ItemTimeline::from_weights(
+        "calf_7".to_string(),
+        vec![reading(10, 40.0), reading(20, 48.0), reading(20, 48.5), reading(30, 60.0)],
+        WeightUnit::Kilogram,
+    );
+    let deltas: Vec<_> = timeline.readings.iter().map(|r| (r.delta, r.daily_gain)).collect();
+    assert_eq!(deltas, vec![(None, None), (Some(8.0), Some(0.8)), (Some(0.5), None), (Some(11.5), Some(1.15))]);
+    assert_eq!(timeline.average_daily_gain, Some(1.0));
+
+    let single = ItemTimeline::from_weights("calf_7".to_string(), vec![reading(10, 40.0)], WeightUnit::Kilogram);
+    assert_eq!(single.average_daily_gain, None);
+}
+
+#[test]
+fn test_series_interval_start_in_local_time() {
+    use crate::csv_import::parse_timestamp;
+    use chrono::FixedOffset;
+    let at = |text: &str| parse_timestamp(text).unwrap();
+    let utc = FixedOffset::east_opt(0).unwrap();
+    // A Wednesday evening in UTC, already Thursday at UTC+2
+    let reading = at("2024-05-01T22:30:00Z");
+
+    assert_eq!(SeriesInterval::Day.start_of(reading, utc), at("2024-05-01T00:00:00Z"));
+    assert_eq!(SeriesInterval::Day.start_of(reading, FixedOffset::east_opt(2 * 3600).unwrap()), at("2024-05-01T22:00:00Z"));
+    assert_eq!(SeriesInterval::Week.start_of(reading, utc), at("2024-04-29T00:00:00Z"));
+    assert_eq!(SeriesInterval::Month.start_of(reading, utc), at("2024-05-01T00:00:00Z"));
+    assert_eq!(SeriesInterval::Month.start_of(at("2024-05-01T03:00:00Z"), FixedOffset::west_opt(5 * 3600).unwrap()), at("2024-04-01T05:00:00Z"));
+}
+
+#[test]
+fn test_outlier_warnings() {
+    let batch = [98.0, 99.0, 100.0, 100.0, 101.0, 102.0];
+    let kinds = |warnings: Vec<OutlierWarning>| warnings.into_iter().map(|w| w.kind).collect::<Vec<_>>();
+
+    // A slip of the decimal point trips every check
+    assert_eq!(
+        kinds(OutlierWarning::detect(1000.0, &batch, Some((100.0, 0)))),
+        vec![OutlierKind::OutsideBatchRange, OutlierKind::FarFromBatchAverage, OutlierKind::JumpFromPrevious]
+    );
+    assert!(OutlierWarning::detect(101.0, &batch, Some((95.0, 0))).is_empty());
+
+    // Too few weights to judge the batch, but the jump still counts
+    assert!(OutlierWarning::detect(1000.0, &batch[..4], None).is_empty());
+    let warnings = OutlierWarning::detect(40.0, &batch[..4], Some((100.0, 0)));
+    assert_eq!(warnings, vec![OutlierWarning { kind: OutlierKind::JumpFromPrevious, score: -60.0 }]);
+    assert!(OutlierWarning::detect(140.0, &[], Some((100.0, 0))).is_empty());
+
+    // The jump is per day: a chick quadrupling in its first week is fine,
+    // quadrupling in a day is not
+    let day = 24 * 60 * 60 * 1_000_000_000;
+    assert!(OutlierWarning::detect(0.18, &[], Some((0.04, 7 * day))).is_empty());
+    assert_eq!(kinds(OutlierWarning::detect(0.18, &[], Some((0.04, day)))), vec![OutlierKind::JumpFromPrevious]);
+}
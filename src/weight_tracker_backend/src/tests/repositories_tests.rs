@@ -1,7 +1,13 @@
+use crate::errors::WeightTrackerError;
+use crate::memory::test_memory;
 use crate::models::*;
 use crate::traits::*;
 use crate::repositories::*;
 use candid::Principal;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[derive(Clone)]
 struct MockTimeProvider {
@@ -29,6 +35,8 @@ fn test_batch_storage() {
         created_at: 1000,
         updated_at: 1000,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     let result = storage.create(batch.clone());
     assert!(result.is_ok());
@@ -39,13 +47,16 @@ fn test_batch_storage() {
     assert_eq!(retrieved.unwrap().name, "Test Batch");
 
     // Test Update
-    let update_result = storage.update(&batch.id, "Updated Batch".to_string(), None);
+    let rules = ValidationRules { max_weight: Some(5.0), ..ValidationRules::default() };
+    let update_result = storage.update(&batch.id, "Updated Batch".to_string(), None, WeightUnit::Pound, rules.clone());
     assert!(update_result.is_ok());
 
     // Verify Update
     let updated = storage.get(&batch.id).unwrap();
     assert_eq!(updated.name, "Updated Batch");
     assert_eq!(updated.description, None);
+    assert_eq!(updated.unit, WeightUnit::Pound);
+    assert_eq!(updated.validation, rules);
 
     // Test Delete
     let delete_result = storage.delete(&batch.id);
@@ -59,7 +70,7 @@ fn test_batch_storage() {
 #[test]
 fn test_weight_storage() {
     let time_provider = MockTimeProvider { time: 1000 };
-    let mut storage = WeightStorageImpl::new(time_provider);
+    let storage = WeightStorageImpl::new(time_provider);
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
 
     // Test Create
@@ -76,6 +87,9 @@ fn test_weight_storage() {
         created_at: 1000,
         updated_at: 1000,
         deleted_at: None,
+        deleted_with_batch: false,
+        entered_unit: WeightUnit::Kilogram,
+        notes: None,
     };
     let result = storage.create(key.clone(), weight);
     assert!(result.is_ok());
@@ -86,7 +100,7 @@ fn test_weight_storage() {
     assert_eq!(weights[0].weight, 100.0);
 
     // Test Update
-    let update_result = storage.update(key.clone(), 150.0);
+    let update_result = storage.update(key.clone(), 150.0, owner, None);
     assert!(update_result.is_ok());
 
     // Verify Update
@@ -105,4 +119,414 @@ fn test_weight_storage() {
     let all_weights = storage.get_all(Some("test_batch".to_string()), true);
     assert_eq!(all_weights.len(), 1);
     assert!(all_weights[0].deleted_at.is_some());
-} 
\ No newline at end of file
+} 
+#[test]
+fn test_access_control_storage() {
+    let mut access_control = AccessControlImpl::new();
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    access_control.share_access(owner, collaborator, "batch_a".to_string(), Role::Editor).unwrap();
+    access_control.share_access(owner, collaborator, "batch_b".to_string(), Role::Editor).unwrap();
+
+    assert!(access_control.has_access(owner, collaborator, Some("batch_a")));
+    assert!(access_control.has_access(owner, collaborator, Some("batch_b")));
+    assert!(!access_control.has_access(owner, collaborator, Some("batch_c")));
+    assert_eq!(access_control.get_collaborators(owner), vec![collaborator]);
+
+    access_control.remove_access(owner, collaborator).unwrap();
+    assert!(!access_control.has_access(owner, collaborator, Some("batch_a")));
+    assert!(access_control.get_collaborators(owner).is_empty());
+}
+
+#[test]
+fn test_remove_batch_access_keeps_other_batches() {
+    let mut access_control = AccessControlImpl::new();
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    access_control.share_access(owner, collaborator, "batch_a".to_string(), Role::Editor).unwrap();
+    access_control.share_access(owner, collaborator, "batch_b".to_string(), Role::Editor).unwrap();
+
+    access_control.remove_batch_access(owner, collaborator, "batch_a").unwrap();
+    assert!(!access_control.has_access(owner, collaborator, Some("batch_a")));
+    assert!(access_control.has_access(owner, collaborator, Some("batch_b")));
+    assert!(access_control.get_batch_collaborators(owner, "batch_a").is_empty());
+    assert_eq!(
+        access_control.get_batch_collaborators(owner, "batch_b"),
+        vec![BatchCollaborator { collaborator, role: Role::Editor }]
+    );
+
+    let result = access_control.remove_batch_access(owner, collaborator, "batch_a");
+    assert!(matches!(result, Err(WeightTrackerError::NotFound(_))));
+
+    // Revoking the last batch drops the collaborator entirely
+    access_control.remove_batch_access(owner, collaborator, "batch_b").unwrap();
+    assert!(access_control.get_collaborators(owner).is_empty());
+}
+
+#[test]
+fn test_storage_survives_reinitialization() {
+    // Re-opening the maps on the same stable memory is exactly what happens
+    // after a canister upgrade.
+    let stable_memory = DefaultMemoryImpl::default();
+    let open_stores = |memory: &DefaultMemoryImpl| {
+        let manager = MemoryManager::init(memory.clone());
+        (
+            WeightStore::init(
+                manager.get(MemoryId::new(0)),
+                manager.get(MemoryId::new(4)),
+                manager.get(MemoryId::new(5)),
+                manager.get(MemoryId::new(14)),
+                manager.get(MemoryId::new(11)),
+                manager.get(MemoryId::new(13)),
+                manager.get(MemoryId::new(15)),
+            ),
+            Rc::new(RefCell::new(StableBTreeMap::init(manager.get(MemoryId::new(1))))),
+            Rc::new(RefCell::new(StableBTreeMap::init(manager.get(MemoryId::new(2))))),
+            init_sequence(manager.get(MemoryId::new(6))),
+        )
+    };
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let key = WeightKey {
+        owner,
+        item_id: "test_animal".to_string(),
+        created_at: 1000,
+    };
+
+    {
+        let (weights, batches, permissions, sequence) = open_stores(&stable_memory);
+        let weight_storage = WeightStorageImpl::with_storage(weights, MockTimeProvider { time: 1000 });
+        let mut batch_storage = BatchStorageImpl::with_storage(batches, sequence, MockTimeProvider { time: 1000 });
+        assert_eq!(batch_storage.next_sequence(), 1);
+        let mut access_control = AccessControlImpl::with_storage(permissions);
+
+        batch_storage.create(Batch {
+            id: "test_batch".to_string(),
+            name: "Test Batch".to_string(),
+            owner,
+            description: None,
+            created_at: 1000,
+            updated_at: 1000,
+            deleted_at: None,
+            unit: WeightUnit::Kilogram,
+            validation: ValidationRules::default(),
+        }).unwrap();
+        weight_storage.create(key.clone(), Weight {
+            owner,
+            batch_id: "test_batch".to_string(),
+            item_id: "test_animal".to_string(),
+            weight: 100.0,
+            created_at: 1000,
+            updated_at: 1000,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        }).unwrap();
+        weight_storage.update(key.clone(), 120.0, owner, Some("Reweighed".to_string())).unwrap();
+        access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Editor).unwrap();
+    }
+
+    let (weights, batches, permissions, sequence) = open_stores(&stable_memory);
+    let weight_storage = WeightStorageImpl::with_storage(weights, MockTimeProvider { time: 2000 });
+    let mut batch_storage = BatchStorageImpl::with_storage(batches, sequence, MockTimeProvider { time: 2000 });
+    let access_control = AccessControlImpl::with_storage(permissions);
+
+    assert_eq!(batch_storage.get("test_batch").unwrap().name, "Test Batch");
+    assert_eq!(batch_storage.next_sequence(), 2);
+    let stored = weight_storage.get_all(Some("test_batch".to_string()), false);
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].weight, 120.0);
+    assert!(access_control.has_access(owner, collaborator, Some("test_batch")));
+}
+
+fn indexed_weight(owner: Principal, batch_id: &str, item_id: &str, created_at: u64) -> (WeightKey, Weight) {
+    (
+        WeightKey { owner, item_id: item_id.to_string(), created_at },
+        Weight {
+            owner,
+            batch_id: batch_id.to_string(),
+            item_id: item_id.to_string(),
+            weight: 100.0,
+            created_at,
+            updated_at: created_at,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        },
+    )
+}
+
+#[test]
+fn test_weight_index_lookups() {
+    let storage = WeightStorageImpl::new(MockTimeProvider { time: 5000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let other_owner = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+
+    for (owner, batch_id, item_id, created_at) in [
+        (owner, "batch_a", "cow_1", 1000),
+        (owner, "batch_a", "cow_1", 2000),
+        (owner, "batch_b", "cow_2", 1500),
+        (other_owner, "batch_c", "cow_1", 1000),
+        (other_owner, "batch_ab", "cow_3", 3000),
+    ] {
+        let (key, weight) = indexed_weight(owner, batch_id, item_id, created_at);
+        storage.create(key, weight).unwrap();
+    }
+    storage.delete(WeightKey { owner, item_id: "cow_1".to_string(), created_at: 2000 }).unwrap();
+
+    // A batch ID that is a prefix of another must not pick up its weights
+    assert_eq!(storage.get_all(Some("batch_a".to_string()), true).len(), 2);
+    assert_eq!(storage.get_all(Some("batch_a".to_string()), false).len(), 1);
+    assert!(storage.get_all(Some("missing".to_string()), true).is_empty());
+
+    let same_instant = storage.find_at("cow_1", 1000);
+    assert_eq!(same_instant.len(), 2);
+    assert!(same_instant.iter().any(|w| w.owner == other_owner));
+    assert!(storage.find_at("cow_1", 1001).is_empty());
+
+    let created: Vec<u64> = storage.get_created_between(1000, 2000, true)
+        .iter()
+        .map(|w| w.created_at)
+        .collect();
+    assert_eq!(created, vec![1000, 1000, 1500, 2000]);
+
+    // Recording the same key into another batch moves it between batch indexes
+    let (key, weight) = indexed_weight(owner, "batch_b", "cow_1", 1000);
+    storage.create(key, weight).unwrap();
+    assert_eq!(storage.get_all(Some("batch_a".to_string()), true).len(), 1);
+    assert_eq!(storage.get_all(Some("batch_b".to_string()), true).len(), 2);
+}
+
+#[test]
+fn test_rebuild_weight_indexes() {
+    let store = WeightStore::init(test_memory(), test_memory(), test_memory(), test_memory(), test_memory(), test_memory(), test_memory());
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+
+    // Records written by a release that kept no indexes
+    for created_at in [1000, 2000, 3000] {
+        let (key, weight) = indexed_weight(owner, "batch_a", "cow_1", created_at);
+        store.records.borrow_mut().insert(key, weight);
+    }
+    assert!(!store.indexes_in_sync());
+
+    // Halfway through, lookups still find every record, in index order
+    store.start_index_rebuild();
+    assert!(store.index_next(2));
+    let storage = WeightStorageImpl::with_storage(store.clone(), MockTimeProvider { time: 5000 });
+    let (key, weight) = indexed_weight(owner, "batch_a", "cow_1", 500);
+    storage.create(key, weight).unwrap();
+    let times = |weights: Vec<Weight>| weights.iter().map(|w| w.created_at).collect::<Vec<_>>();
+    assert_eq!(times(storage.get_for_item("cow_1", false)), vec![500, 1000, 2000, 3000]);
+    assert_eq!(times(storage.get_created_between(1500, 5000, false)), vec![2000, 3000]);
+    assert_eq!(storage.get_all(Some("batch_a".to_string()), false).len(), 4);
+    assert_eq!(storage.find_at("cow_1", 3000).len(), 1);
+
+    assert!(!store.index_next(2));
+    assert!(store.indexes_in_sync());
+    assert_eq!(storage.get_all(Some("batch_a".to_string()), false).len(), 4);
+    assert_eq!(storage.find_at("cow_1", 2000).len(), 1);
+}
+
+#[test]
+fn test_weights_for_item_span_owners_in_time_order() {
+    let storage = WeightStorageImpl::new(MockTimeProvider { time: 5000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let other = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    for (who, batch_id, item_id, created_at) in [
+        (owner, "batch_a", "cow_1", 3000),
+        (other, "batch_b", "cow_1", 2000),
+        (owner, "batch_b", "cow_1", 1000),
+        (owner, "batch_a", "cow_10", 1500),
+    ] {
+        let (key, weight) = indexed_weight(who, batch_id, item_id, created_at);
+        storage.create(key, weight).unwrap();
+    }
+    storage.delete(WeightKey { owner, item_id: "cow_1".to_string(), created_at: 3000 }).unwrap();
+
+    let times = |weights: Vec<Weight>| weights.iter().map(|w| w.created_at).collect::<Vec<_>>();
+    assert_eq!(times(storage.get_for_item("cow_1", true)), vec![1000, 2000, 3000]);
+    assert_eq!(times(storage.get_for_item("cow_1", false)), vec![1000, 2000]);
+    assert!(storage.get_for_item("cow", true).is_empty());
+}
+
+#[test]
+fn test_batch_create_refuses_existing_id() {
+    let mut storage = BatchStorageImpl::new(MockTimeProvider { time: 1000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let batch = |name: &str| Batch {
+        id: "batch_1000_1".to_string(),
+        name: name.to_string(),
+        owner,
+        description: None,
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
+    };
+
+    storage.create(batch("First")).unwrap();
+    let result = storage.create(batch("Second"));
+
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+    assert_eq!(storage.get("batch_1000_1").unwrap().name, "First");
+}
+
+#[test]
+fn test_weight_delete_with_batch_and_restore() {
+    let storage = WeightStorageImpl::new(MockTimeProvider { time: 5000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (key, weight) = indexed_weight(owner, "batch_a", "cow_1", 1000);
+    storage.create(key.clone(), weight).unwrap();
+
+    storage.delete_with_batch(key.clone()).unwrap();
+    let deleted = &storage.get_all(Some("batch_a".to_string()), true)[0];
+    assert_eq!(deleted.deleted_at, Some(5000));
+    assert!(deleted.deleted_with_batch);
+
+    storage.restore(key).unwrap();
+    let restored = &storage.get_all(Some("batch_a".to_string()), false)[0];
+    assert!(restored.deleted_at.is_none());
+    assert!(!restored.deleted_with_batch);
+}
+
+#[test]
+fn test_purge_deleted_weights_and_batches() {
+    let store = WeightStore::init(test_memory(), test_memory(), test_memory(), test_memory(), test_memory(), test_memory(), test_memory());
+    let weights = WeightStorageImpl::with_storage(store.clone(), MockTimeProvider { time: 1000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    for created_at in [1, 2, 3] {
+        let (key, weight) = indexed_weight(owner, "batch_a", "cow_1", created_at);
+        weights.create(key, weight).unwrap();
+    }
+    let purged_key = WeightKey { owner, item_id: "cow_1".to_string(), created_at: 1 };
+    weights.update(purged_key.clone(), 20.0, owner, None).unwrap();
+    weights.delete(purged_key.clone()).unwrap();
+
+    assert!(weights.purge_deleted_before(1000).is_empty());
+    assert_eq!(weights.purge_deleted_before(1001).len(), 1);
+    assert!(store.indexes_in_sync());
+    assert_eq!(weights.get_all(Some("batch_a".to_string()), true).len(), 2);
+    assert!(weights.find_at("cow_1", 1).is_empty());
+    assert!(weights.get_history(&purged_key).is_empty());
+
+    let mut batches = BatchStorageImpl::new(MockTimeProvider { time: 1000 });
+    for id in ["batch_a", "batch_b"] {
+        batches.create(Batch {
+            id: id.to_string(),
+            name: id.to_string(),
+            owner,
+            description: None,
+            created_at: 1,
+            updated_at: 1,
+            deleted_at: None,
+            unit: WeightUnit::Kilogram,
+            validation: ValidationRules::default(),
+        }).unwrap();
+    }
+    batches.delete("batch_b").unwrap();
+
+    let purged = batches.purge_deleted_before(1001);
+    assert_eq!(purged.len(), 1);
+    assert_eq!(purged[0].id, "batch_b");
+    assert!(batches.get("batch_b").is_none());
+    assert!(batches.get("batch_a").is_some());
+}
+
+#[test]
+fn test_audit_log_indexes_entries_by_batch_and_item() {
+    let log = AuditLogImpl::new(MockTimeProvider { time: 1000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let weight_event = |batch_id: &str, item_id: &str| AuditEvent {
+        actor: owner,
+        action: AuditAction::Create,
+        owner,
+        batch_id: batch_id.to_string(),
+        target: AuditTarget::Weight(WeightKey { owner, item_id: item_id.to_string(), created_at: 1 }),
+        before: None,
+        after: Some(AuditValue::Weight { weight: 10.0, deleted_at: None }),
+    };
+
+    assert_eq!(log.record(weight_event("batch_a", "cow_1")), 0);
+    assert_eq!(log.record(weight_event("batch_b", "cow_1")), 1);
+    assert_eq!(log.record(AuditEvent {
+        target: AuditTarget::Batch,
+        ..weight_event("batch_a", "")
+    }), 2);
+    assert_eq!(log.record(weight_event("batch_a", "cow_2")), 3);
+
+    let ids = |entries: Vec<AuditEntry>| entries.into_iter().map(|e| e.id).collect::<Vec<_>>();
+    assert_eq!(ids(log.get_for_batch("batch_a", None, 10)), vec![0, 2, 3]);
+    assert_eq!(ids(log.get_for_batch("batch_a", Some(0), 1)), vec![2]);
+    assert_eq!(ids(log.get_for_item("cow_1", None, 10)), vec![0, 1]);
+    assert_eq!(ids(log.get_for_item("cow_1", Some(1), 10)), Vec::<u64>::new());
+    assert_eq!(log.get_for_item("cow_2", None, 10)[0].recorded_at, 1000);
+}
+
+#[test]
+fn test_api_token_storage() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let other = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let tokens = ApiTokenStorageImpl::new();
+    let token = |owner: Principal, label: &str| ApiToken { owner, label: label.to_string(), created_at: 1000 };
+    tokens.create("hash_1".to_string(), token(owner, "Reports"));
+    tokens.create("hash_2".to_string(), token(other, "Dashboards"));
+
+    assert_eq!(tokens.get("hash_1"), Some(token(owner, "Reports")));
+    assert_eq!(tokens.get_for_owner(other), vec![("hash_2".to_string(), token(other, "Dashboards"))]);
+    assert_eq!(tokens.delete("hash_1"), Some(token(owner, "Reports")));
+    assert_eq!(tokens.get("hash_1"), None);
+    assert_eq!(tokens.delete("hash_1"), None);
+}
+
+#[test]
+fn test_client_requests_are_forgotten_after_cutoff() {
+    let weights = WeightStorageImpl::new(MockTimeProvider { time: 1000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let key = WeightKey { owner, item_id: "ITEM_001".to_string(), created_at: 1000 };
+    weights.record_client_request(owner, "retry-1".to_string(), key.clone());
+
+    assert_eq!(
+        weights.find_client_request(owner, "retry-1"),
+        Some(ClientRequest { key, recorded_at: 1000 })
+    );
+    assert_eq!(weights.find_client_request(Principal::anonymous(), "retry-1"), None);
+
+    assert_eq!(weights.forget_client_requests_before(1000), 0);
+    assert_eq!(weights.forget_client_requests_before(1001), 1);
+    assert_eq!(weights.find_client_request(owner, "retry-1"), None);
+}
+
+#[test]
+fn test_weight_flags_are_confirmed_and_purged() {
+    let weights = WeightStorageImpl::new(MockTimeProvider { time: 1000 });
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (key, weight) = indexed_weight(owner, "batch_a", "cow_1", 1);
+    weights.create(key.clone(), weight).unwrap();
+    let warnings = vec![OutlierWarning { kind: OutlierKind::JumpFromPrevious, score: 900.0 }];
+
+    assert!(matches!(weights.confirm_flag(&key, owner), Err(WeightTrackerError::NotFound(_))));
+    weights.set_flag(key.clone(), warnings.clone());
+    weights.confirm_flag(&key, owner).unwrap();
+    assert_eq!(weights.get_flag(&key), Some(WeightFlag {
+        warnings: warnings.clone(),
+        flagged_at: 1000,
+        confirmed_by: Some(owner),
+        confirmed_at: Some(1000),
+    }));
+
+    // Flagging again starts over, and no warnings clears the flag
+    weights.set_flag(key.clone(), warnings.clone());
+    assert_eq!(weights.get_flag(&key).unwrap().confirmed_at, None);
+    weights.set_flag(key.clone(), Vec::new());
+    assert_eq!(weights.get_flag(&key), None);
+
+    weights.set_flag(key.clone(), warnings);
+    weights.delete(key.clone()).unwrap();
+    assert_eq!(weights.purge_deleted_before(1001).len(), 1);
+    assert_eq!(weights.get_flag(&key), None);
+}
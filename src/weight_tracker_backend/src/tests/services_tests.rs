@@ -1,21 +1,22 @@
+use crate::errors::WeightTrackerError;
 use crate::models::*;
 use crate::traits::*;
 use crate::services::*;
 use candid::Principal;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 // Mock implementations
 #[derive(Clone)]
 struct MockAccessControl {
-    permissions: Arc<RefCell<HashMap<String, SharingPermissions>>>,
+    permissions: Rc<RefCell<HashMap<String, SharingPermissions>>>,
 }
 
 impl MockAccessControl {
     fn new() -> Self {
         Self {
-            permissions: Arc::new(RefCell::new(HashMap::new()))
+            permissions: Rc::new(RefCell::new(HashMap::new()))
         }
     }
 }
@@ -29,16 +30,22 @@ impl AccessControl for MockAccessControl {
         self.permissions.borrow()
             .get(&data_owner.to_string())
             .and_then(|p| p.shared_with.get(&accessor))
-            .map(|batch_ids| {
+            .map(|grants| {
                 match batch_id {
-                    Some(bid) => batch_ids.contains(bid),
-                    None => !batch_ids.is_empty()
+                    Some(bid) => grants.contains_key(bid),
+                    None => !grants.is_empty()
                 }
             })
             .unwrap_or(false)
     }
 
-    fn share_access(&mut self, owner: Principal, collaborator: Principal, batch_id: String) -> Result<(), String> {
+    fn get_role(&self, data_owner: Principal, accessor: Principal, batch_id: &str) -> Option<Role> {
+        self.permissions.borrow()
+            .get(&data_owner.to_string())
+            .and_then(|p| p.shared_with.get(&accessor)?.get(batch_id).copied())
+    }
+
+    fn share_access(&mut self, owner: Principal, collaborator: Principal, batch_id: String, role: Role) -> Result<(), WeightTrackerError> {
         let mut perms = self.permissions.borrow_mut();
         let owner_key = owner.to_string();
         
@@ -50,19 +57,32 @@ impl AccessControl for MockAccessControl {
             
         sharing.shared_with
             .entry(collaborator)
-            .or_insert_with(HashSet::new)
-            .insert(batch_id);
+            .or_default()
+            .insert(batch_id, role);
         
         Ok(())
     }
 
-    fn remove_access(&mut self, owner: Principal, collaborator: Principal) -> Result<(), String> {
+    fn remove_access(&mut self, owner: Principal, collaborator: Principal) -> Result<(), WeightTrackerError> {
         let mut perms = self.permissions.borrow_mut();
         if let Some(sharing) = perms.get_mut(&owner.to_string()) {
             sharing.shared_with.remove(&collaborator);
             Ok(())
         } else {
-            Err("No sharing permissions found".to_string())
+            Err(WeightTrackerError::not_found("No sharing permissions found"))
+        }
+    }
+
+    fn remove_batch_access(&mut self, owner: Principal, collaborator: Principal, batch_id: &str) -> Result<(), WeightTrackerError> {
+        let mut perms = self.permissions.borrow_mut();
+        let removed = perms.get_mut(&owner.to_string())
+            .and_then(|p| p.shared_with.get_mut(&collaborator))
+            .map(|grants| grants.remove(batch_id).is_some())
+            .unwrap_or(false);
+        if removed {
+            Ok(())
+        } else {
+            Err(WeightTrackerError::not_found("Batch is not shared with this user"))
         }
     }
 
@@ -70,25 +90,42 @@ impl AccessControl for MockAccessControl {
         self.permissions.borrow()
             .get(&owner.to_string())
             .map(|p| p.shared_with.keys().cloned().collect())
-            .unwrap_or_else(Vec::new)
+            .unwrap_or_default()
+    }
+
+    fn get_batch_collaborators(&self, owner: Principal, batch_id: &str) -> Vec<BatchCollaborator> {
+        self.permissions.borrow()
+            .get(&owner.to_string())
+            .map(|p| p.shared_with.iter()
+                .filter_map(|(collaborator, grants)| {
+                    grants.get(batch_id).map(|role| BatchCollaborator { collaborator: *collaborator, role: *role })
+                })
+                .collect())
+            .unwrap_or_default()
     }
 }
 
 #[derive(Clone)]
 struct MockWeightRepo {
     weights: RefCell<HashMap<WeightKey, Weight>>,
+    history: RefCell<HashMap<WeightKey, Vec<WeightRevision>>>,
+    client_requests: RefCell<HashMap<(Principal, String), ClientRequest>>,
+    flags: RefCell<HashMap<WeightKey, WeightFlag>>,
 }
 
 impl MockWeightRepo {
     fn new() -> Self {
         Self {
-            weights: RefCell::new(HashMap::new())
+            weights: RefCell::new(HashMap::new()),
+            history: RefCell::new(HashMap::new()),
+            client_requests: RefCell::new(HashMap::new()),
+            flags: RefCell::new(HashMap::new()),
         }
     }
 }
 
 impl WeightRepository for MockWeightRepo {
-    fn create(&self, key: WeightKey, weight: Weight) -> Result<(), String> {
+    fn create(&self, key: WeightKey, weight: Weight) -> Result<(), WeightTrackerError> {
         self.weights.borrow_mut().insert(key, weight);
         Ok(())
     }
@@ -106,24 +143,128 @@ impl WeightRepository for MockWeightRepo {
             .collect()
     }
 
-    fn update(&self, key: WeightKey, weight: f64) -> Result<(), String> {
+    fn update(&self, key: WeightKey, weight: f64, changed_by: Principal, reason: Option<String>) -> Result<(), WeightTrackerError> {
         if let Some(entry) = self.weights.borrow_mut().get_mut(&key) {
+            self.history.borrow_mut().entry(key).or_default().push(WeightRevision {
+                weight: entry.weight,
+                valid_from: entry.updated_at,
+                replaced_at: 1000,
+                replaced_by: changed_by,
+                reason,
+            });
             entry.weight = weight;
             entry.updated_at = 1000;
             Ok(())
         } else {
-            Err("Weight not found".to_string())
+            Err(WeightTrackerError::not_found("Weight not found"))
+        }
+    }
+
+    fn get_history(&self, key: &WeightKey) -> Vec<WeightRevision> {
+        self.history.borrow().get(key).cloned().unwrap_or_default()
+    }
+
+    fn delete(&self, key: WeightKey) -> Result<(), WeightTrackerError> {
+        if let Some(entry) = self.weights.borrow_mut().get_mut(&key) {
+            entry.deleted_at = Some(1000);
+            entry.deleted_with_batch = false;
+            Ok(())
+        } else {
+            Err(WeightTrackerError::not_found("Weight not found"))
         }
     }
 
-    fn delete(&self, key: WeightKey) -> Result<(), String> {
+    fn delete_with_batch(&self, key: WeightKey) -> Result<(), WeightTrackerError> {
         if let Some(entry) = self.weights.borrow_mut().get_mut(&key) {
             entry.deleted_at = Some(1000);
+            entry.deleted_with_batch = true;
             Ok(())
         } else {
-            Err("Weight not found".to_string())
+            Err(WeightTrackerError::not_found("Weight not found"))
         }
     }
+
+    fn restore(&self, key: WeightKey) -> Result<(), WeightTrackerError> {
+        if let Some(entry) = self.weights.borrow_mut().get_mut(&key) {
+            entry.deleted_at = None;
+            entry.deleted_with_batch = false;
+            Ok(())
+        } else {
+            Err(WeightTrackerError::not_found("Weight not found"))
+        }
+    }
+
+    fn purge_deleted_before(&self, cutoff: u64) -> Vec<Weight> {
+        let mut weights = self.weights.borrow_mut();
+        let expired: Vec<Weight> = weights.values()
+            .filter(|w| w.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff))
+            .cloned()
+            .collect();
+        weights.retain(|_, w| w.deleted_at.is_none_or(|deleted_at| deleted_at >= cutoff));
+        expired
+    }
+
+    fn find_at(&self, item_id: &str, created_at: u64) -> Vec<Weight> {
+        self.weights.borrow()
+            .values()
+            .filter(|w| w.item_id == item_id && w.created_at == created_at)
+            .cloned()
+            .collect()
+    }
+
+    fn get_created_between(&self, from: u64, to: u64, include_deleted: bool) -> Vec<Weight> {
+        self.weights.borrow()
+            .values()
+            .filter(|w| w.created_at >= from && w.created_at <= to && (include_deleted || w.deleted_at.is_none()))
+            .cloned()
+            .collect()
+    }
+
+    fn get_for_item(&self, item_id: &str, include_deleted: bool) -> Vec<Weight> {
+        let mut weights: Vec<Weight> = self.weights.borrow()
+            .values()
+            .filter(|w| w.item_id == item_id && (include_deleted || w.deleted_at.is_none()))
+            .cloned()
+            .collect();
+        weights.sort_by_key(|w| w.created_at);
+        weights
+    }
+
+    fn set_flag(&self, key: WeightKey, warnings: Vec<OutlierWarning>) {
+        if warnings.is_empty() {
+            self.flags.borrow_mut().remove(&key);
+        } else {
+            self.flags.borrow_mut().insert(key, WeightFlag { warnings, flagged_at: 1000, confirmed_by: None, confirmed_at: None });
+        }
+    }
+
+    fn get_flag(&self, key: &WeightKey) -> Option<WeightFlag> {
+        self.flags.borrow().get(key).cloned()
+    }
+
+    fn confirm_flag(&self, key: &WeightKey, confirmed_by: Principal) -> Result<(), WeightTrackerError> {
+        let mut flags = self.flags.borrow_mut();
+        let flag = flags.get_mut(key)
+            .ok_or_else(|| WeightTrackerError::not_found("This weight is not flagged"))?;
+        flag.confirmed_by = Some(confirmed_by);
+        flag.confirmed_at = Some(1000);
+        Ok(())
+    }
+
+    fn find_client_request(&self, caller: Principal, client_request_id: &str) -> Option<ClientRequest> {
+        self.client_requests.borrow().get(&(caller, client_request_id.to_string())).cloned()
+    }
+
+    fn record_client_request(&self, caller: Principal, client_request_id: String, key: WeightKey) {
+        self.client_requests.borrow_mut().insert((caller, client_request_id), ClientRequest { key, recorded_at: 1000 });
+    }
+
+    fn forget_client_requests_before(&self, cutoff: u64) -> u64 {
+        let mut client_requests = self.client_requests.borrow_mut();
+        let before = client_requests.len();
+        client_requests.retain(|_, request| request.recorded_at >= cutoff);
+        (before - client_requests.len()) as u64
+    }
 }
 
 #[derive(Clone)]
@@ -137,22 +278,75 @@ impl TimeProvider for MockTimeProvider {
     }
 }
 
+#[derive(Clone)]
+struct MockAuditLog {
+    entries: Rc<RefCell<Vec<AuditEntry>>>,
+}
+
+impl MockAuditLog {
+    fn new() -> Self {
+        Self {
+            entries: Rc::new(RefCell::new(Vec::new()))
+        }
+    }
+
+    fn actions(&self) -> Vec<AuditAction> {
+        self.entries.borrow().iter().map(|e| e.event.action).collect()
+    }
+
+    fn query(&self, after: Option<u64>, limit: usize, matches: impl Fn(&AuditEvent) -> bool) -> Vec<AuditEntry> {
+        self.entries.borrow()
+            .iter()
+            .filter(|e| after.is_none_or(|after| e.id > after) && matches(&e.event))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl AuditLog for MockAuditLog {
+    fn record(&self, event: AuditEvent) -> u64 {
+        let mut entries = self.entries.borrow_mut();
+        let id = entries.len() as u64;
+        entries.push(AuditEntry { id, recorded_at: 1000, event });
+        id
+    }
+
+    fn get_for_batch(&self, batch_id: &str, after: Option<u64>, limit: usize) -> Vec<AuditEntry> {
+        self.query(after, limit, |e| e.batch_id == batch_id)
+    }
+
+    fn get_for_item(&self, item_id: &str, after: Option<u64>, limit: usize) -> Vec<AuditEntry> {
+        self.query(after, limit, |e| matches!(&e.target, AuditTarget::Weight(key) if key.item_id == item_id))
+    }
+}
+
 #[derive(Clone)]
 struct MockBatchRepo {
     batches: RefCell<HashMap<String, Batch>>,
+    sequence: u64,
 }
 
 impl MockBatchRepo {
     fn new() -> Self {
         Self {
-            batches: RefCell::new(HashMap::new())
+            batches: RefCell::new(HashMap::new()),
+            sequence: 0,
         }
     }
 }
 
 impl BatchRepository for MockBatchRepo {
-    fn create(&mut self, batch: Batch) -> Result<String, String> {
+    fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    fn create(&mut self, batch: Batch) -> Result<String, WeightTrackerError> {
         let id = batch.id.clone();
+        if self.batches.borrow().contains_key(&id) {
+            return Err(WeightTrackerError::conflict("Batch already exists"));
+        }
         self.batches.borrow_mut().insert(id.clone(), batch);
         Ok(id)
     }
@@ -169,27 +363,84 @@ impl BatchRepository for MockBatchRepo {
             .collect()
     }
 
-    fn update(&mut self, id: &str, name: String, description: Option<String>) -> Result<(), String> {
+    fn update(&mut self, id: &str, name: String, description: Option<String>, unit: WeightUnit, validation: ValidationRules) -> Result<(), WeightTrackerError> {
         if let Some(batch) = self.batches.borrow_mut().get_mut(id) {
             batch.name = name;
             batch.description = description;
+            batch.unit = unit;
+            batch.validation = validation;
             batch.updated_at = 1000;
             Ok(())
         } else {
-            Err("Batch not found".to_string())
+            Err(WeightTrackerError::not_found("Batch not found"))
         }
     }
 
-    fn delete(&mut self, id: &str) -> Result<(), String> {
+    fn delete(&mut self, id: &str) -> Result<(), WeightTrackerError> {
         if let Some(batch) = self.batches.borrow_mut().get_mut(id) {
             batch.deleted_at = Some(1000);
             Ok(())
         } else {
-            Err("Batch not found".to_string())
+            Err(WeightTrackerError::not_found("Batch not found"))
+        }
+    }
+
+    fn restore(&mut self, id: &str) -> Result<(), WeightTrackerError> {
+        if let Some(batch) = self.batches.borrow_mut().get_mut(id) {
+            batch.deleted_at = None;
+            Ok(())
+        } else {
+            Err(WeightTrackerError::not_found("Batch not found"))
+        }
+    }
+
+    fn purge_deleted_before(&mut self, cutoff: u64) -> Vec<Batch> {
+        let mut batches = self.batches.borrow_mut();
+        let expired: Vec<Batch> = batches.values()
+            .filter(|b| b.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff))
+            .cloned()
+            .collect();
+        for batch in &expired {
+            batches.remove(&batch.id);
+        }
+        expired
+    }
+}
+
+struct MockApiTokenRepo {
+    tokens: RefCell<HashMap<String, ApiToken>>,
+}
+
+impl MockApiTokenRepo {
+    fn new() -> Self {
+        Self {
+            tokens: RefCell::new(HashMap::new()),
         }
     }
 }
 
+impl ApiTokenRepository for MockApiTokenRepo {
+    fn create(&self, hash: String, token: ApiToken) {
+        self.tokens.borrow_mut().insert(hash, token);
+    }
+
+    fn get(&self, hash: &str) -> Option<ApiToken> {
+        self.tokens.borrow().get(hash).cloned()
+    }
+
+    fn get_for_owner(&self, owner: Principal) -> Vec<(String, ApiToken)> {
+        self.tokens.borrow()
+            .iter()
+            .filter(|(_, token)| token.owner == owner)
+            .map(|(hash, token)| (hash.clone(), token.clone()))
+            .collect()
+    }
+
+    fn delete(&self, hash: &str) -> Option<ApiToken> {
+        self.tokens.borrow_mut().remove(hash)
+    }
+}
+
 #[test]
 fn test_access_control_operations() {
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
@@ -197,7 +448,7 @@ fn test_access_control_operations() {
     let mut access_control = MockAccessControl::new();
 
     // Test share access
-    let result = access_control.share_access(owner, collaborator, "test_batch".to_string());
+    let result = access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Editor);
     assert!(result.is_ok());
 
     // Test has access
@@ -235,14 +486,17 @@ fn test_weight_service_create() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateWeightRequest {
@@ -250,6 +504,9 @@ fn test_weight_service_create() {
         batch_id: "test_batch".to_string(),
         item_id: "test_animal".to_string(),
         weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
 
     let result = service.create_weight(request, owner);
@@ -276,14 +533,17 @@ fn test_weight_service_unauthorized() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateWeightRequest {
@@ -291,12 +551,15 @@ fn test_weight_service_unauthorized() {
         batch_id: "test_batch".to_string(),
         item_id: "test_animal".to_string(),
         weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
 
     // Try to create weight as unauthorized user
     let result = service.create_weight(request, unauthorized_user);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Unauthorized"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Unauthorized(_)));
 }
 
 #[test]
@@ -318,14 +581,17 @@ fn test_weight_service_crud_operations() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     // Test Create
@@ -334,12 +600,15 @@ fn test_weight_service_crud_operations() {
         batch_id: "test_batch".to_string(),
         item_id: "test_animal".to_string(),
         weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
     let result = service.create_weight(create_request, owner);
     assert!(result.is_ok());
 
     // Test Read
-    let weights = service.get_weights(Some("test_batch".to_string()), false, owner);
+    let weights = service.get_weights(Some("test_batch".to_string()), false, WeightUnit::Kilogram, owner);
     assert_eq!(weights.len(), 1);
     assert_eq!(weights[0].weight, 100.0);
 
@@ -348,12 +617,14 @@ fn test_weight_service_crud_operations() {
         "test_animal".to_string(),
         now,
         150.0,
+        WeightUnit::Kilogram,
+        None,
         owner
     );
     assert!(update_result.is_ok());
 
     // Verify Update
-    let weights = service.get_weights(Some("test_batch".to_string()), false, owner);
+    let weights = service.get_weights(Some("test_batch".to_string()), false, WeightUnit::Kilogram, owner);
     assert_eq!(weights[0].weight, 150.0);
 
     // Test Delete
@@ -365,7 +636,7 @@ fn test_weight_service_crud_operations() {
     assert!(delete_result.is_ok());
 
     // Verify Delete
-    let weights = service.get_weights(Some("test_batch".to_string()), false, owner);
+    let weights = service.get_weights(Some("test_batch".to_string()), false, WeightUnit::Kilogram, owner);
     assert_eq!(weights.len(), 0);
 }
 
@@ -383,13 +654,16 @@ fn test_batch_service() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     // Test create batch
     let request = CreateBatchRequest {
         name: "Test Batch".to_string(),
         description: Some("Test Description".to_string()),
+        unit: None,
+        validation: None,
     };
 
     let result = service.create_batch(request, owner);
@@ -397,7 +671,7 @@ fn test_batch_service() {
 
     // Test get batch
     let batch_id = result.unwrap();
-    let result = service.get_batch(&batch_id, owner);
+    let result = service.get_batch(&batch_id, WeightUnit::Kilogram, &[], owner);
     assert!(result.is_ok());
     let batch_with_stats = result.unwrap();
     assert_eq!(batch_with_stats.batch.name, "Test Batch");
@@ -417,20 +691,23 @@ fn test_batch_service_crud_operations() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     // Test Create
     let create_request = CreateBatchRequest {
         name: "Test Batch".to_string(),
         description: Some("Test Description".to_string()),
+        unit: None,
+        validation: None,
     };
     let result = service.create_batch(create_request, owner);
     assert!(result.is_ok());
     let batch_id = result.unwrap();
 
     // Test Read
-    let batches = service.get_batches(false, owner);
+    let batches = service.get_batches(false, WeightUnit::Kilogram, &[], owner);
     assert_eq!(batches.len(), 1);
     assert_eq!(batches[0].batch.name, "Test Batch");
 
@@ -438,12 +715,14 @@ fn test_batch_service_crud_operations() {
     let update_request = UpdateBatchRequest {
         name: "Updated Batch".to_string(),
         description: None,
+        unit: None,
+        validation: None,
     };
     let update_result = service.update_batch(&batch_id, update_request, owner);
     assert!(update_result.is_ok());
 
     // Verify Update
-    let batch = service.get_batch(&batch_id, owner).unwrap();
+    let batch = service.get_batch(&batch_id, WeightUnit::Kilogram, &[], owner).unwrap();
     assert_eq!(batch.batch.name, "Updated Batch");
     assert_eq!(batch.batch.description, None);
 
@@ -452,7 +731,7 @@ fn test_batch_service_crud_operations() {
     assert!(delete_result.is_ok());
 
     // Verify Delete
-    let batches = service.get_batches(false, owner);
+    let batches = service.get_batches(false, WeightUnit::Kilogram, &[], owner);
     assert_eq!(batches.len(), 0);
 }
 
@@ -462,7 +741,7 @@ fn test_batch_stats_calculation() {
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
     let time_provider = MockTimeProvider { time: now };
     
-    let mut weight_repo = MockWeightRepo::new();
+    let weight_repo = MockWeightRepo::new();
     let mut batch_repo = MockBatchRepo::new();
     let access_control = MockAccessControl::new();
 
@@ -475,11 +754,13 @@ fn test_batch_stats_calculation() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
     // Add some weights
-    let weights = vec![10.0, 20.0, 30.0, 40.0, 50.0];
+    let weights = [10.0, 20.0, 30.0, 40.0, 50.0];
     for (i, &weight) in weights.iter().enumerate() {
         let key = WeightKey {
             owner,
@@ -494,6 +775,9 @@ fn test_batch_stats_calculation() {
             created_at: now + i as u64,
             updated_at: now + i as u64,
             deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
         };
         weight_repo.create(key, weight_entry).unwrap();
     }
@@ -502,10 +786,11 @@ fn test_batch_stats_calculation() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
-    let stats = service.calculate_batch_stats("test_batch");
+    let stats = service.calculate_batch_stats("test_batch", &[]);
     assert_eq!(stats.count, 5);
     assert_eq!(stats.min_weight, 10.0);
     assert_eq!(stats.max_weight, 50.0);
@@ -526,17 +811,20 @@ fn test_empty_batch_name() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateBatchRequest {
         name: "".to_string(),
         description: None,
+        unit: None,
+        validation: None,
     };
 
     let result = service.create_batch(request, owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("empty"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "name"));
 }
 
 #[test]
@@ -549,11 +837,12 @@ fn test_invalid_batch_id() {
     let batch_repo = MockBatchRepo::new();
     let access_control = MockAccessControl::new();
     
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateWeightRequest {
@@ -561,11 +850,14 @@ fn test_invalid_batch_id() {
         batch_id: "nonexistent_batch".to_string(),
         item_id: "test_animal".to_string(),
         weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
 
     let result = service.create_weight(request, owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Invalid batch ID"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::NotFound(_)));
 }
 
 #[test]
@@ -588,6 +880,8 @@ fn test_update_batch_unauthorized() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
@@ -595,17 +889,20 @@ fn test_update_batch_unauthorized() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let update_request = UpdateBatchRequest {
         name: "Updated Name".to_string(),
         description: None,
+        unit: None,
+        validation: None,
     };
 
     let result = service.update_batch("test_batch", update_request, unauthorized);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Unauthorized"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Unauthorized(_)));
 }
 
 #[test]
@@ -628,6 +925,8 @@ fn test_delete_batch_unauthorized() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
@@ -635,12 +934,13 @@ fn test_delete_batch_unauthorized() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let result = service.delete_batch("test_batch", unauthorized);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Unauthorized"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Unauthorized(_)));
 }
 
 #[test]
@@ -648,11 +948,11 @@ fn test_share_with_self() {
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
     let access_control = MockAccessControl::new();
     let batch_repo = MockBatchRepo::new();
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
-    let result = service.share_with_user(owner, owner, "test_batch".to_string());
+    let result = service.share_with_user(owner, owner, "test_batch".to_string(), Role::Editor);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Cannot share data with yourself"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "collaborator"));
 }
 
 #[test]
@@ -669,12 +969,13 @@ fn test_get_nonexistent_batch() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
-    let result = service.get_batch("nonexistent", owner);
+    let result = service.get_batch("nonexistent", WeightUnit::Kilogram, &[], owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("not found"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::NotFound(_)));
 }
 
 #[test]
@@ -688,14 +989,16 @@ fn test_batch_stats_with_deleted_batch() {
     let access_control = MockAccessControl::new();
     
     // Create a deleted batch
-    let mut batch = Batch {
+    let batch = Batch {
         id: "test_batch".to_string(),
         name: "Test Batch".to_string(),
         owner,
         description: None,
         created_at: now,
         updated_at: now,
-        deleted_at: Some(now),  // Deleted batch
+        deleted_at: Some(now),  // Deleted batch,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
@@ -703,10 +1006,11 @@ fn test_batch_stats_with_deleted_batch() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
-    let stats = service.calculate_batch_stats("test_batch");
+    let stats = service.calculate_batch_stats("test_batch", &[]);
     assert_eq!(stats.count, 0);
     assert_eq!(stats.min_weight, 0.0);
     assert_eq!(stats.max_weight, 0.0);
@@ -732,26 +1036,32 @@ fn test_negative_weight_value() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateWeightRequest {
         owner_override: None,
         batch_id: "test_batch".to_string(),
         item_id: "test_animal".to_string(),
-        weight: -100.0,  // Negative weight
+        weight: -100.0,  // Negative weight,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
 
     let result = service.create_weight(request, owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Invalid weight value"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "weight"));
 }
 
 #[test]
@@ -760,7 +1070,7 @@ fn test_update_deleted_weight() {
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
     let time_provider = MockTimeProvider { time: now };
     
-    let mut weight_repo = MockWeightRepo::new();
+    let weight_repo = MockWeightRepo::new();
     let mut batch_repo = MockBatchRepo::new();
     let access_control = MockAccessControl::new();
 
@@ -773,6 +1083,8 @@ fn test_update_deleted_weight() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
@@ -790,24 +1102,30 @@ fn test_update_deleted_weight() {
         created_at: now,
         updated_at: now,
         deleted_at: Some(now),  // Deleted weight
+        deleted_with_batch: false,
+        entered_unit: WeightUnit::Kilogram,
+        notes: None,
     };
     weight_repo.create(key, weight).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let result = service.update_weight(
         "test_animal".to_string(),
         now,
         150.0,
+        WeightUnit::Kilogram,
+        None,
         owner
     );
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Cannot update deleted weight"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::AlreadyDeleted(_)));
 }
 
 #[test]
@@ -828,7 +1146,9 @@ fn test_delete_already_deleted_batch() {
         description: None,
         created_at: now,
         updated_at: now,
-        deleted_at: Some(now),  // Already deleted
+        deleted_at: Some(now),  // Already deleted,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
@@ -836,12 +1156,13 @@ fn test_delete_already_deleted_batch() {
         batch_repo,
         weight_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let result = service.delete_batch("test_batch", owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Batch is already deleted"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::AlreadyDeleted(_)));
 }
 
 #[test]
@@ -850,11 +1171,11 @@ fn test_share_with_nonexistent_batch() {
     let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
     let access_control = MockAccessControl::new();
     let batch_repo = MockBatchRepo::new();
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
-    let result = service.share_with_user(owner, collaborator, "nonexistent_batch".to_string());
+    let result = service.share_with_user(owner, collaborator, "nonexistent_batch".to_string(), Role::Editor);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Batch not found"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::NotFound(_)));
 }
 
 #[test]
@@ -876,26 +1197,32 @@ fn test_extremely_large_weight() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateWeightRequest {
         owner_override: None,
         batch_id: "test_batch".to_string(),
         item_id: "test_animal".to_string(),
-        weight: f64::MAX,  // Extremely large weight
+        weight: f64::MAX,  // Extremely large weight,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
 
     let result = service.create_weight(request, owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Weight value out of reasonable range"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "weight"));
 }
 
 #[test]
@@ -917,14 +1244,17 @@ fn test_empty_animal_id() {
         created_at: now,
         updated_at: now,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = WeightService::new(
+    let service = WeightService::new(
         weight_repo,
         batch_repo,
         access_control,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     let request = CreateWeightRequest {
@@ -932,11 +1262,14 @@ fn test_empty_animal_id() {
         batch_id: "test_batch".to_string(),
         item_id: "".to_string(),  // Empty animal ID
         weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
     };
 
     let result = service.create_weight(request, owner);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Item ID cannot be empty"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "item_id"));
 }
 
 #[test]
@@ -953,16 +1286,18 @@ fn test_share_batch_with_deleted_batch() {
         description: None,
         created_at: 1000,
         updated_at: 1000,
-        deleted_at: Some(1000),  // Deleted batch
+        deleted_at: Some(1000),  // Deleted batch,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
     let access_control = MockAccessControl::new();
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
-    let result = service.share_with_user(owner, collaborator, "test_batch".to_string());
+    let result = service.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Editor);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Cannot share deleted batch"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::AlreadyDeleted(_)));
 }
 
 #[test]
@@ -970,9 +1305,7 @@ fn test_share_batch_as_non_owner() {
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
     let non_owner = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();  // Valid principal
     let collaborator = Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap();  // Valid principal
-    let time_provider = MockTimeProvider { time: 1000 };
     
-    let weight_repo = MockWeightRepo::new();
     let mut batch_repo = MockBatchRepo::new();
     let access_control = MockAccessControl::new();
     
@@ -985,15 +1318,17 @@ fn test_share_batch_as_non_owner() {
         created_at: 1000,
         updated_at: 1000,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
     // Try to share as non-owner
-    let result = service.share_with_user(non_owner, collaborator, "test_batch".to_string());
+    let result = service.share_with_user(non_owner, collaborator, "test_batch".to_string(), Role::Editor);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Only the owner can share this batch"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Unauthorized(_)));
 }
 
 #[test]
@@ -1012,19 +1347,21 @@ fn test_share_batch_multiple_times() {
         created_at: 1000,
         updated_at: 1000,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
     // Share first time
-    let result1 = service.share_with_user(owner, collaborator, "test_batch".to_string());
+    let result1 = service.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Editor);
     assert!(result1.is_ok());
 
     // Share same batch again
-    let result2 = service.share_with_user(owner, collaborator, "test_batch".to_string());
+    let result2 = service.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Editor);
     assert!(result2.is_err());
-    assert!(result2.unwrap_err().contains("Batch is already shared with this user"));
+    assert!(matches!(result2.unwrap_err(), WeightTrackerError::Conflict(_)));
 }
 
 #[test]
@@ -1044,18 +1381,20 @@ fn test_remove_sharing_as_non_owner() {
         created_at: 1000,
         updated_at: 1000,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
     // Set up initial sharing permissions in access_control
-    access_control.share_access(owner, collaborator, "test_batch".to_string()).unwrap();
+    access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Editor).unwrap();
 
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
     // Try to remove sharing as non-owner
     let result = service.remove_sharing(non_owner, collaborator);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Only the owner can remove sharing permissions"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Unauthorized(_)));
 }
 
 #[test]
@@ -1063,12 +1402,12 @@ fn test_share_with_invalid_principal() {
     let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
     let access_control = MockAccessControl::new();
     let batch_repo = MockBatchRepo::new();
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
     // Try to share with an invalid principal
-    let result = service.share_with_user(owner, Principal::anonymous(), "test_batch".to_string());
+    let result = service.share_with_user(owner, Principal::anonymous(), "test_batch".to_string(), Role::Editor);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Cannot share with anonymous principal"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "collaborator"));
 }
 
 #[test]
@@ -1077,11 +1416,11 @@ fn test_share_batch_with_empty_batch_id() {
     let collaborator = Principal::from_text("aaaaa-aa").unwrap();
     let access_control = MockAccessControl::new();
     let batch_repo = MockBatchRepo::new();
-    let mut service = AccessControlService::new(access_control, batch_repo);
+    let mut service = AccessControlService::new(access_control, batch_repo, MockAuditLog::new());
 
-    let result = service.share_with_user(owner, collaborator, "".to_string());
+    let result = service.share_with_user(owner, collaborator, "".to_string(), Role::Editor);
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Batch ID cannot be empty"));
+    assert!(matches!(result.unwrap_err(), WeightTrackerError::Validation { field, .. } if field == "batch_id"));
 }
 
 #[test]
@@ -1103,30 +1442,1559 @@ fn test_access_after_sharing_removed() {
         created_at: 1000,
         updated_at: 1000,
         deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
     };
     batch_repo.create(batch).unwrap();
 
     // Clone access_control before using it
     let access_control_clone = access_control.clone();
-    let mut service = BatchService::new(
+    let service = BatchService::new(
         batch_repo,
         weight_repo,
         access_control_clone,
-        time_provider
+        time_provider,
+        MockAuditLog::new()
     );
 
     // Set up sharing
-    access_control.share_access(owner, collaborator, "test_batch".to_string()).unwrap();
+    access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Editor).unwrap();
 
     // Verify access works
-    let result1 = service.get_batch("test_batch", collaborator);
+    let result1 = service.get_batch("test_batch", WeightUnit::Kilogram, &[], collaborator);
     assert!(result1.is_ok());
 
     // Remove sharing
     access_control.remove_access(owner, collaborator).unwrap();
 
     // Verify access is revoked
-    let result2 = service.get_batch("test_batch", collaborator);
+    let result2 = service.get_batch("test_batch", WeightUnit::Kilogram, &[], collaborator);
     assert!(result2.is_err());
-    assert!(result2.unwrap_err().contains("Unauthorized access to batch"));
-}
\ No newline at end of file
+    assert!(matches!(result2.unwrap_err(), WeightTrackerError::Unauthorized(_)));
+}
+#[test]
+fn test_create_weight_returns_key() {
+    let now = 1000;
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(Batch {
+        id: "test_batch".to_string(),
+        name: "Test Batch".to_string(),
+        owner,
+        description: None,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
+    }).unwrap();
+
+    let service = WeightService::new(
+        MockWeightRepo::new(),
+        batch_repo,
+        MockAccessControl::new(),
+        MockTimeProvider { time: now },
+        MockAuditLog::new()
+    );
+
+    let key = service.create_weight(CreateWeightRequest {
+        owner_override: None,
+        batch_id: "test_batch".to_string(),
+        item_id: "test_animal".to_string(),
+        weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
+    }, owner).unwrap().key;
+
+    assert_eq!(key, WeightKey {
+        owner,
+        item_id: "test_animal".to_string(),
+        created_at: now,
+    });
+}
+
+#[test]
+fn test_remove_sharing_reports_revoked_batches() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let mut batch_repo = MockBatchRepo::new();
+    for id in ["batch_a", "batch_b", "batch_c"] {
+        batch_repo.create(Batch {
+            id: id.to_string(),
+            name: id.to_string(),
+            owner,
+            description: None,
+            created_at: 1000,
+            updated_at: 1000,
+            deleted_at: None,
+            unit: WeightUnit::Kilogram,
+            validation: ValidationRules::default(),
+        }).unwrap();
+    }
+
+    let mut service = AccessControlService::new(MockAccessControl::new(), batch_repo, MockAuditLog::new());
+    service.share_with_user(owner, collaborator, "batch_a".to_string(), Role::Editor).unwrap();
+    service.share_with_user(owner, collaborator, "batch_b".to_string(), Role::Editor).unwrap();
+
+    assert_eq!(service.remove_sharing(owner, collaborator), Ok(2));
+    assert!(service.get_collaborators(owner).is_empty());
+}
+
+fn paged_weight_service(count: u64) -> (WeightService<MockWeightRepo, MockBatchRepo, MockAccessControl, MockTimeProvider, MockAuditLog>, Principal) {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let weight_repo = MockWeightRepo::new();
+
+    for i in 0..count {
+        let created_at = 1000 + i;
+        let weight = Weight {
+            owner,
+            batch_id: if i % 2 == 0 { "even".to_string() } else { "odd".to_string() },
+            item_id: format!("animal_{}", i),
+            // Every third weight repeats a value so ties have to be broken by key
+            weight: ((i % 3) * 10) as f64 + 50.0,
+            created_at,
+            updated_at: created_at,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        };
+        weight_repo.create(WeightKey { owner, item_id: weight.item_id.clone(), created_at }, weight).unwrap();
+    }
+
+    let service = WeightService::new(
+        weight_repo,
+        MockBatchRepo::new(),
+        MockAccessControl::new(),
+        MockTimeProvider { time: 1000 },
+        MockAuditLog::new()
+    );
+    (service, owner)
+}
+
+#[test]
+fn test_weights_page_covers_every_sort_order() {
+    let (service, owner) = paged_weight_service(25);
+
+    for sort in [
+        WeightSortOrder::CreatedAtAsc,
+        WeightSortOrder::CreatedAtDesc,
+        WeightSortOrder::WeightAsc,
+        WeightSortOrder::WeightDesc,
+    ] {
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = service.get_weights_page(WeightPageRequest {
+                sort: Some(sort),
+                page_size: Some(7),
+                cursor,
+                ..Default::default()
+            }, owner).unwrap();
+            assert!(page.weights.len() <= 7);
+            seen.extend(page.weights);
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(seen.len(), 25, "{:?} returned duplicates or skipped weights", sort);
+        let is_sorted = seen.windows(2).all(|pair| match sort {
+            WeightSortOrder::CreatedAtAsc => pair[0].created_at < pair[1].created_at,
+            WeightSortOrder::CreatedAtDesc => pair[0].created_at > pair[1].created_at,
+            WeightSortOrder::WeightAsc => pair[0].weight <= pair[1].weight,
+            WeightSortOrder::WeightDesc => pair[0].weight >= pair[1].weight,
+        });
+        assert!(is_sorted, "{:?} page order is wrong", sort);
+    }
+}
+
+#[test]
+fn test_weights_page_filters() {
+    let (service, owner) = paged_weight_service(25);
+
+    let page = service.get_weights_page(WeightPageRequest {
+        filter: WeightFilter {
+            batch_id: Some("even".to_string()),
+            created_from: Some(1004),
+            created_to: Some(1012),
+            min_weight: Some(60.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    }, owner).unwrap();
+
+    let created: Vec<u64> = page.weights.iter().map(|w| w.created_at).collect();
+    assert_eq!(created, vec![1004, 1008, 1010]);
+    assert!(page.next_cursor.is_none());
+
+    let result = service.get_weights_page(WeightPageRequest {
+        filter: WeightFilter { min_weight: Some(80.0), max_weight: Some(50.0), ..Default::default() },
+        ..Default::default()
+    }, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "min_weight"));
+}
+
+#[test]
+fn test_weights_page_rejects_bad_cursor_and_size() {
+    let (service, owner) = paged_weight_service(5);
+
+    let result = service.get_weights_page(WeightPageRequest {
+        cursor: Some("not a cursor".to_string()),
+        ..Default::default()
+    }, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "cursor"));
+
+    let first = service.get_weights_page(WeightPageRequest {
+        page_size: Some(2),
+        ..Default::default()
+    }, owner).unwrap();
+    let result = service.get_weights_page(WeightPageRequest {
+        sort: Some(WeightSortOrder::WeightDesc),
+        cursor: first.next_cursor,
+        ..Default::default()
+    }, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "cursor"));
+
+    for page_size in [0, crate::pagination::MAX_PAGE_SIZE + 1] {
+        let result = service.get_weights_page(WeightPageRequest {
+            page_size: Some(page_size),
+            ..Default::default()
+        }, owner);
+        assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "page_size"));
+    }
+}
+
+#[test]
+fn test_batches_created_in_same_round_get_distinct_ids() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let mut service = BatchService::new(
+        MockBatchRepo::new(),
+        MockWeightRepo::new(),
+        MockAccessControl::new(),
+        MockTimeProvider { time: 1000 },
+        MockAuditLog::new()
+    );
+
+    let first = service.create_batch(CreateBatchRequest { name: "First".to_string(), description: None, unit: None, validation: None }, owner).unwrap();
+    let second = service.create_batch(CreateBatchRequest { name: "Second".to_string(), description: None, unit: None, validation: None }, owner).unwrap();
+
+    assert_ne!(first, second);
+    assert_eq!(service.get_batch(&first, WeightUnit::Kilogram, &[], owner).unwrap().batch.name, "First");
+    assert_eq!(service.get_batch(&second, WeightUnit::Kilogram, &[], owner).unwrap().batch.name, "Second");
+}
+
+#[test]
+fn test_unshare_single_batch() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let other_collaborator = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let mut batch_repo = MockBatchRepo::new();
+    for id in ["batch_a", "batch_b"] {
+        batch_repo.create(Batch {
+            id: id.to_string(),
+            name: id.to_string(),
+            owner,
+            description: None,
+            created_at: 1000,
+            updated_at: 1000,
+            deleted_at: None,
+            unit: WeightUnit::Kilogram,
+            validation: ValidationRules::default(),
+        }).unwrap();
+    }
+
+    let access_control = MockAccessControl::new();
+    let mut service = AccessControlService::new(access_control.clone(), batch_repo, MockAuditLog::new());
+    service.share_with_user(owner, collaborator, "batch_a".to_string(), Role::Editor).unwrap();
+    service.share_with_user(owner, collaborator, "batch_b".to_string(), Role::Editor).unwrap();
+    service.share_with_user(owner, other_collaborator, "batch_a".to_string(), Role::Viewer).unwrap();
+
+    let mut batch_a = service.list_batch_collaborators(owner, "batch_a").unwrap();
+    batch_a.sort_by_key(|c| c.collaborator);
+    let mut expected = vec![
+        BatchCollaborator { collaborator, role: Role::Editor },
+        BatchCollaborator { collaborator: other_collaborator, role: Role::Viewer },
+    ];
+    expected.sort_by_key(|c| c.collaborator);
+    assert_eq!(batch_a, expected);
+
+    service.unshare_batch(owner, collaborator, "batch_a").unwrap();
+    assert!(!access_control.has_access(owner, collaborator, Some("batch_a")));
+    assert!(access_control.has_access(owner, collaborator, Some("batch_b")));
+    assert_eq!(
+        service.list_batch_collaborators(owner, "batch_a").unwrap(),
+        vec![BatchCollaborator { collaborator: other_collaborator, role: Role::Viewer }]
+    );
+
+    // Only the owner may unshare or list, and only existing shares can be revoked
+    let result = service.unshare_batch(collaborator, other_collaborator, "batch_a");
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    let result = service.list_batch_collaborators(collaborator, "batch_b");
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    let result = service.unshare_batch(owner, collaborator, "batch_a");
+    assert!(matches!(result, Err(WeightTrackerError::NotFound(_))));
+    let result = service.unshare_batch(owner, collaborator, "missing");
+    assert!(matches!(result, Err(WeightTrackerError::NotFound(_))));
+}
+
+#[test]
+fn test_roles_limit_what_collaborators_can_do() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let viewer = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let recorder = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let editor = Principal::from_text("rkp4c-7iaaa-aaaaa-aaaca-cai").unwrap();
+    let manager = Principal::from_text("rno2w-sqaaa-aaaaa-aaacq-cai").unwrap();
+
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(Batch {
+        id: "test_batch".to_string(),
+        name: "Test Batch".to_string(),
+        owner,
+        description: None,
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
+    }).unwrap();
+    let mut access_control = MockAccessControl::new();
+    for (collaborator, role) in [(viewer, Role::Viewer), (recorder, Role::Recorder), (editor, Role::Editor), (manager, Role::Manager)] {
+        access_control.share_access(owner, collaborator, "test_batch".to_string(), role).unwrap();
+    }
+
+    let weight_service = WeightService::new(
+        MockWeightRepo::new(),
+        batch_repo.clone(),
+        access_control.clone(),
+        MockTimeProvider { time: 1000 },
+        MockAuditLog::new()
+    );
+    let mut batch_service = BatchService::new(
+        batch_repo,
+        MockWeightRepo::new(),
+        access_control,
+        MockTimeProvider { time: 1000 },
+        MockAuditLog::new()
+    );
+    let record = |caller| weight_service.create_weight(CreateWeightRequest {
+        owner_override: None,
+        batch_id: "test_batch".to_string(),
+        item_id: "test_animal".to_string(),
+        weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
+    }, caller).map(|response| response.key);
+
+    // Only recorders and up can add weights
+    assert!(matches!(record(viewer), Err(WeightTrackerError::Unauthorized(_))));
+    let key = record(recorder).unwrap();
+
+    // Recorders cannot read back or change what they recorded
+    assert!(weight_service.get_weights(None, false, WeightUnit::Kilogram, recorder).is_empty());
+    assert_eq!(weight_service.get_weights(None, false, WeightUnit::Kilogram, viewer).len(), 1);
+    let result = weight_service.update_weight(key.item_id.clone(), key.created_at, 110.0, WeightUnit::Kilogram, None, recorder);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    let result = weight_service.update_weight(key.item_id.clone(), key.created_at, 110.0, WeightUnit::Kilogram, None, viewer);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    weight_service.update_weight(key.item_id.clone(), key.created_at, 110.0, WeightUnit::Kilogram, None, editor).unwrap();
+
+    // Every role sees the batch, but only managers may rename it
+    assert!(batch_service.get_batch("test_batch", WeightUnit::Kilogram, &[], recorder).is_ok());
+    let rename = UpdateBatchRequest { name: "Renamed".to_string(), description: None, unit: None, validation: None };
+    let result = batch_service.update_batch("test_batch", rename.clone(), editor);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    batch_service.update_batch("test_batch", rename, manager).unwrap();
+    let result = batch_service.delete_batch("test_batch", manager);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+
+    weight_service.delete_weight(key.item_id, key.created_at, manager).unwrap();
+}
+
+#[test]
+fn test_sharing_again_changes_role() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(Batch {
+        id: "test_batch".to_string(),
+        name: "Test Batch".to_string(),
+        owner,
+        description: None,
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
+    }).unwrap();
+    let access_control = MockAccessControl::new();
+    let mut service = AccessControlService::new(access_control.clone(), batch_repo, MockAuditLog::new());
+
+    service.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Recorder).unwrap();
+    service.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Viewer).unwrap();
+    assert_eq!(access_control.get_role(owner, collaborator, "test_batch"), Some(Role::Viewer));
+
+    let result = service.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Viewer);
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+}
+
+fn batch_with_weights(owner: Principal, deleted_at: Option<u64>) -> (MockBatchRepo, MockWeightRepo) {
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(Batch {
+        id: "test_batch".to_string(),
+        name: "Test Batch".to_string(),
+        owner,
+        description: None,
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
+    }).unwrap();
+
+    let weight_repo = MockWeightRepo::new();
+    for created_at in [1001, 1002, 1003] {
+        weight_repo.create(WeightKey { owner, item_id: "test_animal".to_string(), created_at }, Weight {
+            owner,
+            batch_id: "test_batch".to_string(),
+            item_id: "test_animal".to_string(),
+            weight: 100.0,
+            created_at,
+            updated_at: created_at,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        }).unwrap();
+    }
+    (batch_repo, weight_repo)
+}
+
+#[test]
+fn test_restore_batch_restores_only_cascaded_weights() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    weight_repo.delete(WeightKey { owner, item_id: "test_animal".to_string(), created_at: 1001 }).unwrap();
+
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Manager).unwrap();
+    let mut service = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    let result = service.restore_batch("test_batch", owner);
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+
+    service.delete_batch("test_batch", owner).unwrap();
+    let result = service.restore_batch("test_batch", collaborator);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+
+    assert_eq!(service.restore_batch("test_batch", owner), Ok(2));
+    let restored = service.get_batch("test_batch", WeightUnit::Kilogram, &[], owner).unwrap();
+    assert!(restored.batch.deleted_at.is_none());
+    assert_eq!(restored.stats.count, 2);
+}
+
+#[test]
+fn test_restore_keeps_one_weight_per_item() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let key = |created_at: u64| WeightKey { owner, item_id: "test_animal".to_string(), created_at };
+    let one_per_item = ValidationRules { allow_duplicate_item_ids: false, ..ValidationRules::default() };
+    let rejected = |error: WeightTrackerError| matches!(error, WeightTrackerError::Validation { field, .. } if field == "item_id");
+
+    let (mut batch_repo, weight_repo) = batch_with_weights(owner, None);
+    batch_repo.update("test_batch", "Test Batch".to_string(), None, WeightUnit::Kilogram, one_per_item.clone()).unwrap();
+    weight_repo.delete(key(1001)).unwrap();
+    weight_repo.delete(key(1002)).unwrap();
+    let service = WeightService::new(weight_repo, batch_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    assert!(rejected(service.restore_weight("test_animal".to_string(), 1001, owner).unwrap_err()));
+    service.delete_weight("test_animal".to_string(), 1003, owner).unwrap();
+    service.restore_weight("test_animal".to_string(), 1001, owner).unwrap();
+
+    // Weights recorded before the rule was set cannot all come back together
+    let (mut batch_repo, weight_repo) = batch_with_weights(owner, Some(1500));
+    batch_repo.update("test_batch", "Test Batch".to_string(), None, WeightUnit::Kilogram, one_per_item).unwrap();
+    for created_at in [1001, 1002, 1003] {
+        weight_repo.delete_with_batch(key(created_at)).unwrap();
+    }
+    let mut service = BatchService::new(batch_repo, weight_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    assert!(rejected(service.restore_batch("test_batch", owner).unwrap_err()));
+    assert!(service.get_batch("test_batch", WeightUnit::Kilogram, &[], owner).unwrap().batch.deleted_at.is_some());
+}
+
+#[test]
+fn test_restore_weight() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Editor).unwrap();
+
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let service = WeightService::new(weight_repo, batch_repo, access_control.clone(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    let result = service.restore_weight("test_animal".to_string(), 1001, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+
+    service.delete_weight("test_animal".to_string(), 1001, collaborator).unwrap();
+    let result = service.restore_weight("test_animal".to_string(), 1001, collaborator);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    service.restore_weight("test_animal".to_string(), 1001, owner).unwrap();
+    assert_eq!(service.get_weights(None, false, WeightUnit::Kilogram, owner).len(), 3);
+
+    // Weights of a deleted batch come back through restore_batch only
+    let (batch_repo, weight_repo) = batch_with_weights(owner, Some(1500));
+    weight_repo.delete_with_batch(WeightKey { owner, item_id: "test_animal".to_string(), created_at: 1001 }).unwrap();
+    let service = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let result = service.restore_weight("test_animal".to_string(), 1001, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+
+    // Deleting it again would cut it loose from the batch
+    let result = service.delete_weight("test_animal".to_string(), 1001, owner);
+    assert!(matches!(result, Err(WeightTrackerError::AlreadyDeleted(_))));
+    let weights = service.get_weights(None, true, WeightUnit::Kilogram, owner);
+    assert!(weights.iter().find(|w| w.created_at == 1001).unwrap().deleted_with_batch);
+}
+
+#[test]
+fn test_deleted_batch_takes_no_new_weights() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, Some(1500));
+    let service = WeightService::new(weight_repo, batch_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let request = CreateWeightRequest {
+        owner_override: None,
+        batch_id: "test_batch".to_string(),
+        item_id: "cow_1".to_string(),
+        weight: 100.0,
+        unit: None,
+        notes: None,
+        client_request_id: None,
+    };
+
+    let result = service.create_weight(request.clone(), owner);
+    assert!(matches!(result, Err(WeightTrackerError::AlreadyDeleted(_))));
+    let response = service.create_weights(vec![request], false, owner).unwrap();
+    assert!(matches!(response.results[0], CreateWeightOutcome::Failed(WeightTrackerError::AlreadyDeleted(_))));
+    let result = service.import_weights_csv(CsvImportRequest {
+        batch_id: "test_batch".to_string(),
+        csv: b"item,kg\ncow_1,100\n".to_vec(),
+        columns: CsvColumns { item_id: "item".to_string(), weight: "kg".to_string(), timestamp: None, notes: None },
+        unit: None,
+        all_or_nothing: false,
+    }, owner);
+    assert!(matches!(result, Err(WeightTrackerError::AlreadyDeleted(_))));
+    assert_eq!(service.get_weights(None, false, WeightUnit::Kilogram, owner).len(), 3);
+}
+
+#[test]
+fn test_purge_removes_records_past_retention() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let mut batch_repo = MockBatchRepo::new();
+    let weight_repo = MockWeightRepo::new();
+    for (batch_id, deleted_at) in [("old_batch", Some(100)), ("recent_batch", Some(1900)), ("live_batch", None)] {
+        batch_repo.create(Batch {
+            id: batch_id.to_string(),
+            name: batch_id.to_string(),
+            owner,
+            description: None,
+            created_at: 50,
+            updated_at: 50,
+            deleted_at,
+            unit: WeightUnit::Kilogram,
+            validation: ValidationRules::default(),
+        }).unwrap();
+    }
+    for (batch_id, item_id, deleted_at) in [
+        ("old_batch", "cow_1", Some(100)),
+        ("live_batch", "cow_2", Some(100)),
+        ("live_batch", "cow_3", Some(1900)),
+        ("live_batch", "cow_4", None),
+    ] {
+        weight_repo.create(WeightKey { owner, item_id: item_id.to_string(), created_at: 50 }, Weight {
+            owner,
+            batch_id: batch_id.to_string(),
+            item_id: item_id.to_string(),
+            weight: 100.0,
+            created_at: 50,
+            updated_at: 50,
+            deleted_at,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        }).unwrap();
+    }
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, collaborator, "old_batch".to_string(), Role::Viewer).unwrap();
+    access_control.share_access(owner, collaborator, "live_batch".to_string(), Role::Viewer).unwrap();
+
+    let audit_log = MockAuditLog::new();
+    let mut service = BatchService::new(batch_repo, weight_repo, access_control.clone(), MockTimeProvider { time: 2000 }, audit_log.clone());
+    let report = service.purge_deleted(500, owner);
+
+    assert_eq!(report, PurgeReport {
+        ran_at: 2000,
+        cutoff: 1500,
+        batch_ids: vec!["old_batch".to_string()],
+        weights_purged: 2,
+    });
+    assert!(matches!(service.get_batch("old_batch", WeightUnit::Kilogram, &[], owner), Err(WeightTrackerError::NotFound(_))));
+    assert!(service.get_batch("recent_batch", WeightUnit::Kilogram, &[], owner).is_ok());
+    assert!(access_control.get_role(owner, collaborator, "old_batch").is_none());
+    assert!(access_control.get_role(owner, collaborator, "live_batch").is_some());
+    assert_eq!(audit_log.actions(), vec![AuditAction::Purge, AuditAction::Unshare, AuditAction::Purge, AuditAction::Purge]);
+
+    // Nothing is left to purge on the next run
+    assert_eq!(service.purge_deleted(500, owner).weights_purged, 0);
+}
+
+#[test]
+fn test_weight_changes_are_audited_with_before_and_after() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let editor = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, editor, "test_batch".to_string(), Role::Editor).unwrap();
+    let audit_log = MockAuditLog::new();
+    let service = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: 2000 }, audit_log.clone());
+
+    service.update_weight("test_animal".to_string(), 1001, 120.0, WeightUnit::Kilogram, None, editor).unwrap();
+    service.delete_weight("test_animal".to_string(), 1001, owner).unwrap();
+
+    let entries = audit_log.entries.borrow().clone();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].event, AuditEvent {
+        actor: editor,
+        action: AuditAction::Update,
+        owner,
+        batch_id: "test_batch".to_string(),
+        target: AuditTarget::Weight(WeightKey { owner, item_id: "test_animal".to_string(), created_at: 1001 }),
+        before: Some(AuditValue::Weight { weight: 100.0, deleted_at: None }),
+        after: Some(AuditValue::Weight { weight: 120.0, deleted_at: None }),
+    });
+    assert_eq!(entries[1].event.actor, owner);
+    assert_eq!(entries[1].event.after, Some(AuditValue::Weight { weight: 120.0, deleted_at: Some(2000) }));
+
+    // Rejected mutations leave no trace
+    let stranger = Principal::from_text("2vxsx-fae").unwrap();
+    assert!(service.update_weight("test_animal".to_string(), 1002, 1.0, WeightUnit::Kilogram, None, stranger).is_err());
+    assert_eq!(audit_log.entries.borrow().len(), 2);
+}
+
+#[test]
+fn test_batch_and_sharing_changes_are_audited() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let access_control = MockAccessControl::new();
+    let audit_log = MockAuditLog::new();
+
+    let mut sharing = AccessControlService::new(access_control.clone(), batch_repo.clone(), audit_log.clone());
+    sharing.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Viewer).unwrap();
+    sharing.share_with_user(owner, collaborator, "test_batch".to_string(), Role::Editor).unwrap();
+    sharing.unshare_batch(owner, collaborator, "test_batch").unwrap();
+
+    let mut batches = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 2000 }, audit_log.clone());
+    batches.delete_batch("test_batch", owner).unwrap();
+    batches.restore_batch("test_batch", owner).unwrap();
+
+    assert_eq!(audit_log.actions(), vec![
+        AuditAction::Share,
+        AuditAction::Share,
+        AuditAction::Unshare,
+        AuditAction::Delete,
+        AuditAction::Delete,
+        AuditAction::Delete,
+        AuditAction::Delete,
+        AuditAction::Restore,
+        AuditAction::Restore,
+        AuditAction::Restore,
+        AuditAction::Restore,
+    ]);
+    let entries = audit_log.entries.borrow().clone();
+    assert_eq!(entries[1].event.before, Some(AuditValue::Grant(Role::Viewer)));
+    assert_eq!(entries[1].event.after, Some(AuditValue::Grant(Role::Editor)));
+    assert_eq!(entries[2].event.target, AuditTarget::Grant(collaborator));
+    assert_eq!(entries[2].event.after, None);
+    assert_eq!(entries[3].event.target, AuditTarget::Batch);
+}
+
+#[test]
+fn test_audit_log_is_paged_and_limited_to_readers() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let recorder = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, recorder, "test_batch".to_string(), Role::Recorder).unwrap();
+    let audit_log = MockAuditLog::new();
+    let weights = WeightService::new(weight_repo.clone(), batch_repo.clone(), access_control.clone(), MockTimeProvider { time: 2000 }, audit_log.clone());
+    for created_at in [1001, 1002, 1003] {
+        weights.update_weight("test_animal".to_string(), created_at, 110.0, WeightUnit::Kilogram, None, owner).unwrap();
+    }
+
+    let request = AuditPageRequest { page_size: Some(2), cursor: None };
+    let first = weights.get_item_audit_log("test_animal", request, owner).unwrap();
+    assert_eq!(first.entries.iter().map(|e| e.id).collect::<Vec<_>>(), vec![0, 1]);
+    let second = weights.get_item_audit_log("test_animal", AuditPageRequest { page_size: Some(2), cursor: first.next_cursor }, owner).unwrap();
+    assert_eq!(second.entries.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2]);
+    assert!(second.next_cursor.is_none());
+
+    // A recorder cannot read weights, so it cannot read their history either
+    assert!(weights.get_item_audit_log("test_animal", AuditPageRequest::default(), recorder).unwrap().entries.is_empty());
+    let batches = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 2000 }, audit_log);
+    let result = batches.get_batch_audit_log("test_batch", AuditPageRequest::default(), recorder);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    assert_eq!(batches.get_batch_audit_log("test_batch", AuditPageRequest::default(), owner).unwrap().entries.len(), 3);
+}
+
+#[test]
+fn test_weight_history_keeps_corrected_values() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let editor = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let recorder = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, editor, "test_batch".to_string(), Role::Editor).unwrap();
+    access_control.share_access(owner, recorder, "test_batch".to_string(), Role::Recorder).unwrap();
+    let service = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    service.update_weight("test_animal".to_string(), 1001, 110.0, WeightUnit::Kilogram, Some("  Scale was off  ".to_string()), editor).unwrap();
+    service.update_weight("test_animal".to_string(), 1001, 105.0, WeightUnit::Kilogram, Some(" ".to_string()), owner).unwrap();
+
+    let history = service.get_weight_history("test_animal".to_string(), 1001, WeightUnit::Kilogram, editor).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].weight, 100.0);
+    assert_eq!(history[0].valid_from, 1001);
+    assert_eq!(history[0].replaced_by, editor);
+    assert_eq!(history[0].reason, Some("Scale was off".to_string()));
+    assert_eq!(history[1].weight, 110.0);
+    assert_eq!(history[1].replaced_by, owner);
+    assert_eq!(history[1].reason, None);
+    assert!(service.get_weight_history("test_animal".to_string(), 1002, WeightUnit::Kilogram, owner).unwrap().is_empty());
+
+    let result = service.get_weight_history("test_animal".to_string(), 1001, WeightUnit::Kilogram, recorder);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+
+    let result = service.update_weight("test_animal".to_string(), 1001, 99.0, WeightUnit::Kilogram, Some("x".repeat(501)), owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "reason"));
+}
+
+#[test]
+fn test_weights_are_stored_in_kilograms_and_converted_on_read() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let mut batches = BatchService::new(MockBatchRepo::new(), MockWeightRepo::new(), MockAccessControl::new(), MockTimeProvider { time: 1000 }, MockAuditLog::new());
+    let batch_id = batches.create_batch(CreateBatchRequest {
+        name: "Lambs".to_string(),
+        description: None,
+        unit: Some(WeightUnit::Pound),
+        validation: None,
+    }, owner).unwrap();
+    let batch = batches.get_batch(&batch_id, WeightUnit::Kilogram, &[], owner).unwrap().batch;
+    assert_eq!(batch.unit, WeightUnit::Pound);
+
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(batch).unwrap();
+    let service = WeightService::new(MockWeightRepo::new(), batch_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let request = |weight: f64, unit: Option<WeightUnit>| CreateWeightRequest {
+        owner_override: None,
+        batch_id: batch_id.clone(),
+        item_id: "lamb_1".to_string(),
+        weight,
+        unit,
+        notes: None,
+        client_request_id: None,
+    };
+    // The batch's unit applies unless the weight gives its own
+    let key = service.create_weight(request(100.0, None), owner).unwrap().key;
+    let stored = service.get_weights(None, false, WeightUnit::Kilogram, owner).remove(0);
+    assert!((stored.weight - 45.359237).abs() < 1e-9);
+    assert_eq!(stored.entered_unit, WeightUnit::Pound);
+
+    let in_pounds = service.get_weights(None, false, WeightUnit::Pound, owner);
+    assert!((in_pounds[0].weight - 100.0).abs() < 1e-9);
+
+    // Corrections say their unit, kilograms like the reads by default
+    service.update_weight("lamb_1".to_string(), key.created_at, 110.0, WeightUnit::Pound, None, owner).unwrap();
+    let in_grams = service.get_weights(None, false, WeightUnit::Gram, owner);
+    assert!((in_grams[0].weight - 49_895.160_7).abs() < 1e-6);
+
+    // The limit applies to the canonical value, whatever the unit
+    let result = service.create_weight(request(11.0, Some(WeightUnit::Tonne)), owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "weight"));
+    let result = service.create_weight(request(f64::NAN, None), owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "weight"));
+
+    let page = service.get_weights_page(WeightPageRequest {
+        filter: WeightFilter { min_weight: Some(105.0), ..Default::default() },
+        unit: Some(WeightUnit::Pound),
+        ..Default::default()
+    }, owner).unwrap();
+    assert_eq!(page.weights.len(), 1);
+    assert!((page.weights[0].weight - 110.0).abs() < 1e-9);
+
+    service.update_weight("lamb_1".to_string(), key.created_at, 50.0, WeightUnit::Kilogram, None, owner).unwrap();
+    assert_eq!(service.get_weights(None, false, WeightUnit::Kilogram, owner)[0].weight, 50.0);
+    let history = service.get_weight_history("lamb_1".to_string(), key.created_at, WeightUnit::Pound, owner).unwrap();
+    assert!((history[0].weight - 100.0).abs() < 1e-9);
+    assert!((history[1].weight - 110.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_batch_stats_in_requested_unit() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let service = BatchService::new(batch_repo, weight_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    let stats = service.get_batch("test_batch", WeightUnit::Gram, &[], owner).unwrap().stats;
+    assert_eq!(stats.unit, WeightUnit::Gram);
+    assert_eq!(stats.average_weight, 100_000.0);
+    assert_eq!(stats.count, 3);
+    let stats = &service.get_owned_batches(WeightUnit::Tonne, &[], owner)[0].stats;
+    assert_eq!(stats.max_weight, 0.1);
+}
+
+#[test]
+fn test_item_timeline_covers_readable_batches() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let viewer = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    // The viewer keeps an animal of their own under the same ID
+    for (owner, batch_id, created_at, weight) in [(owner, "other_batch", 1000, 90.0), (owner, "other_batch", 1004, 110.0), (viewer, "viewer_batch", 1005, 30.0)] {
+        weight_repo.create(WeightKey { owner, item_id: "test_animal".to_string(), created_at }, Weight {
+            owner,
+            batch_id: batch_id.to_string(),
+            item_id: "test_animal".to_string(),
+            weight,
+            created_at,
+            updated_at: created_at,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        }).unwrap();
+    }
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, viewer, "test_batch".to_string(), Role::Viewer).unwrap();
+    let service = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    let timeline = service.get_item_timeline("test_animal", ItemTimelineRequest::default(), owner).unwrap();
+    let readings: Vec<_> = timeline.readings.iter().map(|r| (r.weight.created_at, r.delta)).collect();
+    assert_eq!(readings, vec![(1000, None), (1001, Some(10.0)), (1002, Some(0.0)), (1003, Some(0.0)), (1004, Some(10.0))]);
+
+    // The viewer only sees the shared batch, in grams, within the range asked for
+    let request = ItemTimelineRequest { owner: Some(owner), from: Some(1002), to: None, unit: Some(WeightUnit::Gram) };
+    let timeline = service.get_item_timeline("test_animal", request, viewer).unwrap();
+    let readings: Vec<_> = timeline.readings.iter().map(|r| (r.weight.created_at, r.weight.weight)).collect();
+    assert_eq!(readings, vec![(1002, 100_000.0), (1003, 100_000.0)]);
+    assert_eq!(timeline.average_daily_gain, Some(0.0));
+
+    // Without an owner the viewer gets their own animal, not a mix of both
+    let timeline = service.get_item_timeline("test_animal", ItemTimelineRequest::default(), viewer).unwrap();
+    let readings: Vec<_> = timeline.readings.iter().map(|r| (r.weight.owner, r.weight.weight)).collect();
+    assert_eq!(readings, vec![(viewer, 30.0)]);
+
+    let error = service.get_item_timeline("missing", ItemTimelineRequest::default(), owner).unwrap_err();
+    assert!(matches!(error, WeightTrackerError::NotFound(_)));
+    let request = ItemTimelineRequest { from: Some(2), to: Some(1), ..ItemTimelineRequest::default() };
+    assert!(matches!(service.get_item_timeline("test_animal", request, owner), Err(WeightTrackerError::Validation { .. })));
+}
+
+#[test]
+fn test_weight_series_per_interval() {
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let stranger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let recorder = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    // Day 0 has three weights of 100 kg; add two on day 1 and one on day 9
+    for (created_at, weight) in [(DAY + 5, 80.0), (DAY + 6, 120.0), (9 * DAY, 150.0)] {
+        weight_repo.create(WeightKey { owner, item_id: "test_animal".to_string(), created_at }, Weight {
+            owner,
+            batch_id: "test_batch".to_string(),
+            item_id: "test_animal".to_string(),
+            weight,
+            created_at,
+            updated_at: created_at,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        }).unwrap();
+    }
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, recorder, "test_batch".to_string(), Role::Recorder).unwrap();
+    let service = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 10 * DAY }, MockAuditLog::new());
+    let request = |batch_id: Option<&str>, interval, utc_offset_minutes| SeriesRequest {
+        batch_id: batch_id.map(str::to_string),
+        interval,
+        utc_offset_minutes,
+        from: None,
+        to: None,
+        unit: None,
+    };
+    let summary = |series: WeightSeries| series.points.iter()
+        .map(|p| (p.start, p.stats.count, p.stats.average_weight))
+        .collect::<Vec<_>>();
+
+    let daily = service.get_series(request(Some("test_batch"), SeriesInterval::Day, None), owner).unwrap();
+    assert_eq!(summary(daily), vec![(0, 3, 100.0), (DAY, 2, 100.0), (9 * DAY, 1, 150.0)]);
+    // 1970-01-01 was a Thursday, so day 9 starts the second week
+    let weekly = service.get_series(request(None, SeriesInterval::Week, None), owner).unwrap();
+    assert_eq!(summary(weekly), vec![(0, 5, 100.0), (4 * DAY, 1, 150.0)]);
+    // An hour behind UTC, day 1's readings are still on 1 January, which starts at 01:00 UTC
+    let shifted = service.get_series(request(None, SeriesInterval::Day, Some(-60)), owner).unwrap();
+    assert_eq!(summary(shifted)[1], (3_600_000_000_000, 2, 100.0));
+
+    // Recorders cannot read weights, so they get no series either
+    for caller in [stranger, recorder] {
+        assert!(service.get_series(request(None, SeriesInterval::Month, None), caller).unwrap().points.is_empty());
+        assert!(matches!(
+            service.get_series(request(Some("test_batch"), SeriesInterval::Day, None), caller),
+            Err(WeightTrackerError::Unauthorized(_))
+        ));
+    }
+    let error = service.get_series(request(None, SeriesInterval::Day, Some(15 * 60)), owner).unwrap_err();
+    assert!(matches!(error, WeightTrackerError::Validation { field, .. } if field == "utc_offset_minutes"));
+}
+
+#[test]
+fn test_weight_histogram() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let stranger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let recorder = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, recorder, "test_batch".to_string(), Role::Recorder).unwrap();
+    let service = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let request = |buckets, unit| HistogramRequest { buckets, unit };
+
+    let histogram = service.get_histogram("test_batch", request(HistogramBuckets::Width(50.0), None), owner).unwrap();
+    assert_eq!(histogram.buckets, vec![HistogramBucket { label: None, min_weight: 100.0, max_weight: 150.0, count: 3 }]);
+
+    // Bounds are in the requested unit
+    let grades = HistogramBuckets::Bands(vec![GradeBand { name: "A".to_string(), min_weight: 200.0, max_weight: 250.0 }]);
+    let histogram = service.get_histogram("test_batch", request(grades, Some(WeightUnit::Pound)), owner).unwrap();
+    assert_eq!((histogram.buckets[0].count, histogram.ungraded), (3, 0));
+
+    let invalid = [
+        HistogramBuckets::Width(0.0),
+        HistogramBuckets::Width(f64::NAN),
+        HistogramBuckets::Count(0),
+        HistogramBuckets::Bands(vec![]),
+        HistogramBuckets::Bands(vec![
+            GradeBand { name: "A".to_string(), min_weight: 90.0, max_weight: 110.0 },
+            GradeBand { name: "B".to_string(), min_weight: 100.0, max_weight: 120.0 },
+        ]),
+    ];
+    for buckets in invalid {
+        let error = service.get_histogram("test_batch", request(buckets, None), owner).unwrap_err();
+        assert!(matches!(error, WeightTrackerError::Validation { field, .. } if field == "buckets"));
+    }
+    for caller in [stranger, recorder] {
+        let error = service.get_histogram("test_batch", request(HistogramBuckets::Count(5), None), caller).unwrap_err();
+        assert!(matches!(error, WeightTrackerError::Unauthorized(_)));
+    }
+}
+
+#[test]
+fn test_requested_percentiles_are_checked() {
+    assert_eq!(percentiles(None).unwrap(), vec![10.0, 25.0, 75.0, 90.0]);
+    assert_eq!(percentiles(Some(vec![50.0, 99.5])).unwrap(), vec![50.0, 99.5]);
+    for invalid in [vec![-1.0], vec![100.5], vec![f64::NAN], vec![50.0; 21]] {
+        let error = percentiles(Some(invalid)).unwrap_err();
+        assert!(matches!(error, WeightTrackerError::Validation { field, .. } if field == "percentiles"));
+    }
+
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let service = BatchService::new(batch_repo, weight_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let stats = service.get_batch("test_batch", WeightUnit::Kilogram, &[50.0], owner).unwrap().stats;
+    assert_eq!(stats.percentiles, vec![PercentileWeight { percentile: 50.0, weight: stats.median_weight }]);
+}
+
+#[test]
+fn test_recorders_get_no_weight_distribution() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let viewer = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let recorder = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, viewer, "test_batch".to_string(), Role::Viewer).unwrap();
+    access_control.share_access(owner, recorder, "test_batch".to_string(), Role::Recorder).unwrap();
+    let service = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    let stats = service.get_batch("test_batch", WeightUnit::Kilogram, &[50.0], viewer).unwrap().stats;
+    assert_eq!((stats.median_weight, stats.uniformity, stats.percentiles.len()), (100.0, 100.0, 1));
+
+    // A recorder still sees the count and the range, but nothing finer
+    let stats = service.get_batch("test_batch", WeightUnit::Kilogram, &[50.0], recorder).unwrap().stats;
+    assert_eq!((stats.count, stats.min_weight, stats.max_weight, stats.average_weight), (3, 100.0, 100.0, 100.0));
+    assert_eq!((stats.median_weight, stats.uniformity), (0.0, 0.0));
+    assert!(stats.percentiles.is_empty());
+    let batches = service.get_batches(false, WeightUnit::Kilogram, &[10.0, 90.0], recorder);
+    assert!(batches[0].stats.percentiles.is_empty());
+}
+
+#[test]
+fn test_batch_validation_rules_apply_to_create_and_update() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let mut batches = BatchService::new(MockBatchRepo::new(), MockWeightRepo::new(), MockAccessControl::new(), MockTimeProvider { time: 1000 }, MockAuditLog::new());
+    let rules = ValidationRules {
+        min_weight: Some(20.0),
+        max_weight: Some(500.0),
+        item_id_pattern: Some("CHICK-###".to_string()),
+        allow_duplicate_item_ids: false,
+    };
+    let batch_id = batches.create_batch(CreateBatchRequest {
+        name: "Chicks".to_string(),
+        description: None,
+        unit: Some(WeightUnit::Gram),
+        validation: Some(rules.clone()),
+    }, owner).unwrap();
+    let batch = batches.get_batch(&batch_id, WeightUnit::Kilogram, &[], owner).unwrap().batch;
+    assert_eq!(batch.validation, rules);
+
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(batch).unwrap();
+    let weight_repo = MockWeightRepo::new();
+    weight_repo.create(WeightKey { owner, item_id: "CHICK-003".to_string(), created_at: 1500 }, Weight {
+        owner,
+        batch_id: batch_id.clone(),
+        item_id: "CHICK-003".to_string(),
+        weight: 0.04,
+        created_at: 1500,
+        updated_at: 1500,
+        deleted_at: Some(1600),
+        deleted_with_batch: false,
+        entered_unit: WeightUnit::Gram,
+        notes: None,
+    }).unwrap();
+    let service = WeightService::new(weight_repo, batch_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let request = |item_id: &str, weight: f64| CreateWeightRequest {
+        owner_override: None,
+        batch_id: batch_id.clone(),
+        item_id: item_id.to_string(),
+        weight,
+        unit: None,
+        notes: None,
+        client_request_id: None,
+    };
+    let rejected_field = |result: Result<CreateWeightResponse, WeightTrackerError>| match result {
+        Err(WeightTrackerError::Validation { field, .. }) => field,
+        other => panic!("expected a validation error, got {:?}", other),
+    };
+
+    let key = service.create_weight(request("CHICK-001", 45.0), owner).unwrap().key;
+    assert_eq!(rejected_field(service.create_weight(request("CHICK-1", 45.0), owner)), "item_id");
+    assert_eq!(rejected_field(service.create_weight(request("CHICK-002", 10.0), owner)), "weight");
+    assert_eq!(rejected_field(service.create_weight(request("CHICK-002", 600.0), owner)), "weight");
+    assert_eq!(rejected_field(service.create_weight(request("CHICK-001", 50.0), owner)), "item_id");
+
+    // Without access, the rules and the batch's items stay hidden
+    let stranger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    for item_id in ["CHICK-001", "CHICK-1"] {
+        let result = service.create_weight(request(item_id, 10.0), stranger);
+        assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    }
+
+    // Corrections obey the same bounds
+    let result = service.update_weight("CHICK-001".to_string(), key.created_at, 900.0, WeightUnit::Gram, None, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "weight"));
+    service.update_weight("CHICK-001".to_string(), key.created_at, 60.0, WeightUnit::Gram, None, owner).unwrap();
+
+    // A deleted weight does not block its item ID
+    service.create_weight(request("CHICK-003", 50.0), owner).unwrap();
+}
+
+#[test]
+fn test_update_batch_validation_rules() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    let mut service = BatchService::new(batch_repo, weight_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let update = |validation: Option<ValidationRules>| UpdateBatchRequest {
+        name: "Bulls".to_string(),
+        description: None,
+        unit: None,
+        validation,
+    };
+
+    let invalid = [
+        ValidationRules { min_weight: Some(500.0), max_weight: Some(400.0), ..ValidationRules::default() },
+        ValidationRules { max_weight: Some(20_000.0), ..ValidationRules::default() },
+        ValidationRules { min_weight: Some(f64::NAN), ..ValidationRules::default() },
+        ValidationRules { item_id_pattern: Some(String::new()), ..ValidationRules::default() },
+    ];
+    for rules in invalid {
+        let result = service.update_batch("test_batch", update(Some(rules)), owner);
+        assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "validation"));
+    }
+
+    let rules = ValidationRules { min_weight: Some(300.0), ..ValidationRules::default() };
+    service.update_batch("test_batch", update(Some(rules.clone())), owner).unwrap();
+    // Omitting the rules keeps them
+    service.update_batch("test_batch", update(None), owner).unwrap();
+    assert_eq!(service.get_batch("test_batch", WeightUnit::Kilogram, &[], owner).unwrap().batch.validation, rules);
+
+    // Limits are in the batch's unit, so changing the unit converts the kept ones
+    let to_tonnes = UpdateBatchRequest { unit: Some(WeightUnit::Tonne), ..update(None) };
+    service.update_batch("test_batch", to_tonnes.clone(), owner).unwrap();
+    let validation = service.get_batch("test_batch", WeightUnit::Kilogram, &[], owner).unwrap().batch.validation;
+    assert_eq!(validation.min_weight, Some(0.3));
+    let too_heavy = UpdateBatchRequest {
+        validation: Some(ValidationRules { max_weight: Some(20.0), ..ValidationRules::default() }),
+        ..to_tonnes
+    };
+    let result = service.update_batch("test_batch", too_heavy, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "validation"));
+}
+
+#[test]
+fn test_create_weights_reports_each_row() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (batch_repo, _) = batch_with_weights(owner, None);
+    let audit_log = MockAuditLog::new();
+    let service = WeightService::new(MockWeightRepo::new(), batch_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, audit_log.clone());
+    let request = |batch_id: &str, item_id: &str, weight: f64| CreateWeightRequest {
+        owner_override: None,
+        batch_id: batch_id.to_string(),
+        item_id: item_id.to_string(),
+        weight,
+        unit: None,
+        notes: None,
+        client_request_id: None,
+    };
+    let requests = vec![
+        request("test_batch", "cow_1", 500.0),
+        request("test_batch", "cow_2", -1.0),
+        request("missing_batch", "cow_3", 500.0),
+        request("test_batch", "cow_1", 510.0),
+        request("test_batch", "cow_4", 480.0),
+    ];
+
+    // All or nothing: the valid rows are skipped and nothing is written
+    let response = service.create_weights(requests.clone(), true, owner).unwrap();
+    assert_eq!(response.created, 0);
+    assert_eq!(response.results[0], CreateWeightOutcome::Skipped);
+    assert!(matches!(&response.results[1], CreateWeightOutcome::Failed(WeightTrackerError::Validation { field, .. }) if field == "weight"));
+    assert!(matches!(response.results[2], CreateWeightOutcome::Failed(WeightTrackerError::NotFound(_))));
+    assert!(matches!(response.results[3], CreateWeightOutcome::Failed(WeightTrackerError::Conflict(_))));
+    assert_eq!(response.results[4], CreateWeightOutcome::Skipped);
+    assert!(service.get_weights(None, false, WeightUnit::Kilogram, owner).is_empty());
+    assert!(audit_log.actions().is_empty());
+
+    // Otherwise the valid rows are written
+    let response = service.create_weights(requests, false, owner).unwrap();
+    assert_eq!(response.created, 2);
+    let cow_1 = WeightKey { owner, item_id: "cow_1".to_string(), created_at: 2000 };
+    assert_eq!(response.results[0], CreateWeightOutcome::Created(cow_1));
+    assert!(matches!(response.results[3], CreateWeightOutcome::Failed(WeightTrackerError::Conflict(_))));
+    let mut stored: Vec<_> = service.get_weights(None, false, WeightUnit::Kilogram, owner)
+        .into_iter()
+        .map(|w| (w.item_id, w.weight))
+        .collect();
+    stored.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(stored, vec![("cow_1".to_string(), 500.0), ("cow_4".to_string(), 480.0)]);
+    assert_eq!(audit_log.actions(), vec![AuditAction::Create, AuditAction::Create]);
+
+    let too_many = vec![request("test_batch", "cow_1", 500.0); 1001];
+    let result = service.create_weights(too_many, false, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "requests"));
+}
+
+#[test]
+fn test_import_weights_csv() {
+    const NOW: u64 = 1_700_000_000_000_005_000;
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let recorder = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let viewer = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+    let (mut batch_repo, weight_repo) = batch_with_weights(owner, None);
+    batch_repo.create(Batch {
+        id: "unique_batch".to_string(),
+        name: "Unique Batch".to_string(),
+        owner,
+        description: None,
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules { allow_duplicate_item_ids: false, ..ValidationRules::default() },
+    }).unwrap();
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, recorder, "test_batch".to_string(), Role::Recorder).unwrap();
+    access_control.share_access(owner, viewer, "test_batch".to_string(), Role::Viewer).unwrap();
+    let service = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: NOW }, MockAuditLog::new());
+    // Row 4 gives seconds, and row 6 lands on test_animal's existing weight at 1001
+    let csv = "\
+Animal,Kg,When,Remarks
+cow_1,512.5,1700000000000001000, limping 
+cow_2,heavy,1700000000000002000,
+cow_3,480,1700000000,
+cow_4,490,1700000000000009000,
+test_animal,101,1970-01-01T00:00:00.000001001Z,
+cow_1,515,1700000000000001000,
+cow_5,470,1700000000000003000,
+";
+    let request = |all_or_nothing: bool| CsvImportRequest {
+        batch_id: "test_batch".to_string(),
+        csv: csv.as_bytes().to_vec(),
+        columns: CsvColumns {
+            item_id: "Animal".to_string(),
+            weight: "Kg".to_string(),
+            timestamp: Some("When".to_string()),
+            notes: Some("Remarks".to_string()),
+        },
+        unit: None,
+        all_or_nothing,
+    };
+    let failed_field = |outcome: &CreateWeightOutcome| match outcome {
+        CreateWeightOutcome::Failed(WeightTrackerError::Validation { field, .. }) => field.clone(),
+        CreateWeightOutcome::Failed(WeightTrackerError::Conflict(_)) => "conflict".to_string(),
+        other => panic!("expected a failed row, got {:?}", other),
+    };
+
+    let report = service.import_weights_csv(request(true), recorder).unwrap();
+    assert_eq!(report.created, 0);
+    assert_eq!(report.rows.iter().map(|row| row.line).collect::<Vec<_>>(), vec![2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(report.rows[0].outcome, CreateWeightOutcome::Skipped);
+    assert_eq!(service.get_weights(None, false, WeightUnit::Kilogram, owner).len(), 3);
+
+    let report = service.import_weights_csv(request(false), recorder).unwrap();
+    assert_eq!(report.created, 2);
+    let failures: Vec<_> = report.rows[1..6].iter().map(|row| failed_field(&row.outcome)).collect();
+    assert_eq!(failures, vec!["weight", "timestamp", "timestamp", "conflict", "conflict"]);
+
+    // Imported weights keep the file's timestamps and notes
+    let imported = service.get_weights(None, false, WeightUnit::Kilogram, owner)
+        .into_iter()
+        .find(|w| w.item_id == "cow_1")
+        .unwrap();
+    assert_eq!(imported.created_at, 1_700_000_000_000_001_000);
+    assert_eq!(imported.weight, 512.5);
+    assert_eq!(imported.notes, Some("limping".to_string()));
+
+    let mut missing_column = request(false);
+    missing_column.columns.notes = Some("Comments".to_string());
+    let result = service.import_weights_csv(missing_column, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "columns"));
+
+    let result = service.import_weights_csv(request(false), viewer);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+
+    // A batch with one weight per item holds to it within a single file too
+    let mut unique = request(false);
+    unique.batch_id = "unique_batch".to_string();
+    unique.csv = b"Animal,Kg,When,Remarks\ncow,100,1700000000000001000,\ncow,101,1700000000000002000,\n".to_vec();
+    let report = service.import_weights_csv(unique, owner).unwrap();
+    assert_eq!(report.created, 1);
+    assert_eq!(failed_field(&report.rows[1].outcome), "item_id");
+    assert_eq!(service.get_weights(Some("unique_batch".to_string()), false, WeightUnit::Kilogram, owner).len(), 1);
+}
+
+fn export_all(service: &BatchService<MockBatchRepo, MockWeightRepo, MockAccessControl, MockTimeProvider, MockAuditLog>, request: ExportRequest, caller: Principal) -> (String, usize) {
+    let mut document = Vec::new();
+    let mut chunks = 0;
+    let mut cursor = None;
+    loop {
+        let chunk = service.export(ExportRequest { cursor, ..request.clone() }, caller).unwrap();
+        document.extend(chunk.data);
+        chunks += 1;
+        match chunk.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return (String::from_utf8(document).unwrap(), chunks),
+        }
+    }
+}
+
+#[test]
+fn test_export_is_chunked_and_covers_readable_batches() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let viewer = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (mut batch_repo, weight_repo) = batch_with_weights(owner, None);
+    for (id, name) in [("empty_batch", "Empty, for now"), ("private_batch", "Private")] {
+        batch_repo.create(Batch {
+            id: id.to_string(),
+            name: name.to_string(),
+            owner,
+            description: None,
+            created_at: 1000,
+            updated_at: 1000,
+            deleted_at: None,
+            unit: WeightUnit::Kilogram,
+            validation: ValidationRules::default(),
+        }).unwrap();
+    }
+    weight_repo.create(WeightKey { owner, item_id: "test_animal".to_string(), created_at: 1004 }, Weight {
+        owner,
+        batch_id: "test_batch".to_string(),
+        item_id: "test_animal".to_string(),
+        weight: 120.0,
+        created_at: 1004,
+        updated_at: 1004,
+        deleted_at: None,
+        deleted_with_batch: false,
+        entered_unit: WeightUnit::Kilogram,
+        notes: Some("said \"moo\", loudly".to_string()),
+    }).unwrap();
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, viewer, "test_batch".to_string(), Role::Viewer).unwrap();
+    access_control.share_access(owner, viewer, "empty_batch".to_string(), Role::Viewer).unwrap();
+    let service = BatchService::new(batch_repo, weight_repo, access_control, MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let request = |format: ExportFormat, chunk_size: u32| ExportRequest {
+        batch_id: None,
+        format,
+        include_deleted: false,
+        unit: Some(WeightUnit::Gram),
+        chunk_size: Some(chunk_size),
+        cursor: None,
+    };
+
+    for format in [ExportFormat::Csv, ExportFormat::Json] {
+        // Two batches and four weights
+        let (whole, chunks) = export_all(&service, request(format, 6), viewer);
+        assert_eq!(chunks, 1);
+        for chunk_size in 1..6 {
+            let (chunked, chunks) = export_all(&service, request(format, chunk_size), viewer);
+            assert_eq!(chunked, whole);
+            assert_eq!(chunks, 6usize.div_ceil(chunk_size as usize));
+        }
+        assert!(!whole.contains("private_batch"));
+    }
+
+    let (csv, _) = export_all(&service, request(ExportFormat::Csv, 1000), viewer);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert!(lines[0].starts_with("batch_id,batch_name,"));
+    assert_eq!(lines[1], "empty_batch,\"Empty, for now\",,rrkah-fqaaa-aaaaa-aaaaq-cai,1000,,0,0,0,0,,,,,,,");
+    assert_eq!(lines[5], "test_batch,Test Batch,,rrkah-fqaaa-aaaaa-aaaaq-cai,1000,,4,100000,120000,105000,test_animal,120000,Gram,1004,1004,,\"said \"\"moo\"\", loudly\"");
+
+    let (json, _) = export_all(&service, request(ExportFormat::Json, 1000), viewer);
+    assert!(json.starts_with("{\"batches\":[{\"id\":\"empty_batch\",\"name\":\"Empty, for now\",\"description\":null,"));
+    assert!(json.contains("\"stats\":{\"count\":4,\"min_weight\":100000,\"max_weight\":120000,\"average_weight\":105000,\"unit\":\"Gram\"}"));
+    assert!(json.ends_with("{\"item_id\":\"test_animal\",\"weight\":120000,\"created_at\":1004,\"updated_at\":1004,\"deleted_at\":null,\"notes\":\"said \\\"moo\\\", loudly\"}]}]}"));
+
+    let single = ExportRequest { batch_id: Some("private_batch".to_string()), ..request(ExportFormat::Json, 10) };
+    assert!(matches!(service.export(single.clone(), viewer), Err(WeightTrackerError::Unauthorized(_))));
+    assert!(service.export(single, owner).is_ok());
+    let result = service.export(request(ExportFormat::Csv, 0), viewer);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "chunk_size"));
+}
+
+#[test]
+fn test_export_resumes_after_exported_weights_are_purged() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let (batch_repo, weight_repo) = batch_with_weights(owner, None);
+    weight_repo.delete(WeightKey { owner, item_id: "test_animal".to_string(), created_at: 1001 }).unwrap();
+    let mut service = BatchService::new(batch_repo, weight_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+    let request = ExportRequest {
+        batch_id: Some("test_batch".to_string()),
+        format: ExportFormat::Json,
+        include_deleted: true,
+        unit: None,
+        chunk_size: Some(2),
+        cursor: None,
+    };
+
+    // The first chunk holds the batch and its deleted weight, which is then purged
+    let first = service.export(request.clone(), owner).unwrap();
+    assert_eq!(service.purge_deleted(500, owner).weights_purged, 1);
+    let rest = service.export(ExportRequest { cursor: first.next_cursor, ..request.clone() }, owner).unwrap();
+    assert_eq!(rest.next_cursor, None);
+
+    let json = String::from_utf8([first.data, rest.data].concat()).unwrap();
+    assert!(json.contains("\"created_at\":1001,\"updated_at\":1001,\"deleted_at\":1000,\"notes\":null},{\"item_id\""));
+    assert!(json.ends_with("\"created_at\":1003,\"updated_at\":1003,\"deleted_at\":null,\"notes\":null}]}]}"));
+}
+
+#[test]
+fn test_api_tokens_authenticate_their_owner_until_revoked() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let other = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let service = ApiTokenService::new(MockApiTokenRepo::new(), MockTimeProvider { time: 2000 });
+
+    let secret = service.create_token(owner, " Reporting ".to_string(), &[0xab; 32]).unwrap();
+    assert_eq!(secret, format!("wt_{}", "ab".repeat(32)));
+    assert_eq!(service.authenticate(&secret), Some(owner));
+    assert_eq!(service.authenticate("wt_abab"), None);
+
+    // Listings never reveal the secret
+    let tokens = service.list_tokens(owner);
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].label, "Reporting");
+    assert!(!tokens[0].id.contains(&secret[3..]));
+    assert!(service.list_tokens(other).is_empty());
+
+    let result = service.revoke_token(other, &tokens[0].id);
+    assert!(matches!(result, Err(WeightTrackerError::NotFound(_))));
+    service.revoke_token(owner, &tokens[0].id).unwrap();
+    assert_eq!(service.authenticate(&secret), None);
+
+    let result = service.create_token(owner, "  ".to_string(), &[1; 32]);
+    assert!(matches!(result, Err(WeightTrackerError::Validation { field, .. }) if field == "label"));
+    for n in 0..10u8 {
+        service.create_token(owner, format!("Token {}", n), &[n; 32]).unwrap();
+    }
+    let result = service.create_token(owner, "One too many".to_string(), &[42; 32]);
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+}
+
+#[test]
+fn test_client_request_id_makes_retries_safe() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let collaborator = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let (batch_repo, _) = batch_with_weights(owner, None);
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, collaborator, "test_batch".to_string(), Role::Recorder).unwrap();
+    let request = |item_id: &str, client_request_id: Option<&str>| CreateWeightRequest {
+        owner_override: None,
+        batch_id: "test_batch".to_string(),
+        item_id: item_id.to_string(),
+        weight: 500.0,
+        unit: None,
+        notes: None,
+        client_request_id: client_request_id.map(str::to_string),
+    };
+
+    // A weight recorded a while ago under "retry-0"
+    let weight_repo = MockWeightRepo::new();
+    let earlier = WeightKey { owner, item_id: "cow_0".to_string(), created_at: 1000 };
+    weight_repo.record_client_request(owner, "retry-0".to_string(), earlier.clone());
+
+    let audit_log = MockAuditLog::new();
+    let service = WeightService::new(weight_repo.clone(), batch_repo.clone(), access_control.clone(), MockTimeProvider { time: 2000 }, audit_log.clone());
+    let key = service.create_weight(request("cow_1", Some("retry-1")), owner).unwrap().key;
+    assert_eq!(key.created_at, 2000);
+
+    // Retries return the first key without recording anything, even for another item
+    assert_eq!(service.create_weight(request("cow_1", Some("retry-1")), owner).unwrap().key, key);
+    assert_eq!(service.create_weight(request("cow_9", Some("retry-1")), owner).unwrap().key, key);
+    assert_eq!(service.create_weight(request("cow_0", Some("retry-0")), owner).unwrap().key, earlier);
+    assert_eq!(service.get_weights(None, false, WeightUnit::Kilogram, owner).len(), 1);
+    assert_eq!(audit_log.actions(), vec![AuditAction::Create]);
+
+    // IDs belong to their caller
+    let other = service.create_weight(request("cow_2", Some("retry-1")), collaborator).unwrap().key;
+    assert_eq!(other.item_id, "cow_2");
+
+    let error = service.create_weight(request("cow_3", Some("")), owner).unwrap_err();
+    assert!(matches!(error, WeightTrackerError::Validation { field, .. } if field == "client_request_id"));
+
+    // Bulk creates replay used IDs and reject an ID repeated within the request
+    let response = service.create_weights(vec![
+        request("cow_1", Some("retry-1")),
+        request("cow_4", Some("retry-4")),
+        request("cow_5", Some("retry-4")),
+    ], false, owner).unwrap();
+    assert_eq!(response.created, 1);
+    assert_eq!(response.results[0], CreateWeightOutcome::Replayed(key));
+    assert!(matches!(response.results[1], CreateWeightOutcome::Created(_)));
+    assert!(matches!(response.results[2], CreateWeightOutcome::Failed(WeightTrackerError::Conflict(_))));
+
+    // Once the window has passed the ID records a new weight
+    let later = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: 1000 + 24 * 60 * 60 * 1_000_000_000 }, MockAuditLog::new());
+    let renewed = later.create_weight(request("cow_0", Some("retry-0")), owner).unwrap().key;
+    assert_ne!(renewed, earlier);
+}
+
+#[test]
+fn test_bulk_rows_are_compared_with_earlier_rows() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let mut batch_repo = MockBatchRepo::new();
+    batch_repo.create(Batch {
+        id: "new_batch".to_string(),
+        name: "New Batch".to_string(),
+        owner,
+        description: None,
+        created_at: 1000,
+        updated_at: 1000,
+        deleted_at: None,
+        unit: WeightUnit::Kilogram,
+        validation: ValidationRules::default(),
+    }).unwrap();
+    let service = WeightService::new(MockWeightRepo::new(), batch_repo, MockAccessControl::new(), MockTimeProvider { time: 2000 }, MockAuditLog::new());
+
+    // The batch is empty, so only the rows above the typo are there to judge it
+    let report = service.import_weights_csv(CsvImportRequest {
+        batch_id: "new_batch".to_string(),
+        csv: b"item,kg\ncow_1,450\ncow_2,455\ncow_3,460\ncow_4,448\ncow_5,452\ncow_6,4500\n".to_vec(),
+        columns: CsvColumns { item_id: "item".to_string(), weight: "kg".to_string(), timestamp: None, notes: None },
+        unit: None,
+        all_or_nothing: false,
+    }, owner).unwrap();
+    assert_eq!(report.created, 6);
+    let flagged = service.get_flagged_weights("new_batch", false, WeightUnit::Kilogram, owner).unwrap();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].weight.item_id, "cow_6");
+}
+
+#[test]
+fn test_outliers_are_flagged_until_confirmed_or_corrected() {
+    let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+    let viewer = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let stranger = Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap();
+    let (batch_repo, _) = batch_with_weights(owner, None);
+    let weight_repo = MockWeightRepo::new();
+    for (n, weight) in [445.0, 448.0, 450.0, 452.0, 455.0, 458.0].into_iter().enumerate() {
+        let item_id = format!("cow_{}", n + 1);
+        let created_at = 1001 + n as u64;
+        weight_repo.create(WeightKey { owner, item_id: item_id.clone(), created_at }, Weight {
+            owner,
+            batch_id: "test_batch".to_string(),
+            item_id,
+            weight,
+            created_at,
+            updated_at: created_at,
+            deleted_at: None,
+            deleted_with_batch: false,
+            entered_unit: WeightUnit::Kilogram,
+            notes: None,
+        }).unwrap();
+    }
+    let mut access_control = MockAccessControl::new();
+    access_control.share_access(owner, viewer, "test_batch".to_string(), Role::Viewer).unwrap();
+    let audit_log = MockAuditLog::new();
+    let service = WeightService::new(weight_repo, batch_repo, access_control, MockTimeProvider { time: 2000 }, audit_log.clone());
+    let request = |item_id: &str, weight: f64, client_request_id: Option<&str>| CreateWeightRequest {
+        owner_override: None,
+        batch_id: "test_batch".to_string(),
+        item_id: item_id.to_string(),
+        weight,
+        unit: None,
+        notes: None,
+        client_request_id: client_request_id.map(str::to_string),
+    };
+
+    // A typed extra zero is still recorded, but with warnings
+    let typo = service.create_weight(request("cow_7", 4500.0, Some("typo")), owner).unwrap();
+    let kinds: Vec<_> = typo.warnings.iter().map(|w| w.kind).collect();
+    assert_eq!(kinds, vec![OutlierKind::OutsideBatchRange, OutlierKind::FarFromBatchAverage]);
+    let replayed = service.create_weight(request("cow_7", 4500.0, Some("typo")), owner).unwrap();
+    assert_eq!(replayed.warnings, typo.warnings);
+    assert!(service.create_weight(request("cow_1", 460.0, None), owner).unwrap().warnings.is_empty());
+
+    let flagged = service.get_flagged_weights("test_batch", false, WeightUnit::Tonne, viewer).unwrap();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].weight.item_id, "cow_7");
+    assert!((flagged[0].weight.weight - 4.5).abs() < 1e-9);
+    let result = service.get_flagged_weights("test_batch", false, WeightUnit::Kilogram, stranger);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+
+    // Only the owner can vouch for the weight, and only once
+    let key = typo.key;
+    let result = service.confirm_weight(key.item_id.clone(), key.created_at, viewer);
+    assert!(matches!(result, Err(WeightTrackerError::Unauthorized(_))));
+    service.confirm_weight(key.item_id.clone(), key.created_at, owner).unwrap();
+    let result = service.confirm_weight(key.item_id.clone(), key.created_at, owner);
+    assert!(matches!(result, Err(WeightTrackerError::Conflict(_))));
+    let result = service.confirm_weight("cow_1".to_string(), 2000, owner);
+    assert!(matches!(result, Err(WeightTrackerError::NotFound(_))));
+    assert_eq!(audit_log.actions(), vec![AuditAction::Create, AuditAction::Create, AuditAction::Confirm]);
+
+    assert!(service.get_flagged_weights("test_batch", false, WeightUnit::Kilogram, owner).unwrap().is_empty());
+    let confirmed = service.get_flagged_weights("test_batch", true, WeightUnit::Kilogram, owner).unwrap();
+    assert_eq!(confirmed[0].flag.confirmed_by, Some(owner));
+
+    // Correcting the weight clears its flag
+    service.update_weight(key.item_id, key.created_at, 450.0, WeightUnit::Kilogram, None, owner).unwrap();
+    assert!(service.get_flagged_weights("test_batch", true, WeightUnit::Kilogram, owner).unwrap().is_empty());
+}
@@ -1,26 +1,76 @@
+use crate::errors::WeightTrackerError;
 use crate::models::*;
 use candid::Principal;
 
 pub trait WeightRepository {
-    fn create(&self, key: WeightKey, weight: Weight) -> Result<(), String>;
+    fn create(&self, key: WeightKey, weight: Weight) -> Result<(), WeightTrackerError>;
     fn get_all(&self, batch_id: Option<String>, include_deleted: bool) -> Vec<Weight>;
-    fn update(&self, key: WeightKey, new_weight: f64) -> Result<(), String>;
-    fn delete(&self, key: WeightKey) -> Result<(), String>;
+    /// Sets a new value, keeping the one it replaces in the weight's history.
+    fn update(&self, key: WeightKey, new_weight: f64, changed_by: Principal, reason: Option<String>) -> Result<(), WeightTrackerError>;
+    /// Values the weight held before its corrections, oldest first.
+    fn get_history(&self, key: &WeightKey) -> Vec<WeightRevision>;
+    fn delete(&self, key: WeightKey) -> Result<(), WeightTrackerError>;
+    fn delete_with_batch(&self, key: WeightKey) -> Result<(), WeightTrackerError>;
+    fn restore(&self, key: WeightKey) -> Result<(), WeightTrackerError>;
+    fn purge_deleted_before(&self, cutoff: u64) -> Vec<Weight>;
+    fn find_at(&self, item_id: &str, created_at: u64) -> Vec<Weight>;
+    fn get_created_between(&self, from: u64, to: u64, include_deleted: bool) -> Vec<Weight>;
+    /// Weights of an item across owners and batches, oldest first.
+    fn get_for_item(&self, item_id: &str, include_deleted: bool) -> Vec<Weight>;
+    /// Replaces a weight's outlier warnings, stamped with the current time.
+    /// No warnings clears its flag.
+    fn set_flag(&self, key: WeightKey, warnings: Vec<OutlierWarning>);
+    fn get_flag(&self, key: &WeightKey) -> Option<WeightFlag>;
+    fn confirm_flag(&self, key: &WeightKey, confirmed_by: Principal) -> Result<(), WeightTrackerError>;
+    fn find_client_request(&self, caller: Principal, client_request_id: &str) -> Option<ClientRequest>;
+    /// Remembers the weight a client request ID recorded, stamped with the current time.
+    fn record_client_request(&self, caller: Principal, client_request_id: String, key: WeightKey);
+    /// Forgets client request IDs recorded before `cutoff`. Returns how many.
+    fn forget_client_requests_before(&self, cutoff: u64) -> u64;
 }
 
 pub trait BatchRepository {
-    fn create(&mut self, batch: Batch) -> Result<String, String>;
+    fn next_sequence(&mut self) -> u64;
+    fn create(&mut self, batch: Batch) -> Result<String, WeightTrackerError>;
     fn get(&self, id: &str) -> Option<Batch>;
     fn get_all(&self, include_deleted: bool) -> Vec<Batch>;
-    fn update(&mut self, id: &str, name: String, description: Option<String>) -> Result<(), String>;
-    fn delete(&mut self, id: &str) -> Result<(), String>;
+    fn update(&mut self, id: &str, name: String, description: Option<String>, unit: WeightUnit, validation: ValidationRules) -> Result<(), WeightTrackerError>;
+    fn delete(&mut self, id: &str) -> Result<(), WeightTrackerError>;
+    fn restore(&mut self, id: &str) -> Result<(), WeightTrackerError>;
+    fn purge_deleted_before(&mut self, cutoff: u64) -> Vec<Batch>;
 }
 
 pub trait AccessControl {
     fn has_access(&self, data_owner: Principal, accessor: Principal, batch_id: Option<&str>) -> bool;
-    fn share_access(&mut self, owner: Principal, collaborator: Principal, batch_id: String) -> Result<(), String>;
-    fn remove_access(&mut self, owner: Principal, collaborator: Principal) -> Result<(), String>;
+    fn get_role(&self, data_owner: Principal, accessor: Principal, batch_id: &str) -> Option<Role>;
+    fn share_access(&mut self, owner: Principal, collaborator: Principal, batch_id: String, role: Role) -> Result<(), WeightTrackerError>;
+    fn remove_access(&mut self, owner: Principal, collaborator: Principal) -> Result<(), WeightTrackerError>;
+    fn remove_batch_access(&mut self, owner: Principal, collaborator: Principal, batch_id: &str) -> Result<(), WeightTrackerError>;
     fn get_collaborators(&self, owner: Principal) -> Vec<Principal>;
+    fn get_batch_collaborators(&self, owner: Principal, batch_id: &str) -> Vec<BatchCollaborator>;
+
+    fn has_permission(&self, data_owner: Principal, accessor: Principal, batch_id: &str, permission: Permission) -> bool {
+        data_owner == accessor
+            || self.get_role(data_owner, accessor, batch_id).is_some_and(|role| role.allows(permission))
+    }
+}
+
+/// Append-only record of every mutation. Entries are never changed or removed.
+pub trait AuditLog {
+    /// Stores `event` under the next entry ID, stamped with the current time.
+    fn record(&self, event: AuditEvent) -> u64;
+    /// Entries about a batch with an ID greater than `after`, oldest first.
+    fn get_for_batch(&self, batch_id: &str, after: Option<u64>, limit: usize) -> Vec<AuditEntry>;
+    /// Entries about weights of an item with an ID greater than `after`, oldest first.
+    fn get_for_item(&self, item_id: &str, after: Option<u64>, limit: usize) -> Vec<AuditEntry>;
+}
+
+/// API tokens keyed by the hash of their secret.
+pub trait ApiTokenRepository {
+    fn create(&self, hash: String, token: ApiToken);
+    fn get(&self, hash: &str) -> Option<ApiToken>;
+    fn get_for_owner(&self, owner: Principal) -> Vec<(String, ApiToken)>;
+    fn delete(&self, hash: &str) -> Option<ApiToken>;
 }
 
 pub trait TimeProvider: Clone {
@@ -34,4 +84,4 @@ impl TimeProvider for CanisterTimeProvider {
     fn get_time(&self) -> u64 {
         ic_cdk::api::time()
     }
-} 
\ No newline at end of file
+}
@@ -2,6 +2,7 @@ import { useEffect } from 'react';
 import weightService from '../services/WeightService';
 import batchService from '../services/BatchService';
 import collaboratorService from '../services/CollaboratorService';
+import apiTokenService from '../services/ApiTokenService';
 
 export function useActor(actor) {
   useEffect(() => {
@@ -10,6 +11,7 @@ export function useActor(actor) {
       weightService.setActor(actor);
       batchService.setActor(actor);
       collaboratorService.setActor(actor);
+      apiTokenService.setActor(actor);
     }
   }, [actor]);
 } 
\ No newline at end of file
@@ -2,6 +2,9 @@ import { useState, useEffect } from 'react';
 import weightService from '../services/WeightService';
 import notificationService from '../services/NotificationService';
 
+// Weights are shown, and corrected, in this unit.
+const DISPLAY_UNIT = 'Kilogram';
+
 export function useWeights(backendActor, selectedBatch) {
   const [weights, setWeights] = useState([]);
   const [includeDeleted, setIncludeDeleted] = useState(false);
@@ -18,7 +21,8 @@ export function useWeights(backendActor, selectedBatch) {
     try {
       const fetchedWeights = await weightService.fetchWeights(
         selectedBatch?.id, 
-        includeDeleted
+        includeDeleted,
+        DISPLAY_UNIT
       );
       setWeights(fetchedWeights);
     } catch (error) {
@@ -28,8 +32,12 @@ export function useWeights(backendActor, selectedBatch) {
 
   async function createWeight(request) {
     try {
-      await weightService.createWeight(request);
-      notificationService.success('Weight added successfully');
+      const { warnings } = await weightService.createWeight(request);
+      if (warnings.length > 0) {
+        notificationService.info('Weight added, but it looks unusual for this batch. Please check it.');
+      } else {
+        notificationService.success('Weight added successfully');
+      }
       await fetchWeights();
       return true;
     } catch (error) {
@@ -40,7 +48,7 @@ export function useWeights(backendActor, selectedBatch) {
 
   async function deleteWeight(item_id, created_at) {
     try {
-      const response = await backendActor.delete_weight(item_id, created_at);
+      await weightService.deleteWeight(item_id, created_at);
       await fetchWeights();
       notificationService.success('Weight deleted successfully');
     } catch (error) {
@@ -50,7 +58,7 @@ export function useWeights(backendActor, selectedBatch) {
 
   async function updateWeight(item_id, created_at, weight) {
     try {
-      const response = await backendActor.update_weight(item_id, created_at, weight);
+      await weightService.updateWeight(item_id, created_at, weight, { unit: DISPLAY_UNIT });
       await fetchWeights();
       setEditingWeight(null);
       notificationService.success('Weight updated successfully');
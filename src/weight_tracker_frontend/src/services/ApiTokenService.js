@@ -0,0 +1,49 @@
+import { unwrapResult } from './resultUtils';
+
+// API tokens let reporting tools read batches over plain HTTP, e.g.
+// GET https://<canister id>.raw.icp0.io/batches/<batch id>/weights.csv
+// with the header `Authorization: Bearer <token>`.
+class ApiTokenService {
+  constructor() {
+    this.actor = null;
+  }
+
+  setActor(actor) {
+    this.actor = actor;
+  }
+
+  // Returns the token's secret, which cannot be retrieved again later.
+  async createToken(label) {
+    if (!this.actor) throw new Error('Actor not initialized');
+    try {
+      return unwrapResult(await this.actor.create_api_token(label));
+    } catch (error) {
+      console.error('Error creating API token:', error);
+      throw error;
+    }
+  }
+
+  async fetchTokens() {
+    if (!this.actor) throw new Error('Actor not initialized');
+    try {
+      return unwrapResult(await this.actor.list_api_tokens());
+    } catch (error) {
+      console.error('Error fetching API tokens:', error);
+      throw error;
+    }
+  }
+
+  async revokeToken(id) {
+    if (!this.actor) throw new Error('Actor not initialized');
+    try {
+      unwrapResult(await this.actor.revoke_api_token(id));
+      return true;
+    } catch (error) {
+      console.error('Error revoking API token:', error);
+      throw error;
+    }
+  }
+}
+
+const apiTokenService = new ApiTokenService();
+export default apiTokenService;
@@ -1,3 +1,16 @@
+import { unwrapResult } from './resultUtils';
+
+const optional = (value) => (value === null || value === undefined || value === '' ? [] : [value]);
+
+// validation is { minWeight, maxWeight, itemIdPattern, allowDuplicateItemIds }
+// with weights in the batch's unit; any of them can be left out.
+const validationArg = (validation) => (validation ? [{
+  min_weight: optional(validation.minWeight),
+  max_weight: optional(validation.maxWeight),
+  item_id_pattern: optional(validation.itemIdPattern),
+  allow_duplicate_item_ids: validation.allowDuplicateItemIds ?? true,
+}] : []);
+
 class BatchService {
   constructor(backendActor) {
     this.backendActor = backendActor;
@@ -7,10 +20,16 @@ class BatchService {
     this.backendActor = actor;
   }
 
-  async fetchBatches(includeDeleted = false) {
+  // Stats come back in kilograms unless unit names another, with the 10th,
+  // 25th, 75th and 90th percentiles unless percentiles lists others (0-100).
+  async fetchBatches(includeDeleted = false, unit = null, percentiles = null) {
     try {
       console.log('Fetching batches...');
-      const fetchedBatches = await this.backendActor.get_batches(includeDeleted);
+      const fetchedBatches = unwrapResult(await this.backendActor.get_batches(
+        includeDeleted,
+        unit ? [{ [unit]: null }] : [],
+        optional(percentiles)
+      ));
       console.log('Fetched batches:', fetchedBatches);
       return fetchedBatches;
     } catch (error) {
@@ -19,54 +38,138 @@ class BatchService {
     }
   }
 
-  async createBatch(name, description = '') {
+  async createBatch(name, description = '', unit = null, validation = null) {
     try {
       const result = await this.backendActor.create_batch({
         name,
         description: description ? [description] : [],
+        unit: unit ? [{ [unit]: null }] : [],
+        validation: validationArg(validation),
       });
-      if ('Ok' in result) {
-        return result.Ok;
-      }
-      throw new Error(result.Err);
+      return unwrapResult(result);
     } catch (error) {
       console.error('Error creating batch:', error);
       throw error;
     }
   }
 
-  async updateBatch(batchId, name, description = '') {
+  // Leaving validation out keeps the batch's current rules.
+  async updateBatch(batchId, name, description = '', unit = null, validation = null) {
     try {
       const result = await this.backendActor.update_batch(batchId, {
         name,
         description: description ? [description] : [],
+        unit: unit ? [{ [unit]: null }] : [],
+        validation: validationArg(validation),
       });
-      if ('Ok' in result) {
-        return result.Ok;
-      }
-      throw new Error(result.Err);
+      return unwrapResult(result);
     } catch (error) {
       console.error('Error updating batch:', error);
       throw error;
     }
   }
 
+  // Fetches every chunk of a 'Csv' or 'Json' export and returns the whole
+  // document as text. Leave batchId out to export every readable batch.
+  async exportBatches(format, { batchId = null, includeDeleted = false, unit = null } = {}) {
+    try {
+      const decoder = new TextDecoder();
+      let document = '';
+      let cursor = [];
+      do {
+        const chunk = unwrapResult(await this.backendActor.export({
+          batch_id: batchId ? [batchId] : [],
+          format: { [format]: null },
+          include_deleted: includeDeleted,
+          unit: unit ? [{ [unit]: null }] : [],
+          chunk_size: [],
+          cursor,
+        }));
+        document += decoder.decode(new Uint8Array(chunk.data), { stream: true });
+        cursor = chunk.next_cursor;
+      } while (cursor.length > 0);
+      return document + decoder.decode();
+    } catch (error) {
+      console.error('Error exporting batches:', error);
+      throw error;
+    }
+  }
+
+  // Per-interval stats for charts. interval is 'Day', 'Week' or 'Month';
+  // leave batchId out to cover every visible batch. utcOffsetMinutes defaults
+  // to the browser's time zone so days start at local midnight.
+  async getWeightSeries(interval, {
+    batchId = null,
+    utcOffsetMinutes = -new Date().getTimezoneOffset(),
+    from = null,
+    to = null,
+    unit = null,
+  } = {}) {
+    try {
+      return unwrapResult(await this.backendActor.get_weight_series({
+        batch_id: batchId ? [batchId] : [],
+        interval: { [interval]: null },
+        utc_offset_minutes: [utcOffsetMinutes],
+        from: from !== null ? [from] : [],
+        to: to !== null ? [to] : [],
+        unit: unit ? [{ [unit]: null }] : [],
+      }));
+    } catch (error) {
+      console.error('Error fetching weight series:', error);
+      throw error;
+    }
+  }
+
+  // buckets is { Width: 50 }, { Count: 10 } or
+  // { Bands: [{ name: 'A', min_weight: 450, max_weight: 500 }] }, with the
+  // bounds in unit (kilograms unless unit names another).
+  async getWeightHistogram(batchId, buckets, unit = null) {
+    try {
+      return unwrapResult(await this.backendActor.get_weight_histogram(batchId, {
+        buckets,
+        unit: unit ? [{ [unit]: null }] : [],
+      }));
+    } catch (error) {
+      console.error('Error fetching weight histogram:', error);
+      throw error;
+    }
+  }
+
   async deleteBatch(batchId) {
     try {
-      const result = await this.backendActor.delete_batch(batchId);
-      if ('Ok' in result) {
-        return true;
-      }
-      throw new Error(result.Err);
+      unwrapResult(await this.backendActor.delete_batch(batchId));
+      return true;
     } catch (error) {
       console.error('Error deleting batch:', error);
       throw error;
     }
   }
 
+  // Resolves to the number of weights restored along with the batch.
+  async restoreBatch(batchId) {
+    try {
+      return unwrapResult(await this.backendActor.restore_batch(batchId));
+    } catch (error) {
+      console.error('Error restoring batch:', error);
+      throw error;
+    }
+  }
+
+  async fetchBatchAuditLog(batchId, cursor = null, pageSize = null) {
+    try {
+      return unwrapResult(await this.backendActor.get_batch_audit_log(batchId, {
+        cursor: cursor ? [cursor] : [],
+        page_size: pageSize ? [pageSize] : [],
+      }));
+    } catch (error) {
+      console.error('Error fetching batch audit log:', error);
+      throw error;
+    }
+  }
+
   async fetchShareableBatches() {
     try {
-      const fetchedBatches = await this.backendActor.get_shareable_batches();
+      const fetchedBatches = unwrapResult(await this.backendActor.get_shareable_batches());
       console.log('Fetched shareable batches:', fetchedBatches);
       return fetchedBatches;
     } catch (error) {
@@ -77,7 +180,7 @@ class BatchService {
 
   async fetchOwnedBatches() {
     try {
-      const fetchedBatches = await this.backendActor.get_owned_batches();
+      const fetchedBatches = unwrapResult(await this.backendActor.get_owned_batches([], []));
       console.log('Fetched owned batches:', fetchedBatches);
       return fetchedBatches;
     } catch (error) {
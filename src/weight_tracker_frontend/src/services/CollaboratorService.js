@@ -1,4 +1,5 @@
 import { Principal } from '@dfinity/principal';
+import { unwrapResult } from './resultUtils';
 
 class CollaboratorService {
   constructor() {
@@ -13,7 +14,7 @@ class CollaboratorService {
     if (!this.actor) throw new Error('Actor not initialized');
     try {
       console.log('Fetching owned batches...');
-      const batches = await this.actor.get_owned_batches();
+      const batches = unwrapResult(await this.actor.get_owned_batches([], []));
       console.log('Raw owned batches:', batches);
       
       // Filter out deleted batches and sort by name
@@ -36,7 +37,7 @@ class CollaboratorService {
     if (!this.actor) throw new Error('Actor not initialized');
     try {
       console.log('Fetching shareable batches...');
-      const batches = await this.actor.get_shareable_batches();
+      const batches = unwrapResult(await this.actor.get_shareable_batches());
       console.log('Raw shareable batches:', batches);
       
       // No need to filter, just sort
@@ -57,7 +58,7 @@ class CollaboratorService {
     if (!this.actor) throw new Error('Actor not initialized');
     try {
       console.log('Fetching collaborators...');
-      const fetchedCollaborators = await this.actor.get_collaborators();
+      const fetchedCollaborators = unwrapResult(await this.actor.get_collaborators());
       console.log('Fetched collaborators:', fetchedCollaborators);
       return fetchedCollaborators;
     } catch (error) {
@@ -66,12 +67,15 @@ class CollaboratorService {
     }
   }
 
-  async shareWithUser(collaboratorId, batchId) {
+  // role is one of 'Viewer', 'Recorder', 'Editor' or 'Manager'; the backend
+  // defaults to 'Editor' when it is omitted.
+  async shareWithUser(collaboratorId, batchId, role) {
     if (!this.actor) throw new Error('Actor not initialized');
     try {
-      console.log('Sharing batch:', { collaboratorId, batchId });
+      console.log('Sharing batch:', { collaboratorId, batchId, role });
       const collaboratorPrincipal = Principal.fromText(collaboratorId);
-      const result = await this.actor.share_with_user(collaboratorPrincipal, batchId);
+      const roleArg = role ? [{ [role]: null }] : [];
+      const result = unwrapResult(await this.actor.share_with_user(collaboratorPrincipal, batchId, roleArg));
       console.log('Share result:', result);
       return result;
     } catch (error) {
@@ -85,7 +89,7 @@ class CollaboratorService {
     try {
       console.log('Removing collaborator:', principalId);
       const collaboratorPrincipal = Principal.fromText(principalId);
-      await this.actor.remove_sharing({ collaborator: collaboratorPrincipal });
+      unwrapResult(await this.actor.remove_sharing({ collaborator: collaboratorPrincipal }));
       console.log('Collaborator removed successfully');
       return true;
     } catch (error) {
@@ -93,6 +97,29 @@ class CollaboratorService {
       throw error;
     }
   }
+
+  async unshareBatch(principalId, batchId) {
+    if (!this.actor) throw new Error('Actor not initialized');
+    try {
+      console.log('Unsharing batch:', { principalId, batchId });
+      const collaboratorPrincipal = Principal.fromText(principalId);
+      unwrapResult(await this.actor.unshare_batch(collaboratorPrincipal, batchId));
+      return true;
+    } catch (error) {
+      console.error('Error unsharing batch:', error);
+      throw error;
+    }
+  }
+
+  async fetchBatchCollaborators(batchId) {
+    if (!this.actor) throw new Error('Actor not initialized');
+    try {
+      return unwrapResult(await this.actor.list_batch_collaborators(batchId));
+    } catch (error) {
+      console.error('Error fetching batch collaborators:', error);
+      throw error;
+    }
+  }
 }
 
 const collaboratorService = new CollaboratorService();
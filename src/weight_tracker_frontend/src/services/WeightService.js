@@ -1,3 +1,6 @@
+import { Principal } from '@dfinity/principal';
+import { unwrapResult } from './resultUtils';
+
 class WeightService {
   constructor() {
     this.actor = null;
@@ -7,15 +10,18 @@ class WeightService {
     this.actor = actor;
   }
 
-  async fetchWeights(selectedBatchId = null, includeDeleted = false) {
+  // unit is one of 'Kilogram', 'Pound', 'Gram' or 'Tonne'; weights come back
+  // in kilograms when it is omitted.
+  async fetchWeights(selectedBatchId = null, includeDeleted = false, unit = null) {
     if (!this.actor) throw new Error('Actor not initialized');
 
     try {
       console.log("Fetching weights with batch filter:", selectedBatchId);
-      const fetchedWeights = await this.actor.get_all_weights(
+      const fetchedWeights = unwrapResult(await this.actor.get_all_weights(
         selectedBatchId ? [selectedBatchId] : [], 
-        includeDeleted
-      );
+        includeDeleted,
+        unit ? [{ [unit]: null }] : []
+      ));
       console.log("Fetched weights:", {
         batchId: selectedBatchId,
         totalWeights: fetchedWeights.length,
@@ -28,33 +34,87 @@ class WeightService {
     }
   }
 
-  async createWeight(request) {
-    if (!this.actor) throw new Error('Actor not initialized');
-    
-    // Add owner_override field with null value
-    const weightRequest = {
+  toWeightRequest(request) {
+    // Add owner_override field with null value; the weight is in the
+    // batch's unit unless request.unit names another. Reuse the same
+    // client_request_id when retrying so the weight is recorded only once.
+    return {
       ...request,
-      owner_override: [] // Empty array for Option<Principal> in Rust
+      owner_override: [], // Empty array for Option<Principal> in Rust
+      unit: request.unit ? [{ [request.unit]: null }] : [],
+      notes: request.notes ? [request.notes] : [],
+      client_request_id: request.client_request_id ? [request.client_request_id] : []
     };
+  }
+
+  // Returns { key, warnings }; warnings are non-empty when the weight looks
+  // like a data-entry error. It is recorded either way.
+  async createWeight(request) {
+    if (!this.actor) throw new Error('Actor not initialized');
 
     try {
-      await this.actor.create_weight(weightRequest);
-      return true;
+      return unwrapResult(await this.actor.create_weight(this.toWeightRequest(request)));
     } catch (error) {
       console.error('Error creating weight:', error);
       throw error;
     }
   }
 
-  async updateWeight(animalId, createdAt, newWeight) {
+  // Returns { results, created } where each result is { Created: key },
+  // { Replayed: key }, { Failed: error } or { Skipped: null }, in the order
+  // of requests.
+  async createWeights(requests, allOrNothing = false) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      return unwrapResult(await this.actor.create_weights(
+        requests.map((request) => this.toWeightRequest(request)),
+        allOrNothing
+      ));
+    } catch (error) {
+      console.error('Error creating weights:', error);
+      throw error;
+    }
+  }
+
+  // csv is the file's text or bytes; columns maps itemId, weight and the
+  // optional timestamp and notes to header names. Returns { rows, created }
+  // with each row's line and outcome.
+  async importWeightsCsv(batchId, csv, columns, { unit = null, allOrNothing = false } = {}) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      return unwrapResult(await this.actor.import_weights_csv({
+        batch_id: batchId,
+        csv: typeof csv === 'string' ? new TextEncoder().encode(csv) : csv,
+        columns: {
+          item_id: columns.itemId,
+          weight: columns.weight,
+          timestamp: columns.timestamp ? [columns.timestamp] : [],
+          notes: columns.notes ? [columns.notes] : [],
+        },
+        unit: unit ? [{ [unit]: null }] : [],
+        all_or_nothing: allOrNothing,
+      }));
+    } catch (error) {
+      console.error('Error importing weights:', error);
+      throw error;
+    }
+  }
+
+  // newWeight is in unit, kilograms when it is omitted, so pass the unit
+  // the weight was shown in.
+  async updateWeight(animalId, createdAt, newWeight, { unit = null, reason = '' } = {}) {
     if (!this.actor) throw new Error('Actor not initialized');
 
     try {
-      await this.actor.update_weight(
+      unwrapResult(await this.actor.update_weight(
         animalId,
         createdAt,
-        parseFloat(newWeight)
-      );
+        parseFloat(newWeight),
+        unit ? [{ [unit]: null }] : [],
+        reason ? [reason] : []
+      ));
       return true;
     } catch (error) {
       console.error('Error updating weight:', error);
@@ -62,17 +122,106 @@ class WeightService {
     }
   }
 
+  async fetchWeightHistory(animalId, createdAt, unit = null) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      return unwrapResult(await this.actor.get_weight_history(
+        animalId,
+        createdAt,
+        unit ? [{ [unit]: null }] : []
+      ));
+    } catch (error) {
+      console.error('Error fetching weight history:', error);
+      throw error;
+    }
+  }
+
+  // Readings of an animal across batches, oldest first, with deltas and
+  // average daily gain. from and to are nanosecond timestamps (BigInt);
+  // owner is the principal text of whose animal it is, the caller by default.
+  async fetchItemTimeline(animalId, { owner = null, from = null, to = null, unit = null } = {}) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      return unwrapResult(await this.actor.get_item_timeline(animalId, [{
+        owner: owner ? [Principal.fromText(owner)] : [],
+        from: from !== null ? [from] : [],
+        to: to !== null ? [to] : [],
+        unit: unit ? [{ [unit]: null }] : [],
+      }]));
+    } catch (error) {
+      console.error('Error fetching item timeline:', error);
+      throw error;
+    }
+  }
+
   async deleteWeight(animalId, createdAt) {
     if (!this.actor) throw new Error('Actor not initialized');
 
     try {
-      await this.actor.delete_weight(animalId, createdAt);
+      unwrapResult(await this.actor.delete_weight(animalId, createdAt));
       return true;
     } catch (error) {
       console.error('Error deleting weight:', error);
       throw error;
     }
   }
+
+  async fetchItemAuditLog(animalId, cursor = null, pageSize = null) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      return unwrapResult(await this.actor.get_item_audit_log(animalId, {
+        cursor: cursor ? [cursor] : [],
+        page_size: pageSize ? [pageSize] : [],
+      }));
+    } catch (error) {
+      console.error('Error fetching item audit log:', error);
+      throw error;
+    }
+  }
+
+  // Each entry is { weight, flag }; confirmed weights are left out unless
+  // includeConfirmed is set.
+  async fetchFlaggedWeights(batchId, includeConfirmed = false, unit = null) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      return unwrapResult(await this.actor.get_flagged_weights(
+        batchId,
+        includeConfirmed,
+        unit ? [{ [unit]: null }] : []
+      ));
+    } catch (error) {
+      console.error('Error fetching flagged weights:', error);
+      throw error;
+    }
+  }
+
+  async confirmWeight(animalId, createdAt) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      unwrapResult(await this.actor.confirm_weight(animalId, createdAt));
+      return true;
+    } catch (error) {
+      console.error('Error confirming weight:', error);
+      throw error;
+    }
+  }
+
+  async restoreWeight(animalId, createdAt) {
+    if (!this.actor) throw new Error('Actor not initialized');
+
+    try {
+      unwrapResult(await this.actor.restore_weight(animalId, createdAt));
+      return true;
+    } catch (error) {
+      console.error('Error restoring weight:', error);
+      throw error;
+    }
+  }
 }
 
 const weightService = new WeightService();
@@ -0,0 +1,19 @@
+// Helpers for the backend's `variant { Ok; Err: WeightTrackerError }` results.
+
+export function errorMessage(error) {
+  if ('Unauthenticated' in error) {
+    return 'Please login with Internet Identity';
+  }
+  if ('Validation' in error) {
+    return `Invalid ${error.Validation.field}: ${error.Validation.reason}`;
+  }
+  const [kind, message] = Object.entries(error)[0];
+  return message || kind;
+}
+
+export function unwrapResult(result) {
+  if ('Ok' in result) {
+    return result.Ok;
+  }
+  throw new Error(errorMessage(result.Err));
+}